@@ -0,0 +1,24 @@
+//checked vs unchecked parsing on a big payload
+//the difference is the SHA-256 pass over the whole packet
+//run with: cargo bench --bench parse
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use fdp::packet::*;
+
+fn bench_from_bytes(c: &mut Criterion) {
+    let payload = vec![0xAB; 1024 * 1024]; // 1MB
+    let bytes = Packet::new(SessionId::new(), Intent::DataPush, payload).to_bytes();
+
+    let mut group = c.benchmark_group("from_bytes_1mb");
+    group.bench_function("checked", |b| {
+        b.iter(|| Packet::from_bytes(black_box(&bytes)).unwrap())
+    });
+    group.bench_function("unchecked", |b| {
+        b.iter(|| Packet::from_bytes_unchecked(black_box(&bytes)).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_from_bytes);
+criterion_main!(benches);
@@ -0,0 +1,23 @@
+//hash-verifying a batch of packets, one by one vs verify_batch
+//verify_batch only differs from the loop with the rayon pool behind it:
+//run with: cargo bench --bench verify --features parallel
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use fdp::packet::*;
+
+fn bench_verify_batch(c: &mut Criterion) {
+    let packets: Vec<Packet> = (0..1000)
+        .map(|i| Packet::new(SessionId::new(), Intent::DataPush, vec![i as u8; 16 * 1024]))
+        .collect();
+
+    let mut group = c.benchmark_group("verify_1000x16kb");
+    group.bench_function("serial", |b| {
+        b.iter(|| black_box(&packets).iter().map(Packet::verify).collect::<Vec<bool>>())
+    });
+    group.bench_function("verify_batch", |b| b.iter(|| verify_batch(black_box(&packets))));
+    group.finish();
+}
+
+criterion_group!(benches, bench_verify_batch);
+criterion_main!(benches);
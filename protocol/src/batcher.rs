@@ -0,0 +1,162 @@
+//one Ack for many packets instead of one each
+//received sequences pile up per session and go out as a single range-encoded Ack, either
+//once a session has `threshold` of them waiting or when the flush timer fires, whichever
+//comes first
+//
+//record/flush are the plain bookkeeping, run() wires them to channels and a timer
+
+use std::collections::{BTreeSet, HashMap};
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+
+use crate::packet::*;
+
+#[derive(Debug)]
+pub struct AckBatcher {
+    pending: HashMap<SessionId, BTreeSet<Sequence>>,
+    threshold: usize, // acks waiting in one session before it's flushed right away
+}
+
+impl AckBatcher {
+    pub fn new(threshold: usize) -> Self {
+        AckBatcher {
+            pending: HashMap::new(),
+            threshold: threshold.max(1),
+        }
+    }
+
+    /// Note that `sequence` arrived, returns the batched Ack if this filled the session's batch
+    pub fn record(&mut self, session_id: SessionId, sequence: Sequence) -> Option<Packet> {
+        let pending = self.pending.entry(session_id).or_default();
+        pending.insert(sequence);
+        if pending.len() >= self.threshold {
+            return self.flush(session_id);
+        }
+        None
+    }
+
+    /// Ack whatever the session has waiting, None if nothing is
+    pub fn flush(&mut self, session_id: SessionId) -> Option<Packet> {
+        let pending = self.pending.remove(&session_id)?;
+        Some(Packet::ack(session_id, &to_ranges(&pending)))
+    }
+
+    /// Ack everything waiting in every session
+    pub fn flush_all(&mut self) -> Vec<Packet> {
+        self.pending
+            .drain()
+            .map(|(session_id, pending)| Packet::ack(session_id, &to_ranges(&pending)))
+            .collect()
+    }
+
+    /// Batch acks from `received` into `acks` until `received` closes
+    /// Anything still waiting is flushed every `interval` and once more at the end
+    pub async fn run(
+        mut self,
+        mut received: mpsc::Receiver<(SessionId, Sequence)>,
+        acks: mpsc::Sender<Packet>,
+        interval: Duration,
+    ) {
+        let mut timer = tokio::time::interval(interval);
+        loop {
+            let batch = tokio::select! {
+                next = received.recv() => match next {
+                    Some((session_id, sequence)) => self.record(session_id, sequence).into_iter().collect(),
+                    None => break,
+                },
+                _ = timer.tick() => self.flush_all(),
+            };
+            for ack in batch {
+                if acks.send(ack).await.is_err() {
+                    return;
+                }
+            }
+        }
+        for ack in self.flush_all() {
+            let _ = acks.send(ack).await;
+        }
+    }
+}
+
+// sorted sequences -> contiguous inclusive ranges
+// sorted numerically, so a run across the wrap (u32::MAX then 0) comes out as two ranges
+fn to_ranges(sequences: &BTreeSet<Sequence>) -> Vec<RangeInclusive<Sequence>> {
+    let mut ranges: Vec<RangeInclusive<Sequence>> = Vec::new();
+    for &sequence in sequences {
+        match ranges.last_mut() {
+            Some(last) if last.end().checked_add(1) == Some(sequence) => *last = *last.start()..=sequence,
+            _ => ranges.push(sequence..=sequence),
+        }
+    }
+    ranges
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_threshold_emits_one_ack_for_the_run() {
+        let session_id = SessionId::new();
+        let mut batcher = AckBatcher::new(10);
+
+        for sequence in 1..10 {
+            assert!(batcher.record(session_id, sequence).is_none());
+        }
+        let ack = batcher.record(session_id, 10).expect("tenth ack should flush");
+
+        assert_eq!(ack.session_id, session_id);
+        assert_eq!(ack.ack_ranges().unwrap(), vec![1..=10]);
+        assert!(batcher.flush(session_id).is_none());
+    }
+
+    #[test]
+    fn test_gaps_become_separate_ranges() {
+        let session_id = SessionId::new();
+        let mut batcher = AckBatcher::new(100);
+        for sequence in [5, 1, 2, 3, 9] {
+            batcher.record(session_id, sequence);
+        }
+
+        let ack = batcher.flush(session_id).unwrap();
+        assert_eq!(ack.ack_ranges().unwrap(), vec![1..=3, 5..=5, 9..=9]);
+    }
+
+    #[test]
+    fn test_acks_across_the_wrap() {
+        let session_id = SessionId::new();
+        let mut batcher = AckBatcher::new(100);
+        for sequence in [u32::MAX - 1, u32::MAX, 0] {
+            batcher.record(session_id, sequence);
+        }
+
+        let ack = batcher.flush(session_id).unwrap();
+        let ack = Packet::from_bytes(&ack.to_bytes()).unwrap();
+        assert_eq!(ack.ack_ranges().unwrap(), vec![0..=0, u32::MAX - 1..=u32::MAX]);
+    }
+
+    #[tokio::test]
+    async fn test_timer_flushes_partial_batch() {
+        let session_id = SessionId::new();
+        let (received_tx, received_rx) = mpsc::channel(16);
+        let (acks_tx, mut acks_rx) = mpsc::channel(16);
+
+        // queued up front so they all land before the first real tick
+        for sequence in 1..4 {
+            received_tx.send((session_id, sequence)).await.unwrap();
+        }
+        tokio::spawn(AckBatcher::new(100).run(received_rx, acks_tx, Duration::from_millis(10)));
+
+        let ack = tokio::time::timeout(Duration::from_secs(1), acks_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(ack.ack_ranges().unwrap(), vec![1..=3]);
+    }
+}
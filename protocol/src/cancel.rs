@@ -0,0 +1,103 @@
+//server side of Cancel: which requests of a session are still being worked on
+//a handler registers the request's sequence when it starts and gets a CancelHandle back,
+//it checks the handle between chunks of work and bails out once it's set. on_cancel flips
+//the handle and forgets the request, a Cancel for something unknown (already finished, never
+//seen) is ignored
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::packet::*;
+
+/// Shared with the task doing the work, set once the request is cancelled
+#[derive(Debug, Clone, Default)]
+pub struct CancelHandle(Arc<AtomicBool>);
+
+impl CancelHandle {
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+/// In-flight requests of one session
+#[derive(Debug, Default)]
+pub struct InFlightRequests {
+    requests: HashMap<Sequence, CancelHandle>,
+}
+
+impl InFlightRequests {
+    pub fn new() -> Self {
+        InFlightRequests::default()
+    }
+
+    /// A request started, keep the returned handle with whatever is processing it
+    pub fn start(&mut self, sequence: Sequence) -> CancelHandle {
+        self.requests.entry(sequence).or_default().clone()
+    }
+
+    /// The request finished normally
+    pub fn finish(&mut self, sequence: Sequence) {
+        self.requests.remove(&sequence);
+    }
+
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+
+    /// Cancel a request, true if it was in flight
+    pub fn on_cancel(&mut self, sequence: Sequence) -> bool {
+        match self.requests.remove(&sequence) {
+            Some(handle) => {
+                handle.0.store(true, Ordering::Release);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Apply a received Cancel packet
+    pub fn handle_cancel(&mut self, packet: &Packet) -> Result<bool, PacketError> {
+        Ok(self.on_cancel(packet.cancelled_sequence()?))
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancel_in_flight_request() {
+        let session_id = SessionId::new();
+        let mut requests = InFlightRequests::new();
+        let search = requests.start(10);
+        let push = requests.start(11);
+
+        let cancel = Packet::from_bytes(&Packet::cancel(session_id, 10).to_bytes()).unwrap();
+        assert!(requests.handle_cancel(&cancel).unwrap());
+        assert!(search.is_cancelled());
+        assert!(!push.is_cancelled());
+        assert_eq!(requests.len(), 1);
+    }
+
+    #[test]
+    fn test_cancel_unknown_is_noop() {
+        let mut requests = InFlightRequests::new();
+        let search = requests.start(10);
+        requests.finish(10);
+
+        // finished already, and never seen at all
+        assert!(!requests.on_cancel(10));
+        assert!(!requests.on_cancel(999));
+        assert!(!search.is_cancelled());
+        assert!(requests.is_empty());
+    }
+}
@@ -0,0 +1,95 @@
+//capture files: serialized packets back to back, no framing in between
+//the reader walks a byte slice (typically a memory-mapped file, so the OS pages it in as
+//needed) using each packet's own length fields to find the next one. Packets come out as
+//CowPackets borrowing their payload from the slice, nothing is copied
+//
+//every packet is fully checked (structure + hash). A bad or truncated packet ends the
+//iteration: without a valid length there's no telling where the next packet starts
+
+use crate::packet::*;
+
+#[derive(Debug, Clone)]
+pub struct PacketCaptureReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    failed: bool,
+}
+
+impl<'a> PacketCaptureReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        PacketCaptureReader {
+            bytes,
+            offset: 0,
+            failed: false,
+        }
+    }
+
+    /// Where the next packet starts
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    fn next_packet(&mut self) -> Result<CowPacket<'a>, PacketError> {
+        let rest = &self.bytes[self.offset..];
+        let len = Packet::wire_len(rest)?;
+        let packet = Packet::try_from_bytes_cow(&rest[..len])?;
+        self.offset += len;
+        Ok(packet)
+    }
+}
+
+impl<'a> Iterator for PacketCaptureReader<'a> {
+    type Item = Result<CowPacket<'a>, PacketError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.offset >= self.bytes.len() {
+            return None;
+        }
+        let packet = self.next_packet();
+        self.failed = packet.is_err();
+        Some(packet)
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capture() -> Vec<u8> {
+        let session_id = SessionId::new();
+        let mut with_ext = Packet::new(session_id, Intent::DataPush, vec![9; 500]);
+        with_ext.set_payload_crc();
+
+        let mut bytes = Vec::new();
+        bytes.extend(Packet::new(session_id, Intent::Ping, vec![]).to_bytes());
+        bytes.extend(with_ext.to_bytes());
+        bytes.extend(Packet::new(session_id, Intent::Close, vec![]).to_bytes());
+        bytes
+    }
+
+    #[test]
+    fn test_iterates_concatenated_packets() {
+        let bytes = capture();
+        let packets: Vec<CowPacket> = PacketCaptureReader::new(&bytes).map(Result::unwrap).collect();
+
+        let intents: Vec<Intent> = packets.iter().map(|packet| packet.intent).collect();
+        assert_eq!(intents, vec![Intent::Ping, Intent::DataPush, Intent::Close]);
+        assert!(packets.iter().all(CowPacket::is_borrowed));
+        assert_eq!(packets[1].payload.len(), 500);
+    }
+
+    #[test]
+    fn test_truncated_capture_stops() {
+        let bytes = capture();
+        let mut reader = PacketCaptureReader::new(&bytes[..bytes.len() - 5]);
+
+        assert!(reader.next().unwrap().is_ok());
+        assert!(reader.next().unwrap().is_ok());
+        assert!(matches!(reader.next(), Some(Err(PacketError::UnexpectedEof { .. }))));
+        assert!(reader.next().is_none());
+    }
+}
@@ -0,0 +1,91 @@
+//where now_ms comes from
+//everything time-based in this crate (age_ms, the reaper, cookies, deadlines) takes `now_ms` as
+//an argument instead of reading the clock itself. A server holds one `&dyn Clock` and passes
+//`clock.now_ms()` down, tests hold a MockClock and move time forward when they want to
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub trait Clock: Send + Sync {
+    /// Milliseconds since the Unix epoch
+    fn now_ms(&self) -> u64;
+}
+
+/// The wall clock, same time base as Packet::new's timestamps
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_ms(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64
+    }
+}
+
+/// Stands still until told to move, for tests
+#[derive(Debug, Default)]
+pub struct MockClock {
+    now: AtomicU64,
+}
+
+impl MockClock {
+    pub fn new(now_ms: u64) -> Self {
+        MockClock {
+            now: AtomicU64::new(now_ms),
+        }
+    }
+
+    pub fn advance(&self, ms: u64) {
+        self.now.fetch_add(ms, Ordering::Relaxed);
+    }
+
+    pub fn set(&self, now_ms: u64) {
+        self.now.store(now_ms, Ordering::Relaxed);
+    }
+}
+
+impl Clock for MockClock {
+    fn now_ms(&self) -> u64 {
+        self.now.load(Ordering::Relaxed)
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::*;
+    use crate::reaper::SessionReaper;
+
+    #[test]
+    fn test_mock_clock_drives_expiry() {
+        let clock = MockClock::new(1_700_000_000_000);
+        let mut packet = Packet::new(SessionId::new(), Intent::Ping, vec![]);
+        packet.timestamp = clock.now_ms();
+        packet.reseal();
+
+        let mut reaper = SessionReaper::new(5_000);
+        reaper.touch(packet.session_id, clock.now_ms());
+
+        clock.advance(5_000);
+        assert!(!packet.is_expired(5_000, clock.now_ms()));
+        assert!(reaper.sweep(clock.now_ms()).is_empty());
+
+        clock.advance(1);
+        assert!(packet.is_expired(5_000, clock.now_ms()));
+        assert_eq!(reaper.sweep(clock.now_ms()), vec![packet.session_id]);
+    }
+
+    #[test]
+    fn test_system_clock_matches_packet_timestamps() {
+        let before = SystemClock.now_ms();
+        let packet = Packet::new(SessionId::new(), Intent::Ping, vec![]);
+        assert!(packet.timestamp >= before);
+        assert!(packet.timestamp <= SystemClock.now_ms());
+    }
+}
@@ -0,0 +1,179 @@
+//one end of a session over some transport: outgoing packets wait in a priority queue until
+//flush() sends them, incoming ones are checked and have to belong to the session
+//
+//the connection only knows it has a `dyn Transport`, so the same code runs over UDP, TCP or a
+//MemoryTransport pair in tests
+//
+//handshake: the client sends HandshakeInit and waits for HandshakeAck, the server learns the
+//session id from the init (Connection::accept). Both go out unencrypted, there's no key yet
+
+use crate::packet::*;
+use crate::queue::PriorityQueue;
+use crate::transport::Transport;
+
+pub struct Connection {
+    transport: Box<dyn Transport>,
+    session_id: SessionId,
+    outgoing: PriorityQueue,
+}
+
+impl Connection {
+    pub fn new(transport: Box<dyn Transport>, session_id: SessionId) -> Self {
+        Connection {
+            transport,
+            session_id,
+            outgoing: PriorityQueue::new(),
+        }
+    }
+
+    /// Server side of the handshake: wait for a HandshakeInit, answer it with a HandshakeAck
+    /// carrying `welcome`. Returns the connection for the client's session and the init
+    pub async fn accept(mut transport: Box<dyn Transport>, welcome: Vec<u8>) -> Result<(Self, Packet), PacketError> {
+        let init = transport.recv_packet().await?;
+        if init.intent != Intent::HandshakeInit {
+            return Err(PacketError::UnexpectedIntent(init.intent));
+        }
+        let ack = handshake_packet(init.session_id, Intent::HandshakeAck, welcome);
+        transport.send_packet(&ack).await?;
+        Ok((Connection::new(transport, init.session_id), init))
+    }
+
+    /// Client side of the handshake: send a HandshakeInit carrying `hello`, return the HandshakeAck
+    pub async fn handshake(&mut self, hello: Vec<u8>) -> Result<Packet, PacketError> {
+        let init = handshake_packet(self.session_id, Intent::HandshakeInit, hello);
+        self.transport.send_packet(&init).await?;
+        let ack = self.recv().await?;
+        if ack.intent != Intent::HandshakeAck {
+            return Err(PacketError::UnexpectedIntent(ack.intent));
+        }
+        Ok(ack)
+    }
+
+    /// Only send packets at or above `min_priority`, queue() hands anything lower back
+    pub fn with_min_priority(mut self, min_priority: Priority) -> Self {
+        self.outgoing = std::mem::take(&mut self.outgoing).with_min_priority(min_priority);
+        self
+    }
+
+    pub fn min_priority(&self) -> Priority {
+        self.outgoing.min_priority()
+    }
+
+    /// Would this packet be allowed out on this connection?
+    pub fn accept_outgoing(&self, packet: &Packet) -> bool {
+        self.outgoing.accept_outgoing(packet)
+    }
+
+    pub fn session_id(&self) -> SessionId {
+        self.session_id
+    }
+
+    /// Queue a packet for the next flush
+    /// None when it was queued, the packet itself when it was below min_priority
+    pub fn queue(&mut self, packet: Packet) -> Option<Packet> {
+        self.outgoing.push(packet)
+    }
+
+    /// Packets queued and not sent yet
+    pub fn pending(&self) -> usize {
+        self.outgoing.len()
+    }
+
+    /// Send everything queued, highest priority first
+    /// A failed send leaves that packet (and everything after it) queued
+    pub async fn flush(&mut self) -> Result<usize, PacketError> {
+        let mut sent = 0;
+        while let Some(packet) = self.outgoing.peek() {
+            self.transport.send_packet(packet).await?;
+            self.outgoing.pop();
+            sent += 1;
+        }
+        Ok(sent)
+    }
+
+    /// Next packet from the peer, fully checked
+    /// A packet for another session is a SessionMismatch
+    pub async fn recv(&mut self) -> Result<Packet, PacketError> {
+        let packet = self.transport.recv_packet().await?;
+        if packet.session_id != self.session_id {
+            return Err(PacketError::SessionMismatch);
+        }
+        Ok(packet)
+    }
+}
+
+fn handshake_packet(session_id: SessionId, intent: Intent, payload: Vec<u8>) -> Packet {
+    let mut packet = Packet::new(session_id, intent, payload);
+    packet.priority = Priority::for_intent(intent);
+    packet.flags = Flags::new();
+    packet.reseal();
+    packet
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::MemoryTransport;
+
+    #[tokio::test]
+    async fn test_handshake_then_data_over_memory() {
+        let (client, server) = MemoryTransport::pair();
+        let session_id = SessionId::new();
+
+        let server_task = tokio::spawn(async move {
+            let (mut connection, init) = Connection::accept(Box::new(server), b"welcome".to_vec())
+                .await
+                .unwrap();
+            assert_eq!(init.payload, b"hello");
+            connection.recv().await.unwrap()
+        });
+
+        let mut client = Connection::new(Box::new(client), session_id);
+        let ack = client.handshake(b"hello".to_vec()).await.unwrap();
+        assert_eq!(ack.session_id, session_id);
+        assert_eq!(ack.payload, b"welcome");
+
+        assert!(client.queue(Packet::new(session_id, Intent::DataPush, b"after the handshake".to_vec())).is_none());
+        assert_eq!(client.flush().await.unwrap(), 1);
+        assert_eq!(client.pending(), 0);
+
+        let data = server_task.await.unwrap();
+        assert_eq!(data.session_id, session_id);
+        assert_eq!(data.payload, b"after the handshake");
+    }
+
+    #[tokio::test]
+    async fn test_min_priority_gates_the_connection() {
+        let (client, mut server) = MemoryTransport::pair();
+        let session_id = SessionId::new();
+        let mut client = Connection::new(Box::new(client), session_id).with_min_priority(Priority::NORMAL);
+        assert_eq!(client.min_priority(), Priority::NORMAL);
+
+        let mut low = Packet::new(session_id, Intent::DataPush, b"low".to_vec());
+        low.priority = Priority::LOW;
+        assert!(!client.accept_outgoing(&low));
+        assert_eq!(client.queue(low).unwrap().payload, b"low");
+
+        let mut high = Packet::new(session_id, Intent::DataPush, b"high".to_vec());
+        high.priority = Priority::HIGH;
+        high.reseal();
+        assert!(client.queue(high).is_none());
+
+        assert_eq!(client.flush().await.unwrap(), 1);
+        assert_eq!(server.recv_packet().await.unwrap().payload, b"high");
+    }
+
+    #[tokio::test]
+    async fn test_recv_rejects_other_sessions() {
+        let (client, mut server) = MemoryTransport::pair();
+        let mut client = Connection::new(Box::new(client), SessionId::new());
+
+        let stray = Packet::new(SessionId::new(), Intent::Ping, vec![]);
+        server.send_packet(&stray).await.unwrap();
+        assert!(matches!(client.recv().await, Err(PacketError::SessionMismatch)));
+    }
+}
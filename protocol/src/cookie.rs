@@ -0,0 +1,191 @@
+//handshake cookies, so a flood of HandshakeInit can't make the server allocate sessions
+//
+//first HandshakeInit (no cookie) -> HandshakeAck carrying a cookie, nothing is stored
+//HandshakeInit again, echoing the cookie -> checked, and only then is the session set up
+//
+//the cookie proves the client can receive at its address and is recent:
+// 8 bytes  | issue time in ms (u64, big-endian)
+// 32 bytes | HMAC-SHA256(secret, address | port | session id | issue time)
+//it rides in the extended header so the HandshakeInit payload stays the application's
+
+use std::net::{IpAddr, SocketAddr};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::packet::*;
+
+const TIMESTAMP_SIZE: usize = 8;
+const TAG_SIZE: usize = 32;
+pub const COOKIE_SIZE: usize = TIMESTAMP_SIZE + TAG_SIZE;
+
+/// What to do with a HandshakeInit
+#[derive(Debug)]
+pub enum HandshakeOutcome {
+    /// No (usable) cookie yet, send this HandshakeAck back and forget about the client
+    Challenge(Box<Packet>),
+    /// Cookie checked out, this session can be set up now
+    Established(SessionId),
+}
+
+#[derive(Debug, Clone)]
+pub struct CookieIssuer {
+    secret: [u8; 32],
+    ttl_ms: u64,
+}
+
+impl CookieIssuer {
+    /// Cookies older than `ttl_ms` are rejected
+    /// Rotating the secret invalidates every cookie handed out so far
+    pub fn new(secret: [u8; 32], ttl_ms: u64) -> Self {
+        CookieIssuer { secret, ttl_ms }
+    }
+
+    /// Cookie for a client at `addr` opening `session_id`
+    pub fn issue(&self, addr: SocketAddr, session_id: &SessionId, now_ms: u64) -> Vec<u8> {
+        let mut cookie = Vec::with_capacity(COOKIE_SIZE);
+        cookie.extend_from_slice(&now_ms.to_be_bytes());
+        cookie.extend_from_slice(&self.mac(addr, session_id, now_ms).finalize().into_bytes());
+        cookie
+    }
+
+    /// Check a cookie a client echoed back
+    pub fn verify(
+        &self,
+        addr: SocketAddr,
+        session_id: &SessionId,
+        cookie: &[u8],
+        now_ms: u64,
+    ) -> Result<(), PacketError> {
+        if cookie.len() != COOKIE_SIZE {
+            return Err(PacketError::InvalidCookie);
+        }
+        let (timestamp, tag) = cookie.split_at(TIMESTAMP_SIZE);
+        let issued = u64::from_be_bytes(timestamp.try_into().expect("split at TIMESTAMP_SIZE"));
+
+        // issued in the future doesn't get a pass either
+        if issued > now_ms || now_ms - issued > self.ttl_ms {
+            return Err(PacketError::InvalidCookie);
+        }
+
+        // verify_slice compares in constant time
+        self.mac(addr, session_id, issued)
+            .verify_slice(tag)
+            .map_err(|_| PacketError::InvalidCookie)
+    }
+
+    /// Answer a HandshakeInit: challenge it, or let it through if it carries a valid cookie
+    /// A cookie that doesn't check out is an error, not a fresh challenge, so the caller can log it
+    pub fn handle_init(
+        &self,
+        packet: &Packet,
+        addr: SocketAddr,
+        now_ms: u64,
+    ) -> Result<HandshakeOutcome, PacketError> {
+        if packet.intent != Intent::HandshakeInit {
+            return Err(PacketError::UnexpectedIntent(packet.intent));
+        }
+
+        match &packet.ext.cookie {
+            None => {
+                let mut ack = Packet::new(packet.session_id, Intent::HandshakeAck, vec![]);
+                ack.priority = Priority::for_intent(Intent::HandshakeAck);
+                ack.ext.cookie = Some(self.issue(addr, &packet.session_id, now_ms));
+                ack.reseal();
+                Ok(HandshakeOutcome::Challenge(Box::new(ack)))
+            }
+            Some(cookie) => {
+                self.verify(addr, &packet.session_id, cookie, now_ms)?;
+                Ok(HandshakeOutcome::Established(packet.session_id))
+            }
+        }
+    }
+
+    fn mac(&self, addr: SocketAddr, session_id: &SessionId, issued: u64) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC takes any key size");
+        match addr.ip() {
+            IpAddr::V4(ip) => mac.update(&ip.octets()),
+            IpAddr::V6(ip) => mac.update(&ip.octets()),
+        }
+        mac.update(&addr.port().to_be_bytes());
+        mac.update(session_id.as_bytes());
+        mac.update(&issued.to_be_bytes());
+        mac
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTL_MS: u64 = 10_000;
+
+    fn issuer() -> CookieIssuer {
+        CookieIssuer::new([0x5E; 32], TTL_MS)
+    }
+
+    fn client_addr() -> SocketAddr {
+        "192.0.2.7:40000".parse().unwrap()
+    }
+
+    // client's first HandshakeInit, returns the cookie the server answered with
+    fn challenge(issuer: &CookieIssuer, session_id: SessionId, now_ms: u64) -> Vec<u8> {
+        let init = Packet::new(session_id, Intent::HandshakeInit, b"hello".to_vec());
+        match issuer.handle_init(&init, client_addr(), now_ms).unwrap() {
+            HandshakeOutcome::Challenge(ack) => {
+                // through the wire, the cookie lives in the extended header
+                let ack = Packet::from_bytes(&ack.to_bytes()).unwrap();
+                assert_eq!(ack.intent, Intent::HandshakeAck);
+                ack.ext.cookie.unwrap()
+            }
+            HandshakeOutcome::Established(_) => panic!("established without a cookie"),
+        }
+    }
+
+    fn echo(session_id: SessionId, cookie: Vec<u8>) -> Packet {
+        let mut init = Packet::new(session_id, Intent::HandshakeInit, b"hello".to_vec());
+        init.ext.cookie = Some(cookie);
+        init.reseal();
+        init
+    }
+
+    #[test]
+    fn test_valid_cookie_establishes_session() {
+        let issuer = issuer();
+        let session_id = SessionId::new();
+        let cookie = challenge(&issuer, session_id, 1_000);
+
+        let outcome = issuer.handle_init(&echo(session_id, cookie), client_addr(), 2_000).unwrap();
+        assert!(matches!(outcome, HandshakeOutcome::Established(id) if id == session_id));
+    }
+
+    #[test]
+    fn test_forged_and_expired_cookies_rejected() {
+        let issuer = issuer();
+        let session_id = SessionId::new();
+        let cookie = challenge(&issuer, session_id, 1_000);
+
+        // expired
+        let late = issuer.handle_init(&echo(session_id, cookie.clone()), client_addr(), 1_000 + TTL_MS + 1);
+        assert!(matches!(late, Err(PacketError::InvalidCookie)));
+
+        // tampered tag
+        let mut forged = cookie.clone();
+        forged[COOKIE_SIZE - 1] ^= 0x01;
+        let outcome = issuer.handle_init(&echo(session_id, forged), client_addr(), 2_000);
+        assert!(matches!(outcome, Err(PacketError::InvalidCookie)));
+
+        // someone else's address, or another session
+        let other: SocketAddr = "198.51.100.1:40000".parse().unwrap();
+        assert!(issuer.verify(other, &session_id, &cookie, 2_000).is_err());
+        assert!(issuer.verify(client_addr(), &SessionId::new(), &cookie, 2_000).is_err());
+
+        // a different secret knows nothing about it
+        let rotated = CookieIssuer::new([0x11; 32], TTL_MS);
+        assert!(rotated.verify(client_addr(), &session_id, &cookie, 2_000).is_err());
+    }
+}
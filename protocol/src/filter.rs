@@ -0,0 +1,88 @@
+//which intents a node is willing to accept
+//e.g. a read-only edge node has no business taking RankingUpdate or DataPush
+//start from allow_all() and deny what you don't want, or deny_all() and allow what you do
+
+use crate::packet::*;
+
+#[derive(Debug, Clone)]
+pub struct IntentFilter {
+    allowed: [bool; 256], // indexed by the intent byte
+}
+
+impl IntentFilter {
+    /// Accept everything, deny() to take intents away
+    pub fn allow_all() -> Self {
+        IntentFilter { allowed: [true; 256] }
+    }
+
+    /// Accept nothing, allow() to add intents
+    pub fn deny_all() -> Self {
+        IntentFilter { allowed: [false; 256] }
+    }
+
+    pub fn allow(mut self, intent: Intent) -> Self {
+        self.allowed[intent.to_u8() as usize] = true;
+        self
+    }
+
+    pub fn deny(mut self, intent: Intent) -> Self {
+        self.allowed[intent.to_u8() as usize] = false;
+        self
+    }
+
+    pub fn is_allowed(&self, intent: Intent) -> bool {
+        self.allowed[intent.to_u8() as usize]
+    }
+
+    /// Gate a packet before dispatching it
+    pub fn check(&self, packet: &Packet) -> Result<(), PacketError> {
+        if self.is_allowed(packet.intent) {
+            Ok(())
+        } else {
+            Err(PacketError::IntentNotAllowed(packet.intent))
+        }
+    }
+}
+
+impl Default for IntentFilter {
+    fn default() -> Self {
+        IntentFilter::allow_all()
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_denied_intent_rejected() {
+        // read-only edge node
+        let filter = IntentFilter::allow_all()
+            .deny(Intent::RankingUpdate)
+            .deny(Intent::DataPush);
+
+        let search = Packet::new(SessionId::new(), Intent::Search, vec![]);
+        assert!(filter.check(&search).is_ok());
+
+        let update = Packet::new(SessionId::new(), Intent::RankingUpdate, vec![]);
+        assert!(matches!(
+            filter.check(&update),
+            Err(PacketError::IntentNotAllowed(Intent::RankingUpdate))
+        ));
+    }
+
+    #[test]
+    fn test_allow_list() {
+        let filter = IntentFilter::deny_all()
+            .allow(Intent::Ping)
+            .allow(Intent::Search);
+
+        assert!(filter.is_allowed(Intent::Ping));
+        assert!(filter.is_allowed(Intent::Search));
+        assert!(!filter.is_allowed(Intent::DataPush));
+    }
+}
@@ -0,0 +1,100 @@
+//sender side flow control
+//the receiver advertises how many packets it can still take (WindowUpdate), every send
+//uses up one slot, and once the window hits zero senders wait for the next update
+//instead of piling packets onto a receiver that can't keep up
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use tokio::sync::Notify;
+
+use crate::packet::*;
+
+#[derive(Debug)]
+pub struct FlowController {
+    window: AtomicU32,
+    opened: Notify, // woken whenever an update arrives
+}
+
+impl FlowController {
+    /// Start with the window agreed at handshake
+    pub fn new(initial_window: u32) -> Self {
+        FlowController {
+            window: AtomicU32::new(initial_window),
+            opened: Notify::new(),
+        }
+    }
+
+    /// Packets we may still send before the receiver has to open the window
+    pub fn available(&self) -> u32 {
+        self.window.load(Ordering::Acquire)
+    }
+
+    /// Take one slot if there is one, never waits
+    pub fn try_acquire(&self) -> bool {
+        self.window
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |window| window.checked_sub(1))
+            .is_ok()
+    }
+
+    /// Take one slot, waiting for a window update if the window is exhausted
+    pub async fn acquire(&self) {
+        loop {
+            // registered before the check so an update landing in between isn't missed
+            let opened = self.opened.notified();
+            if self.try_acquire() {
+                return;
+            }
+            opened.await;
+        }
+    }
+
+    /// Apply a WindowUpdate from the receiver and wake any waiting senders
+    pub fn handle_window_update(&self, packet: &Packet) -> Result<(), PacketError> {
+        let window = packet.window_size()?;
+        self.window.store(window, Ordering::Release);
+        self.opened.notify_waiters();
+        Ok(())
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_window_exhausts() {
+        let flow = FlowController::new(2);
+        assert!(flow.try_acquire());
+        assert!(flow.try_acquire());
+        assert!(!flow.try_acquire());
+        assert_eq!(flow.available(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_sends_resume_after_window_update() {
+        let flow = Arc::new(FlowController::new(1));
+        flow.acquire().await;
+
+        // window is used up, the next send has to wait
+        let sender = {
+            let flow = flow.clone();
+            tokio::spawn(async move { flow.acquire().await })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!sender.is_finished());
+
+        flow.handle_window_update(&Packet::window_update(SessionId::new(), 4)).unwrap();
+        tokio::time::timeout(Duration::from_secs(1), sender)
+            .await
+            .expect("sender still blocked after window update")
+            .unwrap();
+        assert_eq!(flow.available(), 3);
+    }
+}
@@ -0,0 +1,628 @@
+//splitting a big message into several packets and putting it back together
+//every fragment carries a FragmentInfo in its extended header: a random group id shared by
+//the whole message, its index and the fragment count. The group id is what lets two big
+//messages from the same session arrive interleaved, the reassembler keeps one buffer per
+//(session, group)
+//
+//fragments keep the message's intent, priority and sequence, and have the fragmented flag set
+//the first fragment also carries the whole message size so receivers can show progress
+//fragment_boosted() additionally lifts the first fragment to at least HIGH, for progressive
+//rendering where the leading bytes (metadata, headers) are wanted before the rest
+//
+//fragment_content_defined() cuts where the content says to instead of every n bytes: a rolling
+//(gear) hash over the last few dozen bytes picks the boundaries, so inserting a byte only moves
+//the cut around it and every chunk after that comes out the same as before. Each of those
+//fragments carries its content fingerprint, which is what delta sync dedups on across versions
+//
+//FragmentStream builds fragments straight from a reader, compressing each one on its own, so a
+//big push never has to sit in memory whole. Those fragments carry the fragment-compressed
+//marker and the reassembler decompresses them one by one before putting the message together
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
+
+use rand::Rng;
+
+use crate::packet::compression::{compress_with_level, decompress, resolve, CompressionLevel};
+use crate::packet::*;
+
+impl Packet {
+    /// Split the payload into fragments of at most `max_fragment_payload` bytes
+    /// Always at least one fragment, an empty payload gives one empty fragment
+    pub fn fragment(&self, max_fragment_payload: usize) -> Result<Vec<Packet>, PacketError> {
+        if max_fragment_payload == 0 {
+            return Err(PacketError::InvalidFragment);
+        }
+        let chunks: Vec<&[u8]> = if self.payload.is_empty() {
+            vec![&[]]
+        } else {
+            self.payload.chunks(max_fragment_payload).collect()
+        };
+        self.fragment_chunks(chunks, false)
+    }
+
+    /// Split the payload at content-defined boundaries (see ContentChunking)
+    /// Every fragment carries its content fingerprint for dedup
+    pub fn fragment_content_defined(&self, chunking: ContentChunking) -> Result<Vec<Packet>, PacketError> {
+        let chunks: Vec<&[u8]> = if self.payload.is_empty() {
+            vec![&[]]
+        } else {
+            chunking
+                .chunks(&self.payload)?
+                .into_iter()
+                .map(|range| &self.payload[range])
+                .collect()
+        };
+        self.fragment_chunks(chunks, true)
+    }
+
+    fn fragment_chunks(&self, chunks: Vec<&[u8]>, fingerprint: bool) -> Result<Vec<Packet>, PacketError> {
+        let count = u16::try_from(chunks.len()).map_err(|_| PacketError::InvalidFragment)?;
+        let group = rand::thread_rng().gen::<u32>();
+
+        chunks
+            .into_iter()
+            .enumerate()
+            .map(|(index, chunk)| {
+                let mut fragment = self.rebuild_with_payload(chunk.to_vec())?;
+                fragment.flags.set_fragmented(true);
+                fragment.ext.fragment = Some(FragmentInfo {
+                    group,
+                    index: index as u16,
+                    count,
+                });
+                if index == 0 {
+                    fragment.ext.total_size = Some(self.payload.len() as u64);
+                }
+                if fingerprint {
+                    // reseals
+                    fragment.set_content_fingerprint();
+                } else {
+                    fragment.reseal();
+                }
+                Ok(fragment)
+            })
+            .collect()
+    }
+
+    /// fragment(), with the first fragment's priority raised to at least Priority::HIGH
+    /// The rest keep the message's priority
+    pub fn fragment_boosted(&self, max_fragment_payload: usize) -> Result<Vec<Packet>, PacketError> {
+        let mut fragments = self.fragment(max_fragment_payload)?;
+        let first = &mut fragments[0];
+        first.priority = first.priority.max(Priority::HIGH);
+        first.reseal();
+        Ok(fragments)
+    }
+}
+
+/// Chunk size bounds for content-defined fragmentation
+/// Cuts land on average every `avg` bytes (rounded up to a power of two), never closer than
+/// `min` and never further apart than `max`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentChunking {
+    pub min: usize,
+    pub avg: usize,
+    pub max: usize,
+}
+
+impl Default for ContentChunking {
+    fn default() -> Self {
+        ContentChunking {
+            min: 2 * 1024,
+            avg: 8 * 1024,
+            max: 64 * 1024,
+        }
+    }
+}
+
+impl ContentChunking {
+    /// Where to cut `data`, as consecutive ranges covering all of it
+    pub fn chunks(&self, data: &[u8]) -> Result<Vec<std::ops::Range<usize>>, PacketError> {
+        if self.min == 0 || self.min > self.avg || self.avg > self.max {
+            return Err(PacketError::InvalidFragment);
+        }
+        let mask = self.avg.next_power_of_two() as u64 - 1;
+
+        let mut ranges = Vec::new();
+        let mut start = 0;
+        while start < data.len() {
+            let end = (start + self.max).min(data.len());
+            let mut cut = end;
+            let mut hash = 0u64;
+            for (i, &byte) in data.iter().enumerate().take(end).skip(start) {
+                // each byte's contribution shifts out after 64 steps, so the hash only
+                // depends on the last 64 bytes
+                hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+                if i + 1 - start >= self.min && hash & mask == 0 {
+                    cut = i + 1;
+                    break;
+                }
+            }
+            ranges.push(start..cut);
+            start = cut;
+        }
+        Ok(ranges)
+    }
+}
+
+// random values per byte for the gear hash, generated (splitmix64) rather than drawn at
+// runtime so every sender cuts the same content in the same places
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// Fragments of a message read from `reader`, each chunk compressed independently
+/// The length has to be known up front, the fragment count goes in every fragment
+pub struct FragmentStream<R> {
+    reader: R,
+    header: Packet,
+    compression: Compression,
+    level: CompressionLevel,
+    chunk_size: usize,
+    group: u32,
+    index: u16,
+    count: u16,
+    total: u64,
+    read: u64,
+}
+
+impl<R: Read> FragmentStream<R> {
+    /// `header` supplies session, intent, priority, sequence (its payload is ignored),
+    /// `total` is how many bytes `reader` will produce, cut into `chunk_size` pieces
+    pub fn new(
+        reader: R,
+        header: &Packet,
+        total: u64,
+        chunk_size: usize,
+        compression: Compression,
+        level: CompressionLevel,
+    ) -> Result<Self, PacketError> {
+        if chunk_size == 0 || chunk_size > MAX_PAYLOAD_SIZE {
+            return Err(PacketError::InvalidFragment);
+        }
+        let count = total.div_ceil(chunk_size as u64).max(1);
+        let count = u16::try_from(count).map_err(|_| PacketError::InvalidFragment)?;
+
+        Ok(FragmentStream {
+            reader,
+            header: header.rebuild_with_payload(Vec::new())?,
+            compression,
+            level,
+            chunk_size,
+            group: rand::thread_rng().gen::<u32>(),
+            index: 0,
+            count,
+            total,
+            read: 0,
+        })
+    }
+
+    fn next_fragment(&mut self) -> Result<Packet, PacketError> {
+        let want = (self.total - self.read).min(self.chunk_size as u64);
+        let mut chunk = Vec::with_capacity(want as usize);
+        (&mut self.reader)
+            .take(want)
+            .read_to_end(&mut chunk)
+            .map_err(|e| PacketError::Io(e.kind()))?;
+        self.read += chunk.len() as u64;
+        if (chunk.len() as u64) < want {
+            return Err(PacketError::UnexpectedEof {
+                read: self.read as usize,
+                needed: self.total as usize,
+            });
+        }
+
+        // Auto picks per chunk, the flags say what each one ended up with
+        let compression = resolve(self.compression, &chunk);
+        let compressed = compress_with_level(compression, self.level, &chunk, None)?;
+        let mut fragment = self.header.rebuild_with_payload(compressed)?;
+        fragment.flags.set_compression(compression);
+        fragment.flags.set_fragmented(true);
+        fragment.ext.fragment_compressed = true;
+        fragment.ext.fragment = Some(FragmentInfo {
+            group: self.group,
+            index: self.index,
+            count: self.count,
+        });
+        if self.index == 0 {
+            fragment.ext.total_size = Some(self.total);
+        }
+        fragment.reseal();
+
+        self.index += 1;
+        Ok(fragment)
+    }
+}
+
+impl<R: Read> Iterator for FragmentStream<R> {
+    type Item = Result<Packet, PacketError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index >= self.count {
+            return None;
+        }
+        let fragment = self.next_fragment();
+        if fragment.is_err() {
+            // no point going on after a short read or a codec failure
+            self.index = self.count;
+        }
+        Some(fragment)
+    }
+}
+
+// fragments of one message collected so far
+#[derive(Debug)]
+struct Group {
+    count: u16,
+    parts: BTreeMap<u16, Vec<u8>>,
+    received: u64,
+    // unknown until the first fragment shows up
+    total: Option<u64>,
+}
+
+/// What feeding a packet into the Reassembler did
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReassemblyEvent {
+    /// Still waiting on fragments, bytes received so far and the message size if known
+    Progress { received: u64, total: Option<u64> },
+    /// The whole payload (unfragmented packets complete right away)
+    Complete(Vec<u8>),
+}
+
+/// Reassembly never holds more than these per message
+/// A sender is free to announce any fragment count and size, without limits a handful
+/// of first fragments could pin gigabytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReassemblyLimits {
+    pub max_fragments: u16,
+    pub max_group_bytes: u64,
+}
+
+impl Default for ReassemblyLimits {
+    fn default() -> Self {
+        ReassemblyLimits {
+            max_fragments: u16::MAX,
+            max_group_bytes: 256 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Reassembler {
+    groups: HashMap<(SessionId, u32), Group>,
+    limits: ReassemblyLimits,
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Reassembler::default()
+    }
+
+    pub fn with_limits(mut self, limits: ReassemblyLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn limits(&self) -> ReassemblyLimits {
+        self.limits
+    }
+
+    /// Messages still missing fragments
+    pub fn pending(&self) -> usize {
+        self.groups.len()
+    }
+
+    /// (received bytes, total bytes if known) of a message still being reassembled
+    /// None for groups that completed or were never seen
+    pub fn progress(&self, session_id: SessionId, group: u32) -> Option<(u64, Option<u64>)> {
+        self.groups
+            .get(&(session_id, group))
+            .map(|group| (group.received, group.total))
+    }
+
+    /// Feed one packet in, get the whole payload back once its message is complete
+    /// Packets that aren't fragments come straight back out
+    pub fn push(&mut self, packet: Packet) -> Result<Option<Vec<u8>>, PacketError> {
+        Ok(match self.push_event(packet)? {
+            ReassemblyEvent::Complete(payload) => Some(payload),
+            ReassemblyEvent::Progress { .. } => None,
+        })
+    }
+
+    /// push() for callers that want progress updates along the way
+    /// A message going over the limits is dropped with everything received of it so far
+    pub fn push_event(&mut self, packet: Packet) -> Result<ReassemblyEvent, PacketError> {
+        let Some(info) = packet.ext.fragment else {
+            return Ok(ReassemblyEvent::Complete(packet.payload));
+        };
+        if info.count == 0 || info.index >= info.count {
+            return Err(PacketError::InvalidFragment);
+        }
+        let key = (packet.session_id, info.group);
+        if info.count > self.limits.max_fragments {
+            self.groups.remove(&key);
+            return Err(PacketError::TooManyFragments);
+        }
+        if packet.ext.total_size.is_some_and(|total| total > self.limits.max_group_bytes) {
+            self.groups.remove(&key);
+            return Err(PacketError::TooLarge);
+        }
+
+        let group = self.groups.entry(key).or_insert_with(|| Group {
+            count: info.count,
+            parts: BTreeMap::new(),
+            received: 0,
+            total: None,
+        });
+        if group.count != info.count {
+            return Err(PacketError::InvalidFragment);
+        }
+        if let Some(total) = packet.ext.total_size {
+            group.total = Some(total);
+        }
+        let payload = if packet.ext.fragment_compressed {
+            decompress(packet.flags.compression(), &packet.payload, None)?
+        } else {
+            packet.payload
+        };
+        let len = payload.len() as u64;
+        if let Some(previous) = group.parts.insert(info.index, payload) {
+            // duplicate, don't count it twice
+            group.received -= previous.len() as u64;
+        }
+        group.received += len;
+        if group.received > self.limits.max_group_bytes {
+            self.groups.remove(&key);
+            return Err(PacketError::TooLarge);
+        }
+
+        if group.parts.len() < group.count as usize {
+            return Ok(ReassemblyEvent::Progress {
+                received: group.received,
+                total: group.total,
+            });
+        }
+
+        let group = self.groups.remove(&key).expect("group was just updated");
+        Ok(ReassemblyEvent::Complete(group.parts.into_values().flatten().collect()))
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(session_id: SessionId, fill: u8, len: usize) -> Packet {
+        Packet::new(session_id, Intent::DataPush, (0..len).map(|i| fill ^ i as u8).collect())
+    }
+
+    #[test]
+    fn test_fragment_roundtrip() {
+        let original = message(SessionId::new(), 0x11, 1000);
+        let fragments = original.fragment(300).unwrap();
+        assert_eq!(fragments.len(), 4);
+
+        let mut reassembler = Reassembler::new();
+        let mut whole = None;
+        for fragment in fragments {
+            // through the wire, so the extended header entry is exercised too
+            let fragment = Packet::from_bytes(&fragment.to_bytes()).unwrap();
+            assert!(fragment.flags.is_fragmented());
+            whole = reassembler.push(fragment).unwrap();
+        }
+        assert_eq!(whole.unwrap(), original.payload);
+        assert_eq!(reassembler.pending(), 0);
+    }
+
+    #[test]
+    fn test_interleaved_messages_in_one_session() {
+        let session_id = SessionId::new();
+        let first = message(session_id, 0xAA, 500);
+        let second = message(session_id, 0x55, 500);
+        let first_fragments = first.fragment(100).unwrap();
+        let second_fragments = second.fragment(100).unwrap();
+
+        let mut reassembler = Reassembler::new();
+        let mut done = Vec::new();
+        // a1 b1 a2 b2 ... with the second message's order reversed for good measure
+        for (a, b) in first_fragments.into_iter().zip(second_fragments.into_iter().rev()) {
+            done.extend(reassembler.push(a).unwrap());
+            done.extend(reassembler.push(b).unwrap());
+        }
+
+        assert_eq!(done.len(), 2);
+        assert!(done.contains(&first.payload));
+        assert!(done.contains(&second.payload));
+    }
+
+    #[test]
+    fn test_progress_reaches_total() {
+        let session_id = SessionId::new();
+        let original = message(session_id, 0x42, 1000);
+        let fragments = original.fragment(128).unwrap();
+        let group = fragments[0].ext.fragment.unwrap().group;
+
+        let mut reassembler = Reassembler::new();
+        assert_eq!(reassembler.progress(session_id, group), None);
+
+        let mut last_received = 0;
+        let mut completed = None;
+        for fragment in fragments {
+            let fragment = Packet::from_bytes(&fragment.to_bytes()).unwrap();
+            match reassembler.push_event(fragment).unwrap() {
+                ReassemblyEvent::Progress { received, total } => {
+                    assert!(received > last_received);
+                    assert_eq!(total, Some(1000));
+                    assert_eq!(reassembler.progress(session_id, group), Some((received, total)));
+                    last_received = received;
+                }
+                ReassemblyEvent::Complete(payload) => completed = Some(payload),
+            }
+        }
+
+        assert_eq!(completed.unwrap().len(), 1000);
+        assert_eq!(reassembler.progress(session_id, group), None);
+    }
+
+    #[test]
+    fn test_first_fragment_boosted() {
+        let mut original = message(SessionId::new(), 0x33, 1000);
+        original.priority = Priority::LOW;
+        original.reseal();
+
+        let fragments = original.fragment_boosted(300).unwrap();
+        assert_eq!(fragments[0].priority, Priority::HIGH);
+        assert!(fragments[0].verify());
+        assert!(fragments[1..].iter().all(|fragment| fragment.priority == Priority::LOW));
+
+        // already above HIGH stays where it is
+        original.priority = Priority::CRITICAL;
+        original.reseal();
+        let fragments = original.fragment_boosted(300).unwrap();
+        assert!(fragments.iter().all(|fragment| fragment.priority == Priority::CRITICAL));
+    }
+
+    #[test]
+    fn test_stream_compressed_fragments() {
+        // 20MB, twice the single-packet limit, never held as one packet
+        let source: Vec<u8> = (0..20 * 1024 * 1024u32).map(|i| (i / 1024 % 251) as u8).collect();
+        let header = Packet::new(SessionId::new(), Intent::DataPush, vec![]);
+        let stream = FragmentStream::new(
+            &source[..],
+            &header,
+            source.len() as u64,
+            1024 * 1024,
+            Compression::Zstd,
+            CompressionLevel::Fast,
+        )
+        .unwrap();
+
+        let mut reassembler = Reassembler::new();
+        let mut whole = None;
+        let mut wire_bytes = 0;
+        for fragment in stream {
+            let bytes = fragment.unwrap().to_bytes();
+            wire_bytes += bytes.len();
+            let fragment = Packet::from_bytes(&bytes).unwrap();
+            assert!(fragment.ext.fragment_compressed);
+            whole = reassembler.push(fragment).unwrap();
+        }
+
+        assert!(wire_bytes < source.len() / 10);
+        assert!(whole.unwrap() == source);
+    }
+
+    #[test]
+    fn test_stream_short_reader() {
+        let header = Packet::new(SessionId::new(), Intent::DataPush, vec![]);
+        let data = [7u8; 100];
+        let mut stream =
+            FragmentStream::new(&data[..], &header, 250, 64, Compression::Lz4, CompressionLevel::Default).unwrap();
+
+        assert!(stream.next().unwrap().is_ok());
+        assert!(stream.next().unwrap().is_err());
+        assert!(stream.next().is_none());
+    }
+
+    #[test]
+    fn test_unfragmented_passes_through() {
+        let packet = message(SessionId::new(), 0, 10);
+        let mut reassembler = Reassembler::new();
+        assert_eq!(reassembler.push(packet.clone()).unwrap(), Some(packet.payload));
+    }
+
+    #[test]
+    fn test_content_defined_fragments_survive_insertion() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(7);
+        let data: Vec<u8> = (0..256 * 1024).map(|_| rng.gen()).collect();
+        let mut edited = data.clone();
+        edited.insert(100_000, 0xFF);
+
+        let session_id = SessionId::new();
+        let fingerprints = |fragments: Vec<Packet>| -> Vec<u32> {
+            fragments.iter().map(|f| xxhash_rust::xxh32::xxh32(&f.payload, 0)).collect()
+        };
+
+        // content-defined: only the chunk holding the new byte differs
+        let before = Packet::new(session_id, Intent::DataPush, data.clone());
+        let after = Packet::new(session_id, Intent::DataPush, edited.clone());
+        let old = before.fragment_content_defined(ContentChunking::default()).unwrap();
+        assert!(old.iter().all(|f| f.content_fingerprint().is_some()));
+        let old = fingerprints(old);
+        let new = fingerprints(after.fragment_content_defined(ContentChunking::default()).unwrap());
+        let shared = new.iter().filter(|hash| old.contains(hash)).count();
+        assert!(shared >= new.len() - 2, "{} of {} chunks shared", shared, new.len());
+
+        // fixed size: everything after the insertion shifts
+        let old = fingerprints(before.fragment(8 * 1024).unwrap());
+        let new = fingerprints(after.fragment(8 * 1024).unwrap());
+        let first_changed = 100_000 / (8 * 1024);
+        assert!(new[first_changed..].iter().all(|hash| !old.contains(hash)));
+
+        // and it still reassembles
+        let mut reassembler = Reassembler::new();
+        let mut whole = None;
+        for fragment in after.fragment_content_defined(ContentChunking::default()).unwrap() {
+            whole = reassembler.push(fragment).unwrap();
+        }
+        assert_eq!(whole.unwrap(), edited);
+    }
+
+    #[test]
+    fn test_reassembly_limits() {
+        let limits = ReassemblyLimits {
+            max_fragments: 8,
+            max_group_bytes: 900,
+        };
+        let session_id = SessionId::new();
+
+        // within both limits
+        let mut reassembler = Reassembler::new().with_limits(limits);
+        let mut whole = None;
+        for fragment in message(session_id, 0x01, 800).fragment(100).unwrap() {
+            whole = reassembler.push(fragment).unwrap();
+        }
+        assert_eq!(whole.unwrap().len(), 800);
+
+        // 9 fragments announced
+        let fragments = message(session_id, 0x02, 900).fragment(100).unwrap();
+        assert!(matches!(
+            reassembler.push(fragments[3].clone()),
+            Err(PacketError::TooManyFragments)
+        ));
+        assert_eq!(reassembler.pending(), 0);
+
+        // announced size over the cap
+        let fragments = message(session_id, 0x03, 1200).fragment(200).unwrap();
+        assert!(matches!(reassembler.push(fragments[0].clone()), Err(PacketError::TooLarge)));
+
+        // no size announced, caught once the bytes add up, and what came before is gone
+        assert!(reassembler.push(fragments[1].clone()).unwrap().is_none());
+        for fragment in &fragments[2..5] {
+            assert!(reassembler.push(fragment.clone()).unwrap().is_none());
+        }
+        assert_eq!(reassembler.pending(), 1);
+        assert!(matches!(reassembler.push(fragments[5].clone()), Err(PacketError::TooLarge)));
+        assert_eq!(reassembler.pending(), 0);
+    }
+}
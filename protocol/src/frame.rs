@@ -0,0 +1,140 @@
+//frames: what actually goes into one datagram/send when packets are sent together
+//payload compression leaves the 36 byte header (and the hash) alone, for lots of tiny packets
+//that's most of the bytes. A frame can compress everything, headers included, and since the
+//packets in it usually share a session id, version and nearby timestamps that compresses well
+//
+//frame layout:
+// 1 byte    | kind: 0x00 plain, 0x01 compressed
+// 1 byte    | compression algorithm (compressed frames only)
+// body      | (u32 big-endian length | packet bytes) repeated, compressed as one block if asked
+//
+//the receiver decompresses the body before parsing any packet, each packet is then fully
+//checked like any other. The decompressed body is capped at MAX_PAYLOAD_SIZE
+
+use crate::packet::compression::{compress, decompress, resolve};
+use crate::packet::*;
+
+const FRAME_PLAIN: u8 = 0x00;
+const FRAME_COMPRESSED: u8 = 0x01;
+
+const LENGTH_SIZE: usize = 4;
+
+/// Pack packets into one frame, Compression::None gives a plain frame
+/// Auto is resolved against the body, the frame header carries the codec it picked
+/// ZstdDict isn't available here, frames don't carry a dictionary
+pub fn encode_frame(packets: &[Packet], compression: Compression) -> Result<Vec<u8>, PacketError> {
+    let mut body = Vec::new();
+    for packet in packets {
+        let bytes = packet.to_bytes();
+        let len = u32::try_from(bytes.len()).map_err(|_| PacketError::TooLarge)?;
+        body.extend_from_slice(&len.to_be_bytes());
+        body.extend_from_slice(&bytes);
+    }
+
+    let compression = resolve(compression, &body);
+    if compression == Compression::None {
+        let mut frame = Vec::with_capacity(1 + body.len());
+        frame.push(FRAME_PLAIN);
+        frame.extend_from_slice(&body);
+        return Ok(frame);
+    }
+
+    let compressed = compress(compression, &body, None)?;
+    let mut frame = Vec::with_capacity(2 + compressed.len());
+    frame.push(FRAME_COMPRESSED);
+    frame.push(compression.to_u8());
+    frame.extend_from_slice(&compressed);
+    Ok(frame)
+}
+
+/// Unpack a frame, every packet in it is checked with from_bytes
+pub fn decode_frame(frame: &[u8]) -> Result<Vec<Packet>, PacketError> {
+    let (&kind, rest) = frame.split_first().ok_or(PacketError::InvalidFrame)?;
+    let body = match kind {
+        FRAME_PLAIN => rest.to_vec(),
+        FRAME_COMPRESSED => {
+            let (&algorithm, data) = rest.split_first().ok_or(PacketError::InvalidFrame)?;
+            let compression = Compression::from_u8(algorithm).ok_or(PacketError::InvalidFrame)?;
+            decompress(compression, data, None)?
+        }
+        _ => return Err(PacketError::InvalidFrame),
+    };
+
+    let mut packets = Vec::new();
+    let mut body = body.as_slice();
+    while !body.is_empty() {
+        if body.len() < LENGTH_SIZE {
+            return Err(PacketError::InvalidFrame);
+        }
+        let len = u32::from_be_bytes([body[0], body[1], body[2], body[3]]) as usize;
+        let end = LENGTH_SIZE
+            .checked_add(len)
+            .filter(|&end| end <= body.len())
+            .ok_or(PacketError::InvalidFrame)?;
+        packets.push(Packet::from_bytes(&body[LENGTH_SIZE..end])?);
+        body = &body[end..];
+    }
+    Ok(packets)
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn small_packets() -> Vec<Packet> {
+        let session_id = SessionId::new();
+        (0..50)
+            .map(|i| {
+                let mut packet = Packet::new(session_id, Intent::SearchSuggest, format!("q{}", i).into_bytes());
+                packet.sequence = i;
+                packet.reseal();
+                packet
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_compressed_frame_roundtrip() {
+        let packets = small_packets();
+        let plain = encode_frame(&packets, Compression::None).unwrap();
+        let compressed = encode_frame(&packets, Compression::Zstd).unwrap();
+        assert!(compressed.len() < plain.len());
+
+        for frame in [plain, compressed] {
+            let recovered = decode_frame(&frame).unwrap();
+            assert_eq!(recovered.len(), packets.len());
+            for (recovered, original) in recovered.iter().zip(&packets) {
+                assert_eq!(recovered.to_bytes(), original.to_bytes());
+            }
+        }
+    }
+
+    #[test]
+    fn test_auto_frame_roundtrip() {
+        let packets = small_packets();
+        let frame = encode_frame(&packets, Compression::Auto).unwrap();
+        assert_eq!(frame[0], FRAME_COMPRESSED);
+        assert_eq!(Compression::from_u8(frame[1]), Some(Compression::Zstd));
+
+        let recovered = decode_frame(&frame).unwrap();
+        assert_eq!(recovered.len(), packets.len());
+        for (recovered, original) in recovered.iter().zip(&packets) {
+            assert_eq!(recovered.to_bytes(), original.to_bytes());
+        }
+    }
+
+    #[test]
+    fn test_malformed_frames() {
+        assert!(matches!(decode_frame(&[]), Err(PacketError::InvalidFrame)));
+        assert!(matches!(decode_frame(&[0x7F]), Err(PacketError::InvalidFrame)));
+
+        // length says more than is there
+        let mut frame = encode_frame(&small_packets()[..1], Compression::None).unwrap();
+        frame.pop();
+        assert!(matches!(decode_frame(&frame), Err(PacketError::InvalidFrame)));
+    }
+}
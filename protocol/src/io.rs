@@ -0,0 +1,229 @@
+//reading packets off a byte stream (TCP, a pipe, anything AsyncRead or std::io::Read)
+//packets are self-delimiting: fixed header, then the extended header if the flag says so,
+//then payload_len bytes of payload and the 32 byte hash
+//
+//the hash is computed while the bytes come in: header fields first, then each payload chunk
+//as it's read, so once the hash arrives checking it is a compare, not a second pass over a
+//payload that can be megabytes
+//
+//a stream that ends mid-packet gives UnexpectedEof { read, needed }: bytes of the packet that
+//did arrive, and how long the packet is as far as the reader knew by then (the fixed header
+//length before that arrived). read == 0 is a clean close between packets
+
+use std::io::{ErrorKind, Read};
+
+use subtle::ConstantTimeEq;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::packet::hasher::AnyHasher;
+use crate::packet::*;
+
+// payload is read (and hashed) this much at a time
+const READ_CHUNK: usize = 64 * 1024;
+
+/// Read one packet, checking structure, CRC and hash
+/// A bad header is rejected before any of the payload is read
+pub async fn read_packet<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Packet, PacketError> {
+    let mut buffer = vec![0u8; HEADER_SIZE];
+    read_into(reader, &mut buffer, 0, HEADER_SIZE).await?;
+    let payload_len = Packet::<SESSION_ID_SIZE>::check_fixed_header(&buffer)?;
+    let mut needed = HEADER_SIZE + payload_len + HASH_SIZE;
+
+    // extended header: u16 length, then the entries
+    let mut ext = None;
+    if Flags(buffer[19]).has_extended() {
+        needed += EXT_LENGTH_SIZE;
+        buffer.resize(HEADER_SIZE + EXT_LENGTH_SIZE, 0);
+        read_into(reader, &mut buffer, HEADER_SIZE, needed).await?;
+
+        let entries_len = u16::from_be_bytes([buffer[HEADER_SIZE], buffer[HEADER_SIZE + 1]]) as usize;
+        needed += entries_len;
+        let start = buffer.len();
+        buffer.resize(start + entries_len, 0);
+        read_into(reader, &mut buffer, start, needed).await?;
+        ext = Some(ExtendedHeader::decode(&buffer[start..])?);
+    }
+
+    let mut hasher = Packet::<SESSION_ID_SIZE>::wire_header_hasher(&buffer[..HEADER_SIZE], ext.as_ref())?;
+
+    buffer.reserve(payload_len + HASH_SIZE);
+    let mut remaining = payload_len;
+    while remaining > 0 {
+        let start = buffer.len();
+        let chunk = remaining.min(READ_CHUNK);
+        buffer.resize(start + chunk, 0);
+        read_into(reader, &mut buffer, start, needed).await?;
+        hasher.update(&buffer[start..]);
+        remaining -= chunk;
+    }
+
+    let start = buffer.len();
+    buffer.resize(start + HASH_SIZE, 0);
+    read_into(reader, &mut buffer, start, needed).await?;
+
+    finish(&buffer, hasher)
+}
+
+/// read_packet for blocking readers
+pub fn read_packet_sync<R: Read>(reader: &mut R) -> Result<Packet, PacketError> {
+    let mut buffer = vec![0u8; HEADER_SIZE];
+    read_into_sync(reader, &mut buffer, 0, HEADER_SIZE)?;
+    let payload_len = Packet::<SESSION_ID_SIZE>::check_fixed_header(&buffer)?;
+    let mut needed = HEADER_SIZE + payload_len + HASH_SIZE;
+
+    let mut ext = None;
+    if Flags(buffer[19]).has_extended() {
+        needed += EXT_LENGTH_SIZE;
+        buffer.resize(HEADER_SIZE + EXT_LENGTH_SIZE, 0);
+        read_into_sync(reader, &mut buffer, HEADER_SIZE, needed)?;
+
+        let entries_len = u16::from_be_bytes([buffer[HEADER_SIZE], buffer[HEADER_SIZE + 1]]) as usize;
+        needed += entries_len;
+        let start = buffer.len();
+        buffer.resize(start + entries_len, 0);
+        read_into_sync(reader, &mut buffer, start, needed)?;
+        ext = Some(ExtendedHeader::decode(&buffer[start..])?);
+    }
+
+    let mut hasher = Packet::<SESSION_ID_SIZE>::wire_header_hasher(&buffer[..HEADER_SIZE], ext.as_ref())?;
+
+    buffer.reserve(payload_len + HASH_SIZE);
+    let mut remaining = payload_len;
+    while remaining > 0 {
+        let start = buffer.len();
+        let chunk = remaining.min(READ_CHUNK);
+        buffer.resize(start + chunk, 0);
+        read_into_sync(reader, &mut buffer, start, needed)?;
+        hasher.update(&buffer[start..]);
+        remaining -= chunk;
+    }
+
+    let start = buffer.len();
+    buffer.resize(start + HASH_SIZE, 0);
+    read_into_sync(reader, &mut buffer, start, needed)?;
+
+    finish(&buffer, hasher)
+}
+
+// everything's in, the structure checks are cheap, the hash was done while reading
+fn finish(buffer: &[u8], hasher: AnyHasher) -> Result<Packet, PacketError> {
+    let packet = Packet::from_bytes_unchecked(buffer)?;
+    packet.check_payload_crc()?;
+    if !bool::from(hasher.finalize().ct_eq(&packet.hash)) {
+        return Err(PacketError::InvalidHash);
+    }
+    Ok(packet)
+}
+
+// fill buffer[start..], the bytes before `start` are the part of the packet read already
+async fn read_into<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    buffer: &mut [u8],
+    start: usize,
+    needed: usize,
+) -> Result<(), PacketError> {
+    let mut filled = start;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]).await {
+            Ok(0) => return Err(PacketError::UnexpectedEof { read: filled, needed }),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(PacketError::Io(e.kind())),
+        }
+    }
+    Ok(())
+}
+
+fn read_into_sync<R: Read>(
+    reader: &mut R,
+    buffer: &mut [u8],
+    start: usize,
+    needed: usize,
+) -> Result<(), PacketError> {
+    let mut filled = start;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => return Err(PacketError::UnexpectedEof { read: filled, needed }),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(PacketError::Io(e.kind())),
+        }
+    }
+    Ok(())
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big_packet() -> Packet {
+        let payload: Vec<u8> = (0..4 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        let mut packet = Packet::new(SessionId::new(), Intent::DataPush, payload);
+        packet.set_payload_crc();
+        packet
+    }
+
+    #[tokio::test]
+    async fn test_read_packet_streams_and_verifies() {
+        let packet = big_packet();
+        let bytes = packet.to_bytes();
+
+        let mut stream = &bytes[..];
+        let recovered = read_packet(&mut stream).await.unwrap();
+        assert_eq!(recovered.payload, packet.payload);
+        assert!(recovered.verify());
+        assert!(stream.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_read_packet_tampered_stream() {
+        let mut packet = big_packet();
+        packet.ext.payload_crc = None; // let the hash be the one that catches it
+        packet.reseal();
+        let mut bytes = packet.to_bytes();
+        bytes[HEADER_SIZE + 3 * 1024 * 1024] ^= 0x01;
+
+        let mut stream = &bytes[..];
+        assert!(matches!(read_packet(&mut stream).await, Err(PacketError::InvalidHash)));
+    }
+
+    #[tokio::test]
+    async fn test_read_packet_truncated() {
+        let bytes = Packet::new(SessionId::new(), Intent::Ping, vec![1, 2, 3]).to_bytes();
+        let mut stream = &bytes[..bytes.len() - 1];
+        assert!(matches!(
+            read_packet(&mut stream).await,
+            Err(PacketError::UnexpectedEof { read, needed }) if read == bytes.len() - 1 && needed == bytes.len()
+        ));
+
+        // nothing at all is a clean close
+        let mut empty: &[u8] = &[];
+        assert!(matches!(
+            read_packet(&mut empty).await,
+            Err(PacketError::UnexpectedEof { read: 0, needed: HEADER_SIZE })
+        ));
+    }
+
+    #[test]
+    fn test_read_packet_sync() {
+        let packet = big_packet();
+        let bytes = packet.to_bytes();
+
+        let mut cursor = std::io::Cursor::new(&bytes);
+        let recovered = read_packet_sync(&mut cursor).unwrap();
+        assert_eq!(recovered.payload, packet.payload);
+        assert!(recovered.verify());
+
+        // cut off inside the payload, with an extended header in front (the CRC)
+        let cut = HEADER_SIZE + 1024 * 1024;
+        let mut cursor = std::io::Cursor::new(&bytes[..cut]);
+        assert!(matches!(
+            read_packet_sync(&mut cursor),
+            Err(PacketError::UnexpectedEof { read, needed }) if read == cut && needed == bytes.len()
+        ));
+    }
+}
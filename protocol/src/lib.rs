@@ -0,0 +1,69 @@
+//FDP - the protocol side of the project
+//packet: the on-the-wire format, types and (de)serialization
+pub mod packet;
+
+//payload: typed payload formats for specific intents
+pub mod payload;
+
+//session: what a session agreed on, checked against the packets arriving on it
+pub mod session;
+
+//clock: where now_ms comes from, the system clock or a mock for tests
+pub mod clock;
+
+//multiplexer: routes packets from one socket to per-session channels
+pub mod multiplexer;
+
+//filter: which intents a node accepts
+pub mod filter;
+
+//queue: outgoing packets ordered by priority
+pub mod queue;
+
+//router: hands packets to a handler per intent
+pub mod router;
+
+//cookie: stateless handshake cookies, no session state until the client proves its address
+pub mod cookie;
+
+//migration: moves a live session to a new address, keeping its sequence and key
+pub mod migration;
+
+//retransmit: sent-but-unacked packets, resent on Nack
+pub mod retransmit;
+
+//monitor: treats a sequence that goes backwards within a key epoch as an attack, flags timestamps jumping back
+pub mod monitor;
+
+//flow: sender side receive-window tracking
+pub mod flow;
+
+//reaper: evicts sessions that went idle without a Close
+pub mod reaper;
+
+//reorder: receiver side, hands packets on in sequence order and drops duplicates
+pub mod reorder;
+
+//batcher: folds many acks into one range-encoded Ack
+pub mod batcher;
+
+//cancel: tracks in-flight requests so a Cancel can stop them
+pub mod cancel;
+
+//fragment: splitting big messages into packets and reassembling them
+pub mod fragment;
+
+//frame: several packets in one send, optionally compressed as a whole
+pub mod frame;
+
+//io: reading packets from async byte streams
+pub mod io;
+
+//capture: iterating packets stored back to back in a buffer (capture files)
+pub mod capture;
+
+//transport: UDP, TCP and in-memory carriers behind one trait
+pub mod transport;
+
+//connection: one session over a transport, handshake, outgoing queue and checked receive
+pub mod connection;
@@ -0,0 +1,220 @@
+//connection migration, for clients that change networks (wifi -> mobile) mid-session
+//
+//the client sends a HandshakeInit for its existing session id from the new address, carrying
+//a proof in the extended header:
+// HMAC-SHA256(session key, "fdp migrate" | session id | sequence)
+//only someone holding the session key can make one, and the sequence ties it to one point
+//in the session so a captured migration can't be replayed later
+//
+//the server moves the session's binding to the new address and keeps everything else: the
+//key, and the sequence so it carries on where it was (no new handshake, no sequence reset)
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::packet::*;
+
+const PROOF_CONTEXT: &[u8] = b"fdp migrate";
+
+/// Proof that whoever sends `sequence` on `session_id` holds the session key
+pub fn migration_proof(key: &[u8; 32], session_id: &SessionId, sequence: Sequence) -> [u8; MIGRATION_PROOF_SIZE] {
+    proof_mac(key, session_id, sequence).finalize().into_bytes().into()
+}
+
+fn proof_mac(key: &[u8; 32], session_id: &SessionId, sequence: Sequence) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes any key size");
+    mac.update(PROOF_CONTEXT);
+    mac.update(session_id.as_bytes());
+    mac.update(&sequence.to_be_bytes());
+    mac
+}
+
+impl Packet {
+    /// HandshakeInit moving `session_id` to wherever this packet is sent from
+    /// `sequence` is the client's next sequence, the session keeps counting from it
+    pub fn migration_init(session_id: SessionId, sequence: Sequence, key: &[u8; 32]) -> Packet {
+        let mut packet = Packet::new(session_id, Intent::HandshakeInit, vec![]);
+        packet.priority = Priority::for_intent(Intent::HandshakeInit);
+        packet.sequence = sequence;
+        packet.ext.migration_proof = Some(migration_proof(key, &session_id, sequence));
+        packet.reseal();
+        packet
+    }
+}
+
+/// What the server keeps per established session
+#[derive(Debug, Clone)]
+pub struct SessionBinding {
+    addr: SocketAddr,
+    key: [u8; 32],
+    last_sequence: Option<Sequence>,
+}
+
+impl SessionBinding {
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn key(&self) -> &[u8; 32] {
+        &self.key
+    }
+
+    /// Highest sequence received on the session, None before the first packet
+    pub fn last_sequence(&self) -> Option<Sequence> {
+        self.last_sequence
+    }
+}
+
+/// Established sessions by id, and the address each one currently lives at
+#[derive(Debug, Default)]
+pub struct SessionBindings {
+    sessions: HashMap<SessionId, SessionBinding>,
+}
+
+impl SessionBindings {
+    pub fn new() -> Self {
+        SessionBindings::default()
+    }
+
+    /// Add a session once its handshake is done
+    pub fn bind(&mut self, session_id: SessionId, addr: SocketAddr, key: [u8; 32]) {
+        self.sessions.insert(
+            session_id,
+            SessionBinding {
+                addr,
+                key,
+                last_sequence: None,
+            },
+        );
+    }
+
+    pub fn get(&self, session_id: &SessionId) -> Option<&SessionBinding> {
+        self.sessions.get(session_id)
+    }
+
+    /// Note a packet received on its session, false when the session isn't bound
+    pub fn record(&mut self, packet: &Packet) -> bool {
+        match self.sessions.get_mut(&packet.session_id) {
+            Some(binding) => {
+                let newer = binding.last_sequence.is_none_or(|last| seq_cmp(packet.sequence, last).is_gt());
+                if newer {
+                    binding.last_sequence = Some(packet.sequence);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Handle a migration HandshakeInit that came in from `addr`
+    /// Returns the address the session was bound to before
+    /// Nothing changes unless the proof checks out and the sequence is past the last one seen
+    pub fn migrate(&mut self, packet: &Packet, addr: SocketAddr) -> Result<SocketAddr, PacketError> {
+        if packet.intent != Intent::HandshakeInit {
+            return Err(PacketError::UnexpectedIntent(packet.intent));
+        }
+        let proof = packet.ext.migration_proof.ok_or(PacketError::InvalidMigration)?;
+        let binding = self
+            .sessions
+            .get_mut(&packet.session_id)
+            .ok_or(PacketError::InvalidMigration)?;
+
+        // verify_slice compares in constant time
+        proof_mac(&binding.key, &packet.session_id, packet.sequence)
+            .verify_slice(&proof)
+            .map_err(|_| PacketError::InvalidMigration)?;
+
+        // a proof for a sequence already seen is a replay
+        if binding.last_sequence.is_some_and(|last| seq_cmp(packet.sequence, last).is_le()) {
+            return Err(PacketError::InvalidMigration);
+        }
+
+        binding.last_sequence = Some(packet.sequence);
+        Ok(std::mem::replace(&mut binding.addr, addr))
+    }
+
+    /// Drop a session once it's closed
+    pub fn unbind(&mut self, session_id: &SessionId) -> Option<SessionBinding> {
+        self.sessions.remove(session_id)
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [0x42; 32];
+
+    fn wifi() -> SocketAddr {
+        "192.0.2.7:40000".parse().unwrap()
+    }
+
+    fn mobile() -> SocketAddr {
+        "198.51.100.9:51000".parse().unwrap()
+    }
+
+    fn data(session_id: SessionId, sequence: Sequence) -> Packet {
+        let mut packet = Packet::new(session_id, Intent::DataPush, b"x".to_vec());
+        packet.sequence = sequence;
+        packet.reseal();
+        packet
+    }
+
+    #[test]
+    fn test_migration_keeps_sequence_and_key() {
+        let session_id = SessionId::new();
+        let mut bindings = SessionBindings::new();
+        bindings.bind(session_id, wifi(), KEY);
+        for sequence in 1..=5 {
+            assert!(bindings.record(&data(session_id, sequence)));
+        }
+
+        // through the wire, the proof lives in the extended header
+        let init = Packet::from_bytes(&Packet::migration_init(session_id, 6, &KEY).to_bytes()).unwrap();
+        assert_eq!(bindings.migrate(&init, mobile()).unwrap(), wifi());
+
+        let binding = bindings.get(&session_id).unwrap();
+        assert_eq!(binding.addr(), mobile());
+        assert_eq!(binding.key(), &KEY);
+        assert_eq!(binding.last_sequence(), Some(6));
+
+        // and the session just carries on
+        bindings.record(&data(session_id, 7));
+        assert_eq!(bindings.get(&session_id).unwrap().last_sequence(), Some(7));
+    }
+
+    #[test]
+    fn test_invalid_migration_rejected() {
+        let session_id = SessionId::new();
+        let mut bindings = SessionBindings::new();
+        bindings.bind(session_id, wifi(), KEY);
+        bindings.record(&data(session_id, 5));
+
+        // someone without the key
+        let forged = Packet::migration_init(session_id, 6, &[0x13; 32]);
+        assert!(matches!(bindings.migrate(&forged, mobile()), Err(PacketError::InvalidMigration)));
+
+        // no proof at all
+        let bare = Packet::new(session_id, Intent::HandshakeInit, vec![]);
+        assert!(matches!(bindings.migrate(&bare, mobile()), Err(PacketError::InvalidMigration)));
+
+        // a genuine proof for a sequence the session is already past
+        let replayed = Packet::migration_init(session_id, 5, &KEY);
+        assert!(matches!(bindings.migrate(&replayed, mobile()), Err(PacketError::InvalidMigration)));
+
+        // a session nobody bound
+        let unknown = Packet::migration_init(SessionId::new(), 6, &KEY);
+        assert!(matches!(bindings.migrate(&unknown, mobile()), Err(PacketError::InvalidMigration)));
+
+        let binding = bindings.get(&session_id).unwrap();
+        assert_eq!(binding.addr(), wifi());
+        assert_eq!(binding.last_sequence(), Some(5));
+    }
+}
@@ -0,0 +1,188 @@
+//sequence monitoring for security-conscious receivers
+//dedup quietly drops a repeated packet. Under encryption a repeated (or backwards) sequence in
+//the same key epoch is worse than a duplicate: the nonce would repeat, or someone is replaying
+//captured traffic. This monitor treats it as an attack and tells the caller to end the session
+//
+//an epoch is whatever the caller uses to tell keys apart (a counter bumped on every rekey),
+//a new epoch starts the sequence over
+//
+//TimestampMonotonicChecker does the same for timestamps, looser: packets do get reordered on
+//the way, so a timestamp a little behind the newest one is fine. Falling more than the
+//allowed skew behind it is a spoofed packet or a sender whose clock jumped
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::packet::*;
+
+/// What to do after observing a packet's sequence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SequenceVerdict {
+    Ok,
+    /// Sequence didn't move forward, tear the session down
+    Terminate { last: Sequence, got: Sequence },
+}
+
+#[derive(Debug, Default)]
+pub struct SecuritySequenceMonitor {
+    // highest sequence seen per session, per epoch
+    last: HashMap<SessionId, BTreeMap<u32, Sequence>>,
+}
+
+impl SecuritySequenceMonitor {
+    pub fn new() -> Self {
+        SecuritySequenceMonitor::default()
+    }
+
+    /// Record a sequence, anything not strictly above the last one in the epoch is an alarm
+    pub fn observe(&mut self, session_id: SessionId, epoch: u32, sequence: Sequence) -> SequenceVerdict {
+        let epochs = self.last.entry(session_id).or_default();
+        // epochs before the one just seen are done with, their keys are gone
+        // (one in-flight packet from the previous epoch still gets checked against it)
+        let keep_from = epoch.saturating_sub(1);
+        while epochs.first_key_value().is_some_and(|(&seen, _)| seen < keep_from) {
+            epochs.pop_first();
+        }
+
+        match epochs.get_mut(&epoch) {
+            Some(last) if seq_cmp(sequence, *last).is_le() => SequenceVerdict::Terminate { last: *last, got: sequence },
+            Some(last) => {
+                *last = sequence;
+                SequenceVerdict::Ok
+            }
+            None => {
+                epochs.insert(epoch, sequence);
+                SequenceVerdict::Ok
+            }
+        }
+    }
+
+    /// observe() for a received packet
+    pub fn check(&mut self, packet: &Packet, epoch: u32) -> SequenceVerdict {
+        self.observe(packet.session_id, epoch, packet.sequence)
+    }
+
+    /// Drop a session's state once it's closed (or was terminated)
+    pub fn forget(&mut self, session_id: &SessionId) {
+        self.last.remove(session_id);
+    }
+}
+
+// ============================================================================
+// TIMESTAMPS
+// ============================================================================
+
+#[derive(Debug, Default)]
+pub struct TimestampMonotonicChecker {
+    skew_ms: u64,
+    // newest timestamp seen per session, in ms
+    newest: HashMap<SessionId, u64>,
+}
+
+impl TimestampMonotonicChecker {
+    /// Packets up to `skew_ms` older than the newest one of their session are let through
+    pub fn new(skew_ms: u64) -> Self {
+        TimestampMonotonicChecker {
+            skew_ms,
+            newest: HashMap::new(),
+        }
+    }
+
+    /// Check a received packet's timestamp against the newest of its session
+    pub fn check(&mut self, packet: &Packet) -> Result<(), PacketError> {
+        let timestamp = packet.timestamp_ms();
+        let newest = self.newest.entry(packet.session_id).or_insert(timestamp);
+        if timestamp.saturating_add(self.skew_ms) < *newest {
+            return Err(PacketError::TimestampRegression {
+                newest: *newest,
+                got: timestamp,
+            });
+        }
+        *newest = (*newest).max(timestamp);
+        Ok(())
+    }
+
+    /// Drop a session's state once it's closed
+    pub fn forget(&mut self, session_id: &SessionId) {
+        self.newest.remove(session_id);
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_increasing_sequence_is_fine() {
+        let mut monitor = SecuritySequenceMonitor::new();
+        let session_id = SessionId::new();
+        for sequence in [1, 2, 5, 9] {
+            assert_eq!(monitor.observe(session_id, 0, sequence), SequenceVerdict::Ok);
+        }
+
+        // a rekey starts over
+        assert_eq!(monitor.observe(session_id, 1, 1), SequenceVerdict::Ok);
+    }
+
+    #[test]
+    fn test_wraparound_is_not_a_regression() {
+        let mut monitor = SecuritySequenceMonitor::new();
+        let session_id = SessionId::new();
+        for sequence in [u32::MAX - 1, u32::MAX, 0, 1] {
+            assert_eq!(monitor.observe(session_id, 0, sequence), SequenceVerdict::Ok);
+        }
+        assert_eq!(
+            monitor.observe(session_id, 0, u32::MAX),
+            SequenceVerdict::Terminate { last: 1, got: u32::MAX }
+        );
+    }
+
+    #[test]
+    fn test_repeat_raises_alarm() {
+        let mut monitor = SecuritySequenceMonitor::new();
+        let session_id = SessionId::new();
+        monitor.observe(session_id, 0, 7);
+
+        assert_eq!(
+            monitor.observe(session_id, 0, 7),
+            SequenceVerdict::Terminate { last: 7, got: 7 }
+        );
+        assert_eq!(
+            monitor.observe(session_id, 0, 3),
+            SequenceVerdict::Terminate { last: 7, got: 3 }
+        );
+
+        // other sessions aren't affected
+        assert_eq!(monitor.observe(SessionId::from_bytes([2; 16]), 0, 7), SequenceVerdict::Ok);
+    }
+
+    fn stamped(session_id: SessionId, timestamp: u64) -> Packet {
+        let mut packet = Packet::new(session_id, Intent::DataPush, vec![]);
+        packet.timestamp = timestamp;
+        packet.reseal();
+        packet
+    }
+
+    #[test]
+    fn test_timestamp_regression() {
+        let mut checker = TimestampMonotonicChecker::new(500);
+        let session_id = SessionId::new();
+        for timestamp in [1_000, 1_200, 2_000] {
+            assert!(checker.check(&stamped(session_id, timestamp)).is_ok());
+        }
+
+        // reordered a bit on the way
+        assert!(checker.check(&stamped(session_id, 1_600)).is_ok());
+
+        assert!(matches!(
+            checker.check(&stamped(session_id, 1_000)),
+            Err(PacketError::TimestampRegression { newest: 2_000, got: 1_000 })
+        ));
+
+        // another session has its own clock
+        assert!(checker.check(&stamped(SessionId::new(), 1_000)).is_ok());
+    }
+}
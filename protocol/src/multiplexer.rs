@@ -0,0 +1,227 @@
+//fans packets from one socket out to one channel per session
+//a server reading a single socket gets packets from everyone mixed together, the multiplexer
+//sorts them so each session handler only ever sees its own packets
+//
+//new session -> new channel, announced with SessionEvent::Opened (the handler takes the receiver from there)
+//Close intent -> delivered to the handler, then the channel is dropped so the handler sees the end
+//full channel -> that session's packet is dropped (Backpressure), routing never waits on a handler
+//an IntentFilter (if set) is checked before anything is routed
+
+use std::collections::HashMap;
+
+use tokio::sync::mpsc;
+
+use crate::filter::IntentFilter;
+use crate::packet::*;
+
+/// Things the server loop wants to hear about
+#[derive(Debug)]
+pub enum SessionEvent {
+    /// First packet from a session we didn't know, its packets arrive on `packets`
+    Opened {
+        session_id: SessionId,
+        packets: mpsc::Receiver<Packet>,
+    },
+    /// Session sent Close (or its handler went away), no more packets for it
+    Closed(SessionId),
+}
+
+pub struct Multiplexer {
+    sessions: HashMap<SessionId, mpsc::Sender<Packet>>,
+    events: mpsc::UnboundedSender<SessionEvent>,
+    channel_capacity: usize, // per session, a slow handler only loses its own packets
+    filter: IntentFilter,
+}
+
+impl Multiplexer {
+    /// Create a multiplexer and the event stream the server listens on
+    pub fn new(channel_capacity: usize) -> (Self, mpsc::UnboundedReceiver<SessionEvent>) {
+        let (events, event_rx) = mpsc::unbounded_channel();
+        let mux = Multiplexer {
+            sessions: HashMap::new(),
+            events,
+            channel_capacity,
+            filter: IntentFilter::allow_all(),
+        };
+        (mux, event_rx)
+    }
+
+    /// Only route intents the filter allows
+    pub fn with_filter(mut self, filter: IntentFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Number of sessions with an open channel
+    pub fn session_count(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// Hand one packet to its session's channel, opening the channel if needed
+    /// Filtered-out intents are rejected before they can open a session
+    /// Never waits: a session whose channel is full gets Backpressure and the packet is dropped,
+    /// everyone else keeps getting theirs
+    pub async fn route(&mut self, packet: Packet) -> Result<(), PacketError> {
+        self.filter.check(&packet)?;
+
+        let session_id = packet.session_id;
+        let closing = packet.intent == Intent::Close;
+
+        let sender = match self.sessions.get(&session_id) {
+            Some(sender) => sender.clone(),
+            None => {
+                let (sender, packets) = mpsc::channel(self.channel_capacity);
+                // nobody listening for events just means nobody gets the receiver
+                let _ = self.events.send(SessionEvent::Opened { session_id, packets });
+                self.sessions.insert(session_id, sender.clone());
+                sender
+            }
+        };
+
+        let (result, gone) = match sender.try_send(packet) {
+            Ok(()) => (Ok(()), false),
+            Err(mpsc::error::TrySendError::Full(_)) => (Err(PacketError::Backpressure(session_id)), false),
+            // handler dropped its receiver -> treat it like a close
+            Err(mpsc::error::TrySendError::Closed(_)) => (Ok(()), true),
+        };
+
+        // a Close that didn't fit still ends the session, the handler sees the channel end
+        if closing || gone {
+            self.sessions.remove(&session_id);
+            let _ = self.events.send(SessionEvent::Closed(session_id));
+        }
+        result
+    }
+
+    /// Route everything from `incoming` until it ends
+    pub async fn run(mut self, mut incoming: mpsc::Receiver<Packet>) {
+        while let Some(packet) = incoming.recv().await {
+            // a rejected packet only concerns its sender, keep routing the rest
+            let _ = self.route(packet).await;
+        }
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn expect_opened(
+        events: &mut mpsc::UnboundedReceiver<SessionEvent>,
+    ) -> (SessionId, mpsc::Receiver<Packet>) {
+        match events.recv().await {
+            Some(SessionEvent::Opened { session_id, packets }) => (session_id, packets),
+            other => panic!("expected Opened, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_routes_packets_to_their_own_session() {
+        let (mut mux, mut events) = Multiplexer::new(16);
+        let alice = SessionId::from_bytes([1; 16]);
+        let bob = SessionId::from_bytes([2; 16]);
+
+        // interleaved on the "socket"
+        mux.route(Packet::new(alice, Intent::Search, b"a1".to_vec())).await.unwrap();
+        mux.route(Packet::new(bob, Intent::Search, b"b1".to_vec())).await.unwrap();
+        mux.route(Packet::new(alice, Intent::Search, b"a2".to_vec())).await.unwrap();
+        mux.route(Packet::new(bob, Intent::Search, b"b2".to_vec())).await.unwrap();
+
+        let (first, mut alice_rx) = expect_opened(&mut events).await;
+        let (second, mut bob_rx) = expect_opened(&mut events).await;
+        assert_eq!(first, alice);
+        assert_eq!(second, bob);
+
+        for expected in [b"a1", b"a2"] {
+            let packet = alice_rx.recv().await.unwrap();
+            assert_eq!(packet.session_id, alice);
+            assert_eq!(packet.payload, expected.to_vec());
+        }
+        for expected in [b"b1", b"b2"] {
+            let packet = bob_rx.recv().await.unwrap();
+            assert_eq!(packet.session_id, bob);
+            assert_eq!(packet.payload, expected.to_vec());
+        }
+
+        assert!(alice_rx.try_recv().is_err());
+        assert!(bob_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_close_drops_channel() {
+        let (mut mux, mut events) = Multiplexer::new(16);
+        let session = SessionId::from_bytes([3; 16]);
+
+        mux.route(Packet::new(session, Intent::Ping, vec![])).await.unwrap();
+        mux.route(Packet::new(session, Intent::Close, vec![])).await.unwrap();
+        assert_eq!(mux.session_count(), 0);
+
+        let (_, mut rx) = expect_opened(&mut events).await;
+        assert_eq!(rx.recv().await.unwrap().intent, Intent::Ping);
+        assert_eq!(rx.recv().await.unwrap().intent, Intent::Close);
+        assert!(rx.recv().await.is_none()); // channel closed
+
+        assert!(matches!(events.recv().await, Some(SessionEvent::Closed(id)) if id == session));
+    }
+
+    #[tokio::test]
+    async fn test_filter_rejects_before_dispatch() {
+        let (mux, mut events) = Multiplexer::new(4);
+        let mut mux = mux.with_filter(IntentFilter::allow_all().deny(Intent::RankingUpdate));
+        let session = SessionId::from_bytes([5; 16]);
+
+        let result = mux.route(Packet::new(session, Intent::RankingUpdate, vec![])).await;
+        assert!(matches!(result, Err(PacketError::IntentNotAllowed(Intent::RankingUpdate))));
+
+        // never got as far as opening a session
+        assert_eq!(mux.session_count(), 0);
+        assert!(events.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_full_session_does_not_block_others() {
+        let (mut mux, mut events) = Multiplexer::new(1);
+        let stuck = SessionId::from_bytes([6; 16]);
+        let moving = SessionId::from_bytes([7; 16]);
+
+        // nobody reads `stuck`, its one slot fills up
+        mux.route(Packet::new(stuck, Intent::Search, b"s1".to_vec())).await.unwrap();
+        let result = mux.route(Packet::new(stuck, Intent::Search, b"s2".to_vec())).await;
+        assert!(matches!(result, Err(PacketError::Backpressure(id)) if id == stuck));
+
+        let (_, mut stuck_rx) = expect_opened(&mut events).await;
+
+        // a handler that keeps up gets everything
+        mux.route(Packet::new(moving, Intent::Search, b"m0".to_vec())).await.unwrap();
+        let (_, mut moving_rx) = expect_opened(&mut events).await;
+        assert_eq!(moving_rx.recv().await.unwrap().payload, b"m0".to_vec());
+        for payload in [b"m1", b"m2", b"m3"] {
+            mux.route(Packet::new(moving, Intent::Search, payload.to_vec())).await.unwrap();
+            assert_eq!(moving_rx.recv().await.unwrap().payload, payload.to_vec());
+        }
+
+        // still open, the dropped packet is just gone
+        assert_eq!(mux.session_count(), 2);
+        assert_eq!(stuck_rx.recv().await.unwrap().payload, b"s1".to_vec());
+        assert!(stuck_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_run_consumes_stream() {
+        let (mux, mut events) = Multiplexer::new(4);
+        let (tx, rx) = mpsc::channel(4);
+        let session = SessionId::from_bytes([4; 16]);
+
+        tx.send(Packet::new(session, Intent::Search, b"q".to_vec())).await.unwrap();
+        drop(tx);
+        mux.run(rx).await;
+
+        let (id, mut packets) = expect_opened(&mut events).await;
+        assert_eq!(id, session);
+        assert_eq!(packets.recv().await.unwrap().payload, b"q".to_vec());
+    }
+}
@@ -0,0 +1,312 @@
+//builder for packets when Packet::new's defaults aren't what you want
+//everything has a sane default so you only set what you care about, build() seals the hash at the end
+//
+//EncryptionLevel::None is refused unless insecure_allow_plaintext() was called, so a flags()
+//override can't quietly send a payload in the clear. builds without the encryption feature
+//have no cipher to pick, plaintext is all they can do and is allowed from the start
+//
+//the other way round, flags that say ChaCha20/AES-256 need a key from encrypt(), otherwise
+//build() would label a plaintext payload as encrypted and send it like that
+
+use super::encryption::KEY_SIZE;
+use super::extended::CORRELATION_ID_SIZE;
+use super::packet::*;
+use super::types::*;
+
+#[derive(Debug, Clone)]
+pub struct PacketBuilder {
+    session_id: SessionId,
+    intent: Intent,
+    payload: Vec<u8>,
+    priority: Option<Priority>, // None -> Priority::for_intent
+    flags: Flags,
+    sequence: Sequence,
+    payload_crc: bool,
+    content_fingerprint: bool,
+    pad_to: Option<usize>,
+    allow_plaintext: bool,
+    correlation_id: Option<[u8; CORRELATION_ID_SIZE]>,
+    ttl_ms: Option<u64>, // None -> Intent::default_ttl
+    encryption: Option<([u8; KEY_SIZE], u64)>, // key and nonce counter value
+}
+
+impl PacketBuilder {
+    pub fn new(session_id: SessionId, intent: Intent) -> Self {
+        // same flags Packet::new starts with
+        let flags = Flags::packet_default();
+        PacketBuilder {
+            session_id,
+            intent,
+            payload: Vec::new(),
+            priority: None,
+            flags,
+            sequence: 0,
+            payload_crc: false,
+            content_fingerprint: false,
+            pad_to: None,
+            allow_plaintext: !cfg!(feature = "encryption"),
+            correlation_id: None,
+            ttl_ms: None,
+            encryption: None,
+        }
+    }
+
+    pub fn payload(mut self, payload: Vec<u8>) -> Self {
+        self.payload = payload;
+        self
+    }
+
+    /// Override the priority, otherwise it comes from the intent
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    /// priority() for a qualitative urgency (see Priority::from_class)
+    pub fn priority_class(mut self, class: PriorityClass) -> Self {
+        self.priority = Some(Priority::from_class(class));
+        self
+    }
+
+    pub fn flags(mut self, flags: Flags) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    pub fn sequence(mut self, sequence: Sequence) -> Self {
+        self.sequence = sequence;
+        self
+    }
+
+    /// Attach a CRC32 of the payload so receivers can catch corruption early
+    pub fn payload_crc(mut self) -> Self {
+        self.payload_crc = true;
+        self
+    }
+
+    /// Attach a content fingerprint for cache lookups (see Packet::content_fingerprint)
+    pub fn content_fingerprint(mut self) -> Self {
+        self.content_fingerprint = true;
+        self
+    }
+
+    /// Pad the payload up to a multiple of `block_size` (see Packet::pad)
+    pub fn pad_to(mut self, block_size: usize) -> Self {
+        self.pad_to = Some(block_size);
+        self
+    }
+
+    /// Tracing id, carried over to responses (see Packet::correlation_id)
+    pub fn correlation_id(mut self, id: [u8; CORRELATION_ID_SIZE]) -> Self {
+        self.correlation_id = Some(id);
+        self
+    }
+
+    /// Deliver within `ttl_ms` of creation or not at all, otherwise the intent's default_ttl
+    /// Goes out as the packet's deadline (see Packet::set_deadline)
+    pub fn ttl(mut self, ttl_ms: u64) -> Self {
+        self.ttl_ms = Some(ttl_ms);
+        self
+    }
+
+    /// Encrypt the payload with `key`, at the level the flags ask for
+    /// `nonce_counter` is the next value from the key's NonceCounter, it goes in the extended
+    /// header so the receiver can rebuild the nonce (see Packet::encrypt_payload_counted)
+    pub fn encrypt(mut self, key: [u8; KEY_SIZE], nonce_counter: u64) -> Self {
+        self.encryption = Some((key, nonce_counter));
+        self
+    }
+
+    /// Permit EncryptionLevel::None, for tests on localhost
+    pub fn insecure_allow_plaintext(mut self) -> Self {
+        self.allow_plaintext = true;
+        self
+    }
+
+    /// Put the packet together and compute its hash
+    /// Fails on oversized payloads, unrequested plaintext, encryption flags without a key and
+    /// anything validate_semantics rejects
+    pub fn build(self) -> Result<Packet, PacketError> {
+        if self.payload.len() > MAX_PAYLOAD_SIZE {
+            return Err(PacketError::TooLarge);
+        }
+        match self.flags.encryption() {
+            EncryptionLevel::None if !self.allow_plaintext => return Err(PacketError::PlaintextNotAllowed),
+            EncryptionLevel::None => {}
+            _ if self.encryption.is_none() => return Err(PacketError::EncryptionRequired),
+            _ => {}
+        }
+
+        let mut packet = Packet::new(self.session_id, self.intent, self.payload);
+        packet.priority = self
+            .priority
+            .unwrap_or_else(|| Priority::for_intent(self.intent));
+        packet.flags = self.flags;
+        packet.sequence = self.sequence;
+        packet.validate_semantics()?;
+        if let Some(block_size) = self.pad_to {
+            packet.pad(block_size)?;
+        }
+        // padding goes under the encryption, that's what hides the length
+        if let Some((key, nonce_counter)) = self.encryption {
+            packet.ext.nonce_counter = Some(nonce_counter);
+            packet.encrypt_payload(self.flags.encryption(), &key)?;
+        }
+        // CRC covers the payload as sent, padding and encryption included
+        if self.payload_crc {
+            packet.ext.payload_crc = Some(crc32fast::hash(&packet.payload));
+        }
+        packet.ext.correlation_id = self.correlation_id;
+        if let Some(ttl_ms) = self.ttl_ms.or(self.intent.default_ttl()) {
+            packet.ext.deadline_ms = Some(packet.timestamp_ms().saturating_add(ttl_ms));
+        }
+        if self.content_fingerprint {
+            packet.set_content_fingerprint();
+        }
+        packet.reseal();
+        Ok(packet)
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; KEY_SIZE] = [0x11; KEY_SIZE];
+
+    #[test]
+    fn test_builder_defaults_priority_from_intent() {
+        let packet = PacketBuilder::new(SessionId::new(), Intent::Error)
+            .payload(b"boom".to_vec())
+            .encrypt(KEY, 0)
+            .build()
+            .unwrap();
+
+        assert_eq!(packet.priority, Priority::CRITICAL);
+        assert!(packet.verify());
+    }
+
+    #[test]
+    fn test_builder_overrides() {
+        let packet = PacketBuilder::new(SessionId::new(), Intent::Search)
+            .priority(Priority::LOWEST)
+            .sequence(42)
+            .encrypt(KEY, 1)
+            .build()
+            .unwrap();
+
+        assert_eq!(packet.priority, Priority::LOWEST);
+        assert_eq!(packet.sequence, 42);
+
+        let interactive = PacketBuilder::new(SessionId::new(), Intent::DataPush)
+            .priority_class(PriorityClass::Interactive)
+            .encrypt(KEY, 2)
+            .build()
+            .unwrap();
+        assert_eq!(interactive.priority, Priority::HIGH);
+
+        // Hash has to cover the overridden fields
+        let recovered = Packet::from_bytes(&packet.to_bytes()).unwrap();
+        assert_eq!(recovered.sequence, 42);
+    }
+
+    #[test]
+    fn test_builder_applies_intent_ttl() {
+        let suggest = PacketBuilder::new(SessionId::new(), Intent::SearchSuggest)
+            .encrypt(KEY, 0)
+            .build()
+            .unwrap();
+        let ttl = Intent::SearchSuggest.default_ttl().unwrap();
+        assert!(ttl <= 1_000);
+        assert_eq!(suggest.deadline_ms(), Some(suggest.timestamp_ms() + ttl));
+        let sent_at = suggest.timestamp_ms();
+        assert!(!suggest.is_expired(u64::MAX, sent_at + ttl));
+        assert!(suggest.is_expired(u64::MAX, sent_at + ttl + 1));
+
+        let push = PacketBuilder::new(SessionId::new(), Intent::DataPush)
+            .encrypt(KEY, 1)
+            .build()
+            .unwrap();
+        assert_eq!(Intent::DataPush.default_ttl(), None);
+        assert_eq!(push.deadline_ms(), None);
+        assert!(!push.is_expired(u64::MAX, push.timestamp_ms() + 3_600_000));
+
+        let patient = PacketBuilder::new(SessionId::new(), Intent::SearchSuggest)
+            .ttl(10_000)
+            .encrypt(KEY, 2)
+            .build()
+            .unwrap();
+        assert_eq!(patient.deadline_ms(), Some(patient.timestamp_ms() + 10_000));
+        assert!(Packet::from_bytes(&patient.to_bytes()).unwrap().deadline_ms().is_some());
+    }
+
+    #[test]
+    fn test_builder_rejects_ack_required_response() {
+        let mut flags = Flags::packet_default();
+        flags.set_ack_required(true);
+
+        let pong = PacketBuilder::new(SessionId::new(), Intent::Pong)
+            .flags(flags)
+            .encrypt(KEY, 0)
+            .build();
+        assert!(matches!(pong, Err(PacketError::SemanticMismatch(_))));
+
+        // requests can ask for acks just fine
+        let search = PacketBuilder::new(SessionId::new(), Intent::Search)
+            .flags(flags)
+            .encrypt(KEY, 1)
+            .build()
+            .unwrap();
+        assert!(search.flags.ack_required());
+    }
+
+    #[test]
+    fn test_builder_rejects_oversized_payload() {
+        let result = PacketBuilder::new(SessionId::new(), Intent::DataPush)
+            .payload(vec![0u8; MAX_PAYLOAD_SIZE + 1])
+            .build();
+        assert!(matches!(result, Err(PacketError::TooLarge)));
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_builder_refuses_plaintext_without_opt_in() {
+        let plaintext = PacketBuilder::new(SessionId::new(), Intent::Ping).flags(Flags::new());
+        assert!(matches!(
+            plaintext.clone().build(),
+            Err(PacketError::PlaintextNotAllowed)
+        ));
+
+        let packet = plaintext.insecure_allow_plaintext().build().unwrap();
+        assert_eq!(packet.flags.encryption(), EncryptionLevel::None);
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_default_build_never_sends_plaintext() {
+        let secret = b"nothing on the wire should read like this".to_vec();
+
+        // the default flags say ChaCha20, with no key that can't be honoured
+        let unkeyed = PacketBuilder::new(SessionId::new(), Intent::DataPush)
+            .payload(secret.clone())
+            .build();
+        assert!(matches!(unkeyed, Err(PacketError::EncryptionRequired)));
+
+        let packet = PacketBuilder::new(SessionId::new(), Intent::DataPush)
+            .payload(secret.clone())
+            .encrypt(KEY, 7)
+            .build()
+            .unwrap();
+        assert_eq!(packet.flags.encryption(), EncryptionLevel::ChaCha20);
+
+        let bytes = packet.to_bytes();
+        assert!(!bytes.windows(secret.len()).any(|window| window == secret));
+        let recovered = Packet::from_bytes(&bytes).unwrap();
+        assert_eq!(recovered.ext.nonce_counter, Some(7));
+        assert_eq!(recovered.decrypt_payload(&KEY).unwrap(), secret);
+    }
+}
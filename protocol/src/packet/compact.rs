@@ -0,0 +1,186 @@
+//compact control framing, for keepalives on links where every byte costs
+//a Ping is 36 bytes of header and 32 of hash around nothing. Empty Ping/Pong/Success packets
+//can go out in this layout instead:
+// 1 byte   | COMPACT_VERSION (version 1 with the high bit set)
+// N bytes  | session id
+// 1 byte   | intent
+// 4 bytes  | sequence (u32, big-endian)
+// 4 bytes  | first 4 bytes of SHA256 over everything before it
+//26 bytes for the default session id. No timestamp (parsed packets get the time they were
+//parsed, so freshness and monotonic checks see them as just arrived), no flags, no extended
+//header, and the priority has to be the intent's default, that's what the receiver assumes
+//
+//from_bytes tells the two layouts apart by the first byte and hands back an ordinary packet
+//(resealed with a full hash), so nothing past parsing knows which layout it came in. One that
+//failed the short hash (from_bytes_unchecked lets it through) keeps failing verify()
+
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+use super::packet::*;
+use super::types::*;
+
+/// Version byte of the compact layout
+pub const COMPACT_VERSION: u8 = FDP_VERSION | 0x80;
+
+/// Truncated hash at the end of a compact packet
+pub const COMPACT_HASH_SIZE: usize = 4;
+
+impl<const N: usize> Packet<N> {
+    /// Wire size of a compact packet
+    pub const COMPACT_LEN: usize = 1 + N + 1 + 4 + COMPACT_HASH_SIZE;
+
+    /// Can this packet go out in the compact layout without losing anything but the timestamp?
+    pub fn is_compact_eligible(&self) -> bool {
+        matches!(self.intent, Intent::Ping | Intent::Pong | Intent::Success)
+            && self.priority == Priority::for_intent(self.intent)
+            && self.payload.is_empty()
+            && self.ext.is_empty()
+            && !self.flags.ack_required()
+    }
+
+    /// Serialize in the compact layout, for eligible packets only (see is_compact_eligible)
+    pub fn to_compact_bytes(&self) -> Result<Vec<u8>, PacketError> {
+        if !self.is_compact_eligible() {
+            return Err(PacketError::SemanticMismatch(
+                "compact framing is for empty control packets",
+            ));
+        }
+
+        let mut buffer = Vec::with_capacity(Self::COMPACT_LEN);
+        buffer.push(COMPACT_VERSION);
+        buffer.extend_from_slice(self.session_id.as_bytes());
+        buffer.push(self.intent.to_u8());
+        buffer.extend_from_slice(&self.sequence.to_be_bytes());
+        let hash = compact_hash(&buffer);
+        buffer.extend_from_slice(&hash);
+        Ok(buffer)
+    }
+
+    // from_bytes for the compact layout, `check_hash` false for from_bytes_unchecked
+    pub(super) fn decode_compact(bytes: &[u8], check_hash: bool) -> Result<Self, PacketError> {
+        if bytes.len() < Self::COMPACT_LEN {
+            return Err(PacketError::TooSmall);
+        }
+        if bytes.len() > Self::COMPACT_LEN {
+            return Err(PacketError::LengthMismatch);
+        }
+
+        let (fields, hash) = bytes.split_at(Self::COMPACT_LEN - COMPACT_HASH_SIZE);
+        let intact = bool::from(compact_hash(fields).ct_eq(hash));
+        if check_hash && !intact {
+            return Err(PacketError::InvalidHash);
+        }
+
+        let mut session_bytes = [0u8; N];
+        session_bytes.copy_from_slice(&fields[1..1 + N]);
+        let intent = parse_intent(fields[1 + N])?;
+        if !matches!(intent, Intent::Ping | Intent::Pong | Intent::Success) {
+            return Err(PacketError::SemanticMismatch(
+                "compact framing is for empty control packets",
+            ));
+        }
+        let sequence = u32::from_be_bytes([fields[2 + N], fields[3 + N], fields[4 + N], fields[5 + N]]);
+
+        // Packet::new stamps the current time, that's the closest thing to a send time we have
+        let mut packet = Packet::new(SessionId::from_bytes(session_bytes), intent, Vec::new());
+        packet.priority = Priority::for_intent(intent);
+        packet.flags = Flags::new();
+        packet.sequence = sequence;
+        packet.reseal();
+        if !intact {
+            packet.hash = [0u8; HASH_SIZE];
+        }
+        Ok(packet)
+    }
+}
+
+fn compact_hash(fields: &[u8]) -> [u8; COMPACT_HASH_SIZE] {
+    let digest = Sha256::digest(fields);
+    let mut hash = [0u8; COMPACT_HASH_SIZE];
+    hash.copy_from_slice(&digest[..COMPACT_HASH_SIZE]);
+    hash
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::monitor::TimestampMonotonicChecker;
+
+    fn ping() -> Packet {
+        let mut ping = Packet::new(SessionId::new(), Intent::Ping, vec![]);
+        ping.priority = Priority::for_intent(Intent::Ping);
+        ping.reseal();
+        ping
+    }
+
+    #[test]
+    fn test_compact_ping_roundtrip() {
+        let mut ping = ping();
+        ping.set_sequence(41);
+        ping.reseal();
+
+        let compact = ping.to_compact_bytes().unwrap();
+        assert_eq!(compact.len(), 26);
+        assert!(compact.len() * 2 < ping.to_bytes().len());
+
+        let recovered = Packet::from_bytes(&compact).unwrap();
+        assert_eq!(recovered.intent, Intent::Ping);
+        assert_eq!(recovered.session_id, ping.session_id);
+        assert_eq!(recovered.sequence, 41);
+        assert_eq!(recovered.priority, Priority::for_intent(Intent::Ping));
+        assert!(recovered.verify());
+
+        let mut tampered = compact.clone();
+        tampered[20] ^= 0x01; // inside the sequence
+        assert!(matches!(Packet::from_bytes(&tampered), Err(PacketError::InvalidHash)));
+        let unchecked = Packet::from_bytes_unchecked(&tampered).unwrap();
+        assert!(!unchecked.verify());
+    }
+
+    #[test]
+    fn test_compact_ping_passes_freshness_checks() {
+        let full = ping();
+        let sent_at = full.timestamp_ms();
+        let recovered = Packet::from_bytes(&full.to_compact_bytes().unwrap()).unwrap();
+
+        // stamped on arrival, not at the epoch
+        assert!(recovered.timestamp_ms() >= sent_at);
+        assert!(!recovered.is_expired(60_000, recovered.timestamp_ms() + 1_000));
+
+        let mut checker = TimestampMonotonicChecker::new(1_000);
+        checker.check(&full).unwrap();
+        checker.check(&recovered).unwrap();
+    }
+
+    #[test]
+    fn test_non_default_priority_keeps_full_layout() {
+        let mut ping = ping();
+        assert!(ping.is_compact_eligible());
+
+        ping.priority = Priority::LOWEST;
+        ping.reseal();
+        assert!(!ping.is_compact_eligible());
+        assert!(ping.to_compact_bytes().is_err());
+    }
+
+    #[test]
+    fn test_data_packets_keep_full_layout() {
+        let push = Packet::new(SessionId::new(), Intent::DataPush, b"data".to_vec());
+        assert!(!push.is_compact_eligible());
+        assert!(matches!(push.to_compact_bytes(), Err(PacketError::SemanticMismatch(_))));
+
+        let bytes = push.to_bytes();
+        assert_eq!(bytes[0], FDP_VERSION);
+        assert_eq!(bytes.len(), MIN_PACKET_SIZE + 4);
+
+        // a Ping with a payload has to go out in full too
+        let ping = Packet::new(SessionId::new(), Intent::Ping, b"t=1".to_vec());
+        assert!(ping.to_compact_bytes().is_err());
+    }
+}
@@ -0,0 +1,921 @@
+//payload compression, the codecs behind the compression bits in Flags
+//compress() turns a payload into what goes on the wire, decompress() undoes it on the other side
+//decompression is always capped at MAX_PAYLOAD_SIZE so a tiny packet can't expand into gigabytes (zip bomb)
+//compress_payload also records the original length in the extended header, decompress_payload
+//allocates exactly that once (after checking it against the same cap) instead of growing a buffer
+
+use std::io::{ErrorKind, Read, Write};
+
+use super::packet::*;
+use super::types::*;
+
+// brotli window size (log2), middle of the road
+const BROTLI_WINDOW: u32 = 22;
+
+// ============================================================================
+// COMPRESSION LEVEL
+// ============================================================================
+// Speed vs ratio, picked by the sender. Decompression doesn't care which level was used,
+// so it's never put on the wire. LZ4 has no levels and ignores this.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompressionLevel {
+    Fast,
+    #[default]
+    Default,
+    Max,
+}
+
+impl CompressionLevel {
+    /// zstd level, Default is zstd's own default (3)
+    fn zstd_level(self) -> i32 {
+        match self {
+            CompressionLevel::Fast => 1,
+            CompressionLevel::Default => 3,
+            CompressionLevel::Max => 19,
+        }
+    }
+
+    /// brotli quality, 0-11
+    fn brotli_quality(self) -> u32 {
+        match self {
+            CompressionLevel::Fast => 1,
+            CompressionLevel::Default => 6,
+            CompressionLevel::Max => 11,
+        }
+    }
+}
+
+// ============================================================================
+// ZSTD DICTIONARY
+// ============================================================================
+// Lots of small similar payloads (search queries!) compress badly on their own because
+// every one starts from an empty history. A dictionary trained on typical payloads gives
+// zstd that history up front. Both ends of a session must hold the exact same dictionary,
+// it is trained/negotiated out of band and never travels inside a packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZstdDictionary(Vec<u8>);
+
+impl ZstdDictionary {
+    /// Use an already trained dictionary
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        ZstdDictionary(bytes)
+    }
+
+    /// Train a dictionary of at most `max_size` bytes from sample payloads
+    /// zstd wants a decent number of samples, a handful is not enough
+    pub fn train<S: AsRef<[u8]>>(samples: &[S], max_size: usize) -> Result<Self, PacketError> {
+        zstd::dict::from_samples(samples, max_size)
+            .map(ZstdDictionary)
+            .map_err(|_| PacketError::CompressionFailed)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+// ============================================================================
+// AUTO SELECTION
+// ============================================================================
+// Images and archives are already compressed, running them through a codec burns CPU and
+// usually makes them a little bigger. Compression::Auto is a sender-side decision only: it
+// resolves to a real Compression before anything is encoded, so it never needs a wire value.
+
+// leading bytes of formats that are compressed already
+const COMPRESSED_MAGIC: &[&[u8]] = &[
+    b"\x89PNG\r\n\x1a\n", // PNG
+    b"\xFF\xD8\xFF",       // JPEG
+    b"GIF87a",             // GIF
+    b"GIF89a",
+    b"PK\x03\x04",         // ZIP (and everything built on it: docx, jar, apk...)
+    b"\x1F\x8B",           // gzip
+];
+
+/// Does the payload start like a format that's compressed already?
+pub fn is_already_compressed(data: &[u8]) -> bool {
+    COMPRESSED_MAGIC.iter().any(|magic| data.starts_with(magic))
+}
+
+/// Pick a codec for a payload: None for already compressed formats, Zstd otherwise
+pub fn auto_compression(data: &[u8]) -> Compression {
+    if is_already_compressed(data) {
+        Compression::None
+    } else {
+        Compression::Zstd
+    }
+}
+
+// the codec that actually gets used for `data`, anything but Auto is taken as is
+pub(crate) fn resolve(compression: Compression, data: &[u8]) -> Compression {
+    match compression {
+        Compression::Auto => auto_compression(data),
+        other => other,
+    }
+}
+
+// ============================================================================
+// COMPRESS / DECOMPRESS
+// ============================================================================
+
+/// Compress a payload with the given algorithm at the default level
+/// `dict` is only used (and required) for Compression::ZstdDict
+pub fn compress(
+    compression: Compression,
+    data: &[u8],
+    dict: Option<&ZstdDictionary>,
+) -> Result<Vec<u8>, PacketError> {
+    compress_with_level(compression, CompressionLevel::Default, data, dict)
+}
+
+/// compress() with an explicit speed/ratio trade-off
+/// Auto compresses with whatever auto_compression picks, the Packet methods record which
+pub fn compress_with_level(
+    compression: Compression,
+    level: CompressionLevel,
+    data: &[u8],
+    dict: Option<&ZstdDictionary>,
+) -> Result<Vec<u8>, PacketError> {
+    match compression {
+        Compression::None => Ok(data.to_vec()),
+
+        Compression::Lz4 => Ok(lz4_flex::compress_prepend_size(data)),
+
+        Compression::Zstd => {
+            zstd::bulk::compress(data, level.zstd_level()).map_err(|_| PacketError::CompressionFailed)
+        }
+
+        Compression::ZstdDict => {
+            let dict = dict.ok_or(PacketError::MissingDictionary)?;
+            zstd::bulk::Compressor::with_dictionary(level.zstd_level(), dict.as_bytes())
+                .and_then(|mut compressor| compressor.compress(data))
+                .map_err(|_| PacketError::CompressionFailed)
+        }
+
+        Compression::Brotli => {
+            let mut out = Vec::new();
+            let mut writer =
+                brotli::CompressorWriter::new(&mut out, 4096, level.brotli_quality(), BROTLI_WINDOW);
+            std::io::Write::write_all(&mut writer, data)
+                .map_err(|_| PacketError::CompressionFailed)?;
+            drop(writer); // flushes the final brotli block into out
+            Ok(out)
+        }
+
+        Compression::Auto => compress_with_level(auto_compression(data), level, data, dict),
+    }
+}
+
+/// Reverse of compress, same dictionary rules
+pub fn decompress(
+    compression: Compression,
+    data: &[u8],
+    dict: Option<&ZstdDictionary>,
+) -> Result<Vec<u8>, PacketError> {
+    decompress_sized(compression, data, dict, 0)
+}
+
+// decompress() with the output buffer allocated at `capacity` up front
+fn decompress_sized(
+    compression: Compression,
+    data: &[u8],
+    dict: Option<&ZstdDictionary>,
+    capacity: usize,
+) -> Result<Vec<u8>, PacketError> {
+    match compression {
+        Compression::None => Ok(data.to_vec()),
+
+        Compression::Lz4 => {
+            // lz4_flex trusts the 4-byte size prefix and allocates it up front, check it first
+            if data.len() < 4 {
+                return Err(PacketError::DecompressionFailed);
+            }
+            let mut size_bytes = [0u8; 4];
+            size_bytes.copy_from_slice(&data[..4]);
+            if u32::from_le_bytes(size_bytes) as usize > MAX_PAYLOAD_SIZE {
+                return Err(PacketError::TooLarge);
+            }
+            lz4_flex::decompress_size_prepended(data).map_err(|_| PacketError::DecompressionFailed)
+        }
+
+        Compression::Zstd => {
+            let decoder =
+                zstd::stream::read::Decoder::new(data).map_err(|_| PacketError::DecompressionFailed)?;
+            read_limited(decoder, capacity)
+        }
+
+        Compression::ZstdDict => {
+            let dict = dict.ok_or(PacketError::MissingDictionary)?;
+            let decoder = zstd::stream::read::Decoder::with_dictionary(data, dict.as_bytes())
+                .map_err(|_| PacketError::DecompressionFailed)?;
+            read_limited(decoder, capacity)
+        }
+
+        Compression::Brotli => read_limited(brotli::Decompressor::new(data, 4096), capacity),
+
+        // never on the wire, there's nothing to tell which codec it was
+        Compression::Auto => Err(PacketError::DecompressionFailed),
+    }
+}
+
+/// Worst-case size of `len` bytes after compressing with the given algorithm
+/// Incompressible data still grows a little (framing, literal blocks), this is that bound
+pub fn max_compressed_size(compression: Compression, len: usize) -> usize {
+    match compression {
+        Compression::None => len,
+        // +4 for the size prefix compress_prepend_size adds
+        Compression::Lz4 => lz4_flex::block::get_maximum_output_size(len) + 4,
+        Compression::Zstd | Compression::ZstdDict => zstd::zstd_safe::compress_bound(len),
+        // same as BrotliEncoderMaxCompressedSize: 4 bytes per 16KB block + a few for the stream
+        Compression::Brotli => len + 2 + 4 * ((len >> 14) + 1) + 4,
+        // either None or Zstd, and zstd's bound is the larger
+        Compression::Auto => zstd::zstd_safe::compress_bound(len),
+    }
+}
+
+// ============================================================================
+// WIRE SIZE
+// ============================================================================
+// size() is the packet as it is right now, with the payload uncompressed.
+// These answer "how big will it be once the payload is compressed per its flags".
+impl Packet {
+    /// Compress the (uncompressed) payload in place, set the compression flag and reseal
+    /// Compress before encrypting, ciphertext doesn't compress
+    /// Auto is resolved against the payload first, the flags get the codec it picked
+    pub fn compress_payload(
+        &mut self,
+        compression: Compression,
+        level: CompressionLevel,
+        dict: Option<&ZstdDictionary>,
+    ) -> Result<(), PacketError> {
+        let compression = resolve(compression, &self.payload);
+        let uncompressed_len = self.payload.len();
+        self.payload = compress_with_level(compression, level, &self.payload, dict)?;
+        self.uncompressed_len = Some(uncompressed_len);
+        self.ext.decompressed_size = decompressed_size(compression, uncompressed_len);
+        self.flags.set_compression(compression);
+        self.reseal();
+        Ok(())
+    }
+
+    /// Compressed / original payload size from the last compress_payload, for tuning
+    /// Only known on the side that compressed, None on parsed packets and empty payloads
+    pub fn compression_ratio(&self) -> Option<f32> {
+        match self.uncompressed_len {
+            Some(original) if original > 0 => Some(self.payload.len() as f32 / original as f32),
+            _ => None,
+        }
+    }
+
+    /// Payload length after decompression, as the sender recorded it
+    pub fn decompressed_size_hint(&self) -> Option<usize> {
+        self.ext.decompressed_size.map(|size| size as usize)
+    }
+
+    /// Decompress the payload according to the packet's flags
+    /// With a size hint the output is allocated once, and has to come out at exactly that size
+    pub fn decompress_payload(&self, dict: Option<&ZstdDictionary>) -> Result<Vec<u8>, PacketError> {
+        let hint = self.decompressed_size_hint();
+        if hint.is_some_and(|size| size > MAX_PAYLOAD_SIZE) {
+            return Err(PacketError::TooLarge);
+        }
+        let payload = decompress_sized(self.flags.compression(), &self.payload, dict, hint.unwrap_or(0))?;
+        if hint.is_some_and(|size| size != payload.len()) {
+            return Err(PacketError::DecompressionFailed);
+        }
+        Ok(payload)
+    }
+
+    /// Same packet with the payload re-encoded as `to`, for relays between peers that
+    /// support different codecs. CRC and fingerprint follow the new payload, and since the
+    /// hash covers the flags and the payload as sent, the copy is resealed with a new hash
+    /// ZstdDict payloads can't be transcoded here, there's no dictionary to hand
+    pub fn recompress(&self, to: Compression) -> Result<Packet, PacketError> {
+        let plain = self.decompress_payload(None)?;
+        let to = resolve(to, &plain);
+        let encoded = compress_with_level(to, CompressionLevel::Default, &plain, None)?;
+        let mut packet = self.rebuild_with_payload(encoded)?;
+        packet.uncompressed_len = Some(plain.len());
+        packet.ext.decompressed_size = decompressed_size(to, plain.len());
+        packet.flags.set_compression(to);
+        packet.reseal();
+        Ok(packet)
+    }
+
+    /// Upper bound on the wire size after compression, no compressing done
+    /// Safe for sizing buffers
+    pub fn estimated_wire_size(&self) -> usize {
+        let payload = max_compressed_size(self.flags.compression(), self.payload.len());
+        self.size() - self.payload.len() + payload
+    }
+
+    /// Real wire size, compresses the payload to find out
+    /// Falls back to the estimate when we can't compress here (e.g. no dictionary at hand)
+    pub fn actual_wire_size(&self) -> usize {
+        match compress(self.flags.compression(), &self.payload, None) {
+            Ok(compressed) => self.size() - self.payload.len() + compressed.len(),
+            Err(_) => self.estimated_wire_size(),
+        }
+    }
+}
+
+// nothing to record for payloads that aren't compressed
+fn decompressed_size(compression: Compression, len: usize) -> Option<u32> {
+    (compression != Compression::None).then_some(len as u32)
+}
+
+// ============================================================================
+// STREAMING
+// ============================================================================
+// Transfers of hundreds of MB don't fit the packet-at-a-time functions above, those hold the
+// input and the output whole. CompressedPushStream reads the source a window at a time through
+// one zstd stream and cuts packets off the compressed output as soon as there's enough of it,
+// so the sender holds a window, at most a packet's worth of output and zstd's own state.
+// PushDecompressor is the other end, it feeds each packet's payload back through zstd and
+// writes the output to a writer (a file, a socket) window by window.
+//
+// The packets are consecutive DataPush packets (sequence counts up from the header's). Each
+// payload is a slice of a single zstd stream and can't be decompressed on its own, so the
+// compression bits say None. The stream ends with the zstd frame, no end marker needed.
+
+/// Bytes read or written per step on the streaming paths
+pub const STREAM_WINDOW: usize = 64 * 1024;
+
+/// Packets carrying one zstd stream of everything `reader` yields
+pub struct CompressedPushStream<R> {
+    reader: R,
+    header: Packet,
+    chunk_size: usize,
+    // None once the reader is exhausted and the frame is finished
+    encoder: Option<zstd::stream::write::Encoder<'static, Vec<u8>>>,
+    // compressed bytes not sent yet
+    output: Vec<u8>,
+    window: Vec<u8>,
+    sequence: Sequence,
+    read: u64,
+    failed: bool,
+}
+
+impl<R: Read> CompressedPushStream<R> {
+    /// `header` supplies session, priority and the first sequence (its payload is ignored),
+    /// every packet carries at most `chunk_size` compressed bytes
+    pub fn new(reader: R, header: &Packet, chunk_size: usize, level: CompressionLevel) -> Result<Self, PacketError> {
+        if chunk_size == 0 || chunk_size > MAX_PAYLOAD_SIZE {
+            return Err(PacketError::InvalidFragment);
+        }
+        let mut header = header.rebuild_with_payload(Vec::new())?;
+        header.intent = Intent::DataPush;
+        header.flags = Flags::new();
+        let encoder = zstd::stream::write::Encoder::new(Vec::new(), level.zstd_level())
+            .map_err(|_| PacketError::CompressionFailed)?;
+
+        Ok(CompressedPushStream {
+            reader,
+            sequence: header.sequence,
+            header,
+            chunk_size,
+            encoder: Some(encoder),
+            output: Vec::new(),
+            window: vec![0u8; STREAM_WINDOW],
+            read: 0,
+            failed: false,
+        })
+    }
+
+    /// Uncompressed bytes read from the source so far
+    pub fn bytes_read(&self) -> u64 {
+        self.read
+    }
+
+    /// Compressed bytes waiting for the next packet, this is what bounds the sender's memory
+    pub fn buffered(&self) -> usize {
+        self.output.len() + self.encoder.as_ref().map_or(0, |encoder| encoder.get_ref().len())
+    }
+
+    // read and compress until a packet's worth of output is ready or the source is done
+    fn fill(&mut self) -> Result<(), PacketError> {
+        while self.output.len() < self.chunk_size {
+            let Some(encoder) = self.encoder.as_mut() else {
+                return Ok(());
+            };
+            let n = match self.reader.read(&mut self.window) {
+                Ok(n) => n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(PacketError::Io(e.kind())),
+            };
+            if n == 0 {
+                let encoder = self.encoder.take().expect("checked above");
+                let rest = encoder.finish().map_err(|_| PacketError::CompressionFailed)?;
+                self.output.extend_from_slice(&rest);
+                return Ok(());
+            }
+            encoder.write_all(&self.window[..n]).map_err(|_| PacketError::CompressionFailed)?;
+            self.read += n as u64;
+            self.output.append(encoder.get_mut());
+        }
+        Ok(())
+    }
+
+    fn next_packet(&mut self) -> Result<Option<Packet>, PacketError> {
+        self.fill()?;
+        if self.output.is_empty() {
+            return Ok(None);
+        }
+        let take = self.output.len().min(self.chunk_size);
+        let payload: Vec<u8> = self.output.drain(..take).collect();
+        let mut packet = self.header.rebuild_with_payload(payload)?;
+        packet.sequence = self.sequence;
+        packet.reseal();
+        self.sequence = self.sequence.wrapping_add(1);
+        Ok(Some(packet))
+    }
+}
+
+impl<R: Read> Iterator for CompressedPushStream<R> {
+    type Item = Result<Packet, PacketError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let packet = self.next_packet();
+        if packet.is_err() {
+            // no point going on after a read or codec failure
+            self.failed = true;
+        }
+        packet.transpose()
+    }
+}
+
+/// Receiving end of a CompressedPushStream, writes the decompressed bytes to `writer`
+/// Packets have to come in order, the first one fixes the session and starting sequence
+pub struct PushDecompressor<W> {
+    writer: W,
+    decoder: zstd::stream::raw::Decoder<'static>,
+    window: Vec<u8>,
+    limit: u64,
+    written: u64,
+    // (session, sequence) the next packet has to have
+    next: Option<(SessionId, Sequence)>,
+    finished: bool,
+}
+
+impl<W: Write> PushDecompressor<W> {
+    /// Fails with TooLarge once more than `limit` bytes come out (the streaming version of
+    /// the zip bomb cap)
+    pub fn new(writer: W, limit: u64) -> Result<Self, PacketError> {
+        Ok(PushDecompressor {
+            writer,
+            decoder: zstd::stream::raw::Decoder::new().map_err(|_| PacketError::DecompressionFailed)?,
+            window: vec![0u8; STREAM_WINDOW],
+            limit,
+            written: 0,
+            next: None,
+            finished: false,
+        })
+    }
+
+    /// Feed the next packet in, true once the stream is complete
+    /// A packet out of order, from another session or past the end is InvalidFragment
+    pub fn push(&mut self, packet: &Packet) -> Result<bool, PacketError> {
+        use zstd::stream::raw::Operation;
+
+        if self.finished {
+            return Err(PacketError::InvalidFragment);
+        }
+        if let Some(next) = self.next {
+            if next != (packet.session_id, packet.sequence) {
+                return Err(PacketError::InvalidFragment);
+            }
+        }
+        self.next = Some((packet.session_id, packet.sequence.wrapping_add(1)));
+
+        let mut input = packet.payload.as_slice();
+        loop {
+            let status = self
+                .decoder
+                .run_on_buffers(input, &mut self.window)
+                .map_err(|_| PacketError::DecompressionFailed)?;
+            input = &input[status.bytes_read..];
+
+            self.written += status.bytes_written as u64;
+            if self.written > self.limit {
+                return Err(PacketError::TooLarge);
+            }
+            self.writer
+                .write_all(&self.window[..status.bytes_written])
+                .map_err(|e| PacketError::Io(e.kind()))?;
+
+            if status.remaining == 0 {
+                // frame done, anything after it isn't ours
+                if !input.is_empty() {
+                    return Err(PacketError::InvalidFragment);
+                }
+                self.finished = true;
+                return Ok(true);
+            }
+            // more output may be waiting while the window came back full
+            if input.is_empty() && status.bytes_written < self.window.len() {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// Decompressed bytes written so far
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+// Read a decompressing stream to the end, refusing to go past MAX_PAYLOAD_SIZE
+fn read_limited<R: Read>(reader: R, capacity: usize) -> Result<Vec<u8>, PacketError> {
+    let mut out = Vec::with_capacity(capacity);
+    reader
+        .take(MAX_PAYLOAD_SIZE as u64 + 1)
+        .read_to_end(&mut out)
+        .map_err(|_| PacketError::DecompressionFailed)?;
+
+    if out.len() > MAX_PAYLOAD_SIZE {
+        return Err(PacketError::TooLarge);
+    }
+    Ok(out)
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // search-query shaped payloads, similar but not identical
+    fn query(i: usize) -> Vec<u8> {
+        format!(
+            "{{\"q\":\"rust async runtime {}\",\"lang\":\"en\",\"filters\":{{\"site\":\"docs\",\"page\":{}}}}}",
+            i,
+            i % 7
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn test_roundtrip_all_codecs() {
+        let data = b"the quick brown fox jumps over the lazy dog ".repeat(50);
+
+        for compression in [
+            Compression::None,
+            Compression::Lz4,
+            Compression::Zstd,
+            Compression::Brotli,
+        ] {
+            let compressed = compress(compression, &data, None).unwrap();
+            let recovered = decompress(compression, &compressed, None).unwrap();
+            assert_eq!(recovered, data, "{:?} did not round-trip", compression);
+        }
+    }
+
+    #[test]
+    fn test_dictionary_beats_plain_zstd_on_small_payloads() {
+        let samples: Vec<Vec<u8>> = (0..500).map(query).collect();
+        let dict = ZstdDictionary::train(&samples, 4096).unwrap();
+
+        let mut plain_total = 0;
+        let mut dict_total = 0;
+        for i in 1000..1020 {
+            let payload = query(i);
+
+            let plain = compress(Compression::Zstd, &payload, None).unwrap();
+            let with_dict = compress(Compression::ZstdDict, &payload, Some(&dict)).unwrap();
+
+            // decompressor needs the very same dictionary
+            assert_eq!(
+                decompress(Compression::ZstdDict, &with_dict, Some(&dict)).unwrap(),
+                payload
+            );
+
+            plain_total += plain.len();
+            dict_total += with_dict.len();
+        }
+
+        assert!(
+            dict_total < plain_total,
+            "dictionary {} bytes vs plain {} bytes",
+            dict_total,
+            plain_total
+        );
+    }
+
+    #[test]
+    fn test_max_level_not_bigger_than_fast() {
+        let data: Vec<u8> = (0..2000).flat_map(query).collect();
+
+        for compression in [Compression::Zstd, Compression::Brotli] {
+            let fast = compress_with_level(compression, CompressionLevel::Fast, &data, None).unwrap();
+            let max = compress_with_level(compression, CompressionLevel::Max, &data, None).unwrap();
+            assert!(
+                max.len() <= fast.len(),
+                "{:?}: max {} bytes vs fast {} bytes",
+                compression,
+                max.len(),
+                fast.len()
+            );
+
+            // the level isn't needed to decompress
+            assert_eq!(decompress(compression, &max, None).unwrap(), data);
+            assert_eq!(decompress(compression, &fast, None).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_compress_payload() {
+        let payload = b"compress me please ".repeat(100);
+        let mut packet = Packet::new(SessionId::new(), Intent::DataPush, payload.clone());
+        packet
+            .compress_payload(Compression::Zstd, CompressionLevel::Max, None)
+            .unwrap();
+        assert!(packet.payload.len() < payload.len());
+
+        let recovered = Packet::from_bytes(&packet.to_bytes()).unwrap();
+        assert_eq!(recovered.flags.compression(), Compression::Zstd);
+        assert_eq!(recovered.decompress_payload(None).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_auto_skips_compressed_formats() {
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.extend_from_slice(&[0x5A; 200]);
+        assert_eq!(auto_compression(&png), Compression::None);
+        assert_eq!(auto_compression(b"\x1F\x8B\x08\x00gzipped"), Compression::None);
+        assert_eq!(auto_compression(b"plain old text, compresses nicely"), Compression::Zstd);
+
+        let mut packet = Packet::new(SessionId::new(), Intent::DataPush, png.clone());
+        packet.compress_payload(Compression::Auto, CompressionLevel::Default, None).unwrap();
+        assert_eq!(packet.flags.compression(), Compression::None);
+        assert_eq!(packet.payload, png);
+
+        // resolved before it reaches the wire, the receiver sees the real codec
+        let text = b"plain old text, plain old text, plain old text".to_vec();
+        let mut packet = Packet::new(SessionId::new(), Intent::DataPush, text.clone());
+        packet.compress_payload(Compression::Auto, CompressionLevel::Default, None).unwrap();
+        let recovered = Packet::from_bytes(&packet.to_bytes()).unwrap();
+        assert_eq!(recovered.flags.compression(), Compression::Zstd);
+        assert_eq!(recovered.decompress_payload(None).unwrap(), text);
+        assert_eq!(Compression::from_u8(Compression::Auto.to_u8()), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Auto is resolved")]
+    fn test_auto_never_reaches_the_flags() {
+        Flags::new().set_compression(Compression::Auto);
+    }
+
+    #[test]
+    fn test_compression_ratio() {
+        let session_id = SessionId::new();
+        let mut text = Packet::new(session_id, Intent::DataPush, b"aaaaaaaaaaaaaaaa".repeat(256));
+        assert_eq!(text.compression_ratio(), None);
+        text.compress_payload(Compression::Zstd, CompressionLevel::Default, None).unwrap();
+        assert!(text.compression_ratio().unwrap() < 0.1);
+
+        // random bytes don't compress, zstd stores them with a little framing on top
+        let noise: Vec<u8> = (0..4096).map(|_| rand::random::<u8>()).collect();
+        let mut noisy = Packet::new(session_id, Intent::DataPush, noise);
+        noisy.compress_payload(Compression::Zstd, CompressionLevel::Default, None).unwrap();
+        let ratio = noisy.compression_ratio().unwrap();
+        assert!((0.98..1.02).contains(&ratio), "ratio {}", ratio);
+
+        // the original size isn't on the wire
+        let parsed = Packet::from_bytes(&text.to_bytes()).unwrap();
+        assert_eq!(parsed.compression_ratio(), None);
+    }
+
+    #[test]
+    fn test_dictionary_required() {
+        let payload = query(1);
+        assert!(matches!(
+            compress(Compression::ZstdDict, &payload, None),
+            Err(PacketError::MissingDictionary)
+        ));
+        assert!(matches!(
+            decompress(Compression::ZstdDict, &payload, None),
+            Err(PacketError::MissingDictionary)
+        ));
+    }
+
+    #[test]
+    fn test_estimated_wire_size_is_upper_bound() {
+        let payloads: Vec<Vec<u8>> = vec![
+            vec![],
+            vec![0x42],
+            vec![0u8; 10_000],                                       // very compressible
+            (0..20_000u32).map(|i| (i * 7919 % 251) as u8).collect(), // noisy
+            b"search query ".repeat(300),
+        ];
+
+        for compression in [
+            Compression::None,
+            Compression::Lz4,
+            Compression::Zstd,
+            Compression::Brotli,
+        ] {
+            for payload in &payloads {
+                let mut packet = Packet::new(SessionId::new(), Intent::DataPush, payload.clone());
+                packet.flags.set_compression(compression);
+
+                assert!(
+                    packet.estimated_wire_size() >= packet.actual_wire_size(),
+                    "{:?} with {} bytes: estimate {} < actual {}",
+                    compression,
+                    payload.len(),
+                    packet.estimated_wire_size(),
+                    packet.actual_wire_size()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_actual_wire_size_shrinks_compressible_payload() {
+        let mut packet = Packet::new(SessionId::new(), Intent::DataPush, vec![0u8; 10_000]);
+        packet.flags.set_compression(Compression::Zstd);
+        assert!(packet.actual_wire_size() < packet.size());
+    }
+
+    #[test]
+    fn test_lz4_bomb_rejected() {
+        // size prefix claims 4GB
+        let bomb = [0xFF, 0xFF, 0xFF, 0xFF, 0x00];
+        assert!(matches!(
+            decompress(Compression::Lz4, &bomb, None),
+            Err(PacketError::TooLarge)
+        ));
+    }
+
+    #[test]
+    fn test_recompress_brotli_to_lz4() {
+        let data = b"the quick brown fox jumps over the lazy dog ".repeat(50);
+        let mut packet = Packet::new(SessionId::new(), Intent::DataPush, data.clone());
+        packet.set_sequence(9);
+        packet.compress_payload(Compression::Brotli, CompressionLevel::Max, None).unwrap();
+        packet.set_payload_crc();
+
+        let relayed = packet.recompress(Compression::Lz4).unwrap();
+        let relayed = Packet::from_bytes(&relayed.to_bytes()).unwrap();
+        assert_eq!(relayed.flags.compression(), Compression::Lz4);
+        assert_eq!(relayed.decompress_payload(None).unwrap(), data);
+        assert_eq!(relayed.decompress_payload(None).unwrap(), packet.decompress_payload(None).unwrap());
+        assert_ne!(relayed.payload, packet.payload);
+
+        // headers carry over, the CRC is redone for the new bytes
+        assert_eq!(relayed.sequence, 9);
+        assert_eq!(relayed.timestamp, packet.timestamp);
+        assert!(relayed.check_payload_crc().is_ok());
+    }
+
+    // generated on the fly so the test itself never holds the payload either
+    struct Generated {
+        remaining: u64,
+        counter: u64,
+    }
+
+    impl Read for Generated {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(self.remaining as usize);
+            for byte in &mut buf[..n] {
+                *byte = b"search result line "[(self.counter % 19) as usize] ^ (self.counter >> 16) as u8;
+                self.counter += 1;
+            }
+            self.remaining -= n as u64;
+            Ok(n)
+        }
+    }
+
+    // hashes what it's given and remembers the biggest single write
+    #[derive(Default)]
+    struct Sink {
+        hasher: sha2::Sha256,
+        total: u64,
+        largest_write: usize,
+    }
+
+    impl Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            use sha2::Digest;
+            self.hasher.update(buf);
+            self.total += buf.len() as u64;
+            self.largest_write = self.largest_write.max(buf.len());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_push_stream_roundtrip_in_bounded_memory() {
+        use sha2::Digest;
+        const LEN: u64 = 48 * 1024 * 1024; // well past MAX_PAYLOAD_SIZE
+        const CHUNK: usize = 16 * 1024;
+
+        let mut expected = Sink::default();
+        std::io::copy(&mut Generated { remaining: LEN, counter: 0 }, &mut expected).unwrap();
+
+        let header = Packet::new(SessionId::new(), Intent::DataPush, vec![]);
+        let source = Generated { remaining: LEN, counter: 0 };
+        let mut stream = CompressedPushStream::new(source, &header, CHUNK, CompressionLevel::Fast).unwrap();
+        let mut receiver = PushDecompressor::new(Sink::default(), LEN).unwrap();
+
+        // packets go straight from the stream to the receiver, only their sizes are kept
+        let mut wire_bytes = 0u64;
+        let mut packets = 0u64;
+        let mut most_buffered = 0;
+        let mut finished = false;
+        while let Some(packet) = stream.next() {
+            most_buffered = most_buffered.max(stream.buffered());
+            let bytes = packet.unwrap().to_bytes();
+            wire_bytes += bytes.len() as u64;
+            packets += 1;
+            let packet = Packet::from_bytes(&bytes).unwrap();
+            assert!(packet.payload.len() <= CHUNK);
+            finished = receiver.push(&packet).unwrap();
+        }
+        assert!(finished);
+        assert_eq!(stream.bytes_read(), LEN);
+        assert!(packets > 1);
+        assert!(wire_bytes < LEN / 10);
+
+        // the sender never held more than a packet plus one window's output
+        assert!(most_buffered <= CHUNK + zstd::zstd_safe::compress_bound(STREAM_WINDOW));
+
+        let out = receiver.into_inner();
+        assert_eq!(out.total, LEN);
+        assert_eq!(out.hasher.finalize(), expected.hasher.finalize());
+        // came out a window at a time, never as one big buffer
+        assert!(out.largest_write <= STREAM_WINDOW);
+    }
+
+    #[test]
+    fn test_push_stream_order_and_limit() {
+        let data = b"stream me ".repeat(20_000);
+        let header = Packet::new(SessionId::new(), Intent::DataPush, vec![]);
+        let packets: Vec<Packet> = CompressedPushStream::new(&data[..], &header, 8, CompressionLevel::Default)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert!(packets.len() > 2);
+        assert!(packets.iter().all(|p| p.flags.compression() == Compression::None));
+
+        // a skipped packet is caught, not decoded into garbage
+        let mut receiver = PushDecompressor::new(Vec::new(), data.len() as u64).unwrap();
+        assert!(!receiver.push(&packets[0]).unwrap());
+        assert!(matches!(receiver.push(&packets[2]), Err(PacketError::InvalidFragment)));
+
+        // one byte less allowed than it expands to
+        let mut receiver = PushDecompressor::new(std::io::sink(), data.len() as u64 - 1).unwrap();
+        let capped = packets.iter().try_for_each(|p| receiver.push(p).map(|_| ()));
+        assert!(matches!(capped, Err(PacketError::TooLarge)));
+
+        let mut receiver = PushDecompressor::new(Vec::new(), data.len() as u64).unwrap();
+        for packet in &packets {
+            receiver.push(packet).unwrap();
+        }
+        assert!(receiver.is_finished());
+        assert!(matches!(receiver.push(&packets[0]), Err(PacketError::InvalidFragment)));
+        assert_eq!(receiver.into_inner(), data);
+    }
+
+    #[test]
+    fn test_decompressed_size_hint() {
+        let data = b"the quick brown fox jumps over the lazy dog ".repeat(500);
+        let mut packet = Packet::new(SessionId::new(), Intent::DataPush, data.clone());
+        packet.compress_payload(Compression::Zstd, CompressionLevel::Default, None).unwrap();
+
+        let parsed = Packet::from_bytes(&packet.to_bytes()).unwrap();
+        assert_eq!(parsed.decompressed_size_hint(), Some(data.len()));
+        let payload = parsed.decompress_payload(None).unwrap();
+        assert_eq!(payload, data);
+        // allocated at the hint and never grown
+        assert_eq!(payload.capacity(), data.len());
+
+        // a hint over the bomb limit is refused before anything is allocated
+        let mut bomb = parsed.clone();
+        bomb.ext.decompressed_size = Some(MAX_PAYLOAD_SIZE as u32 + 1);
+        bomb.reseal();
+        assert!(matches!(bomb.decompress_payload(None), Err(PacketError::TooLarge)));
+
+        // and one that doesn't match what came out is an error
+        let mut wrong = parsed;
+        wrong.ext.decompressed_size = Some(data.len() as u32 - 1);
+        wrong.reseal();
+        assert!(matches!(wrong.decompress_payload(None), Err(PacketError::DecompressionFailed)));
+    }
+}
@@ -0,0 +1,117 @@
+//a parsed packet that points into the receive buffer instead of copying the payload out
+//for the common "parse, look, pass on" path the payload never gets copied, the first write
+//to it (payload_mut) copies it once and from then on it behaves like an owned packet
+//
+//the header fields are small, those are always parsed out as owned values
+
+use std::borrow::Cow;
+
+use super::packet::*;
+use super::types::*;
+use super::extended::*;
+
+#[derive(Debug, Clone)]
+pub struct CowPacket<'a> {
+    pub version: u8,
+    pub session_id: SessionId,
+    pub intent: Intent,
+    pub priority: Priority,
+    pub flags: Flags,
+    pub sequence: Sequence,
+    pub timestamp: u64,
+    pub ext: ExtendedHeader,
+    pub payload: Cow<'a, [u8]>,
+    pub hash: [u8; 32],
+}
+
+impl Packet {
+    /// from_bytes, but the payload stays borrowed from `bytes`
+    /// Same checks as from_bytes (structure + hash)
+    pub fn try_from_bytes_cow(bytes: &[u8]) -> Result<CowPacket<'_>, PacketError> {
+        let (packet, payload) = Packet::decode_in_place(bytes)?;
+        Ok(CowPacket {
+            version: packet.version,
+            session_id: packet.session_id,
+            intent: packet.intent,
+            priority: packet.priority,
+            flags: packet.flags,
+            sequence: packet.sequence,
+            timestamp: packet.timestamp,
+            ext: packet.ext,
+            payload: Cow::Borrowed(&bytes[payload]),
+            hash: packet.hash,
+        })
+    }
+}
+
+impl CowPacket<'_> {
+    /// Still pointing into the buffer it was parsed from?
+    pub fn is_borrowed(&self) -> bool {
+        matches!(self.payload, Cow::Borrowed(_))
+    }
+
+    /// Mutable payload, copies it out of the source buffer on first use
+    pub fn payload_mut(&mut self) -> &mut Vec<u8> {
+        self.payload.to_mut()
+    }
+
+    /// Turn into a regular Packet, resealed so a changed payload gets a fresh hash
+    /// (an untouched packet reseals to the hash it already had)
+    pub fn into_owned(self) -> Packet {
+        let mut packet = Packet::new(self.session_id, self.intent, self.payload.into_owned());
+        packet.version = self.version;
+        packet.priority = self.priority;
+        packet.flags = self.flags;
+        packet.sequence = self.sequence;
+        packet.timestamp = self.timestamp;
+        packet.ext = self.ext;
+        packet.reseal();
+        packet
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cow_parse_borrows() {
+        let packet = Packet::new(SessionId::new(), Intent::DataPush, b"zero copy please".to_vec());
+        let bytes = packet.to_bytes();
+
+        let parsed = Packet::try_from_bytes_cow(&bytes).unwrap();
+        assert!(parsed.is_borrowed());
+        assert_eq!(parsed.payload.as_ptr(), bytes[Packet::<16>::HEADER_LEN..].as_ptr());
+
+        // untouched -> same packet as the normal path
+        assert_eq!(parsed.into_owned().to_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_cow_mutation_copies() {
+        let packet = Packet::new(SessionId::new(), Intent::DataPush, b"original".to_vec());
+        let bytes = packet.to_bytes();
+
+        let mut parsed = Packet::try_from_bytes_cow(&bytes).unwrap();
+        parsed.payload_mut().extend_from_slice(b" + more");
+        assert!(!parsed.is_borrowed());
+
+        // source buffer is left alone
+        assert_eq!(bytes, packet.to_bytes());
+
+        let owned = parsed.into_owned();
+        assert_eq!(owned.payload, b"original + more");
+        assert!(owned.verify());
+    }
+
+    #[test]
+    fn test_cow_rejects_bad_hash() {
+        let mut bytes = Packet::new(SessionId::new(), Intent::DataPush, b"x".to_vec()).to_bytes();
+        bytes[Packet::<16>::HEADER_LEN] ^= 0xFF;
+        assert!(matches!(Packet::try_from_bytes_cow(&bytes), Err(PacketError::InvalidHash)));
+    }
+}
@@ -0,0 +1,133 @@
+//payload CRC32, an early corruption check for big payloads
+//the SHA-256 can only be checked once every byte is in, and it's not cheap
+//a CRC carried in the extended header can be fed chunk by chunk while the payload streams in,
+//so a receiver can drop a broken packet as soon as the payload ends without hashing it at all
+//it is NOT a security check (trivial to forge), the hash/signature still do that job
+
+use super::packet::*;
+
+impl<const N: usize> Packet<N> {
+    /// Attach a CRC32 of the current payload and reseal
+    pub fn set_payload_crc(&mut self) {
+        self.ext.payload_crc = Some(crc32fast::hash(&self.payload));
+        self.reseal();
+    }
+
+    /// Check the payload against the attached CRC, packets without one pass
+    pub fn check_payload_crc(&self) -> Result<(), PacketError> {
+        match self.ext.payload_crc {
+            Some(expected) if crc32fast::hash(&self.payload) != expected => {
+                Err(PacketError::PayloadCrcMismatch)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Incremental CRC check for a payload that arrives in pieces
+pub struct PayloadCrcCheck {
+    expected: u32,
+    hasher: crc32fast::Hasher,
+    remaining: usize, // payload bytes still to come
+}
+
+impl PayloadCrcCheck {
+    pub fn new(expected: u32, payload_len: usize) -> Self {
+        PayloadCrcCheck {
+            expected,
+            hasher: crc32fast::Hasher::new(),
+            remaining: payload_len,
+        }
+    }
+
+    /// Feed the next chunk of payload
+    pub fn update(&mut self, chunk: &[u8]) -> Result<(), PacketError> {
+        if chunk.len() > self.remaining {
+            return Err(PacketError::LengthMismatch);
+        }
+        self.hasher.update(chunk);
+        self.remaining -= chunk.len();
+        Ok(())
+    }
+
+    /// All payload bytes seen?
+    pub fn is_complete(&self) -> bool {
+        self.remaining == 0
+    }
+
+    /// Compare once the whole payload went through
+    pub fn finish(self) -> Result<(), PacketError> {
+        if self.remaining != 0 {
+            return Err(PacketError::LengthMismatch);
+        }
+        if self.hasher.finalize() != self.expected {
+            return Err(PacketError::PayloadCrcMismatch);
+        }
+        Ok(())
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::builder::PacketBuilder;
+    use crate::packet::types::*;
+
+    fn crc_packet() -> Packet {
+        PacketBuilder::new(SessionId::new(), Intent::DataPush)
+            .payload((0..4096u32).map(|i| i as u8).collect())
+            .payload_crc()
+            .flags(Flags::new())
+            .insecure_allow_plaintext()
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_crc_roundtrip() {
+        let packet = crc_packet();
+        assert!(packet.ext.payload_crc.is_some());
+
+        let recovered = Packet::from_bytes(&packet.to_bytes()).unwrap();
+        assert_eq!(recovered.ext.payload_crc, packet.ext.payload_crc);
+        assert!(recovered.check_payload_crc().is_ok());
+    }
+
+    #[test]
+    fn test_streaming_crc_catches_mid_payload_corruption() {
+        let packet = crc_packet();
+        let mut corrupted = packet.payload.clone();
+        corrupted[2000] ^= 0x01;
+
+        // feed it in as it would come off the socket
+        let mut check = PayloadCrcCheck::new(packet.ext.payload_crc.unwrap(), corrupted.len());
+        for chunk in corrupted.chunks(512) {
+            check.update(chunk).unwrap();
+        }
+        assert!(check.is_complete());
+        assert!(matches!(check.finish(), Err(PacketError::PayloadCrcMismatch)));
+
+        // from_bytes hits the CRC before it ever gets to the SHA-256 comparison
+        let mut bytes = packet.to_bytes();
+        let offset = bytes.len() - HASH_SIZE - packet.payload.len() + 2000;
+        bytes[offset] ^= 0x01;
+        assert!(matches!(
+            Packet::from_bytes(&bytes),
+            Err(PacketError::PayloadCrcMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_streaming_crc_clean_payload() {
+        let packet = crc_packet();
+        let mut check = PayloadCrcCheck::new(packet.ext.payload_crc.unwrap(), packet.payload.len());
+        for chunk in packet.payload.chunks(100) {
+            check.update(chunk).unwrap();
+        }
+        assert!(check.finish().is_ok());
+    }
+}
@@ -0,0 +1,276 @@
+//payload encryption, the ciphers behind the encryption bits in Flags
+//only the payload is encrypted, the header has to stay readable for routing
+//the hash is computed over the encrypted payload, so anyone can check integrity without the key
+//
+//nonce (12 bytes) = first 4 bytes of the session id + an 8-byte nonce counter
+//the counter comes from a NonceCounter that lives as long as the key, it never restarts when
+//sequences do (reconnects), and travels in the extended header so the receiver can rebuild
+//the nonce. Packets without a counter fall back to first 8 bytes of the session id + the
+//4-byte sequence number, which repeats if sequences reset
+//either way a key must never be reused across sessions
+//
+//the ciphers sit behind the `encryption` feature (on by default), builds that leave it off
+//only know EncryptionLevel::None and answer everything else with EncryptionUnsupported
+
+#[cfg(feature = "encryption")]
+use aes_gcm::Aes256Gcm;
+#[cfg(feature = "encryption")]
+use chacha20poly1305::aead::{Aead, KeyInit};
+#[cfg(feature = "encryption")]
+use chacha20poly1305::ChaCha20Poly1305;
+
+use super::packet::*;
+use super::types::*;
+
+pub const KEY_SIZE: usize = 32;
+pub const NONCE_SIZE: usize = 12;
+
+/// Nonce for a packet, unique as long as (session, sequence) is
+pub fn packet_nonce(session_id: &SessionId, sequence: Sequence) -> [u8; NONCE_SIZE] {
+    let mut nonce = [0u8; NONCE_SIZE];
+    nonce[..8].copy_from_slice(&session_id.as_bytes()[..8]);
+    nonce[8..].copy_from_slice(&sequence.to_be_bytes());
+    nonce
+}
+
+/// Nonce built from a NonceCounter value
+pub fn counter_nonce(session_id: &SessionId, counter: u64) -> [u8; NONCE_SIZE] {
+    let mut nonce = [0u8; NONCE_SIZE];
+    nonce[..4].copy_from_slice(&session_id.as_bytes()[..4]);
+    nonce[4..].copy_from_slice(&counter.to_be_bytes());
+    nonce
+}
+
+/// Hands out nonce counters for one key, keep it (not the sequence) for the key's whole life
+/// Starts over only together with a new key
+#[derive(Debug, Default)]
+pub struct NonceCounter {
+    next: u64,
+}
+
+impl NonceCounter {
+    pub fn new() -> Self {
+        NonceCounter::default()
+    }
+
+    /// Next unused value, fails instead of wrapping around
+    pub fn next_value(&mut self) -> Result<u64, PacketError> {
+        let value = self.next;
+        self.next = self.next.checked_add(1).ok_or(PacketError::EncryptionFailed)?;
+        Ok(value)
+    }
+}
+
+/// Encrypt a payload, the output carries the 16-byte auth tag at the end
+#[cfg_attr(not(feature = "encryption"), allow(unused_variables))]
+pub fn encrypt(
+    level: EncryptionLevel,
+    key: &[u8; KEY_SIZE],
+    nonce: &[u8; NONCE_SIZE],
+    plaintext: &[u8],
+) -> Result<Vec<u8>, PacketError> {
+    match level {
+        EncryptionLevel::None => Ok(plaintext.to_vec()),
+        #[cfg(feature = "encryption")]
+        EncryptionLevel::ChaCha20 => ChaCha20Poly1305::new(key.into())
+            .encrypt(nonce.into(), plaintext)
+            .map_err(|_| PacketError::EncryptionFailed),
+        #[cfg(feature = "encryption")]
+        EncryptionLevel::Aes256 => Aes256Gcm::new(key.into())
+            .encrypt(nonce.into(), plaintext)
+            .map_err(|_| PacketError::EncryptionFailed),
+        #[cfg(not(feature = "encryption"))]
+        _ => Err(PacketError::EncryptionUnsupported),
+    }
+}
+
+/// Decrypt and authenticate, wrong key or tampered ciphertext -> DecryptionFailed
+#[cfg_attr(not(feature = "encryption"), allow(unused_variables))]
+pub fn decrypt(
+    level: EncryptionLevel,
+    key: &[u8; KEY_SIZE],
+    nonce: &[u8; NONCE_SIZE],
+    ciphertext: &[u8],
+) -> Result<Vec<u8>, PacketError> {
+    match level {
+        EncryptionLevel::None => Ok(ciphertext.to_vec()),
+        #[cfg(feature = "encryption")]
+        EncryptionLevel::ChaCha20 => ChaCha20Poly1305::new(key.into())
+            .decrypt(nonce.into(), ciphertext)
+            .map_err(|_| PacketError::DecryptionFailed),
+        #[cfg(feature = "encryption")]
+        EncryptionLevel::Aes256 => Aes256Gcm::new(key.into())
+            .decrypt(nonce.into(), ciphertext)
+            .map_err(|_| PacketError::DecryptionFailed),
+        #[cfg(not(feature = "encryption"))]
+        _ => Err(PacketError::EncryptionUnsupported),
+    }
+}
+
+impl Packet {
+    /// Encrypt the (plaintext) payload in place, set the encryption flag and reseal
+    /// Uses the nonce counter in the extended header if one is set, the sequence otherwise,
+    /// prefer encrypt_payload_counted
+    pub fn encrypt_payload(
+        &mut self,
+        level: EncryptionLevel,
+        key: &[u8; KEY_SIZE],
+    ) -> Result<(), PacketError> {
+        let nonce = self.nonce();
+        self.payload = encrypt(level, key, &nonce, &self.payload)?;
+        self.flags.set_encryption(level);
+        self.reseal();
+        Ok(())
+    }
+
+    /// Encrypt with the next value of `counter`, which is recorded in the extended header
+    pub fn encrypt_payload_counted(
+        &mut self,
+        level: EncryptionLevel,
+        key: &[u8; KEY_SIZE],
+        counter: &mut NonceCounter,
+    ) -> Result<(), PacketError> {
+        self.ext.nonce_counter = Some(counter.next_value()?);
+        self.encrypt_payload(level, key)
+    }
+
+    /// Decrypt the payload according to the packet's flags
+    pub fn decrypt_payload(&self, key: &[u8; KEY_SIZE]) -> Result<Vec<u8>, PacketError> {
+        decrypt(self.flags.encryption(), key, &self.nonce(), &self.payload)
+    }
+
+    /// Decrypted copy for a trusted network behind a gateway: plaintext payload, encryption
+    /// flag cleared, resealed. Padding and compression markers stay, they describe what's
+    /// under the encryption; the nonce counter and signature go
+    pub fn into_plaintext(self, key: &[u8; KEY_SIZE]) -> Result<Packet, PacketError> {
+        let plaintext = self.decrypt_payload(key)?;
+        let mut packet = self.rebuild_with_payload(plaintext)?;
+        packet.ext.padded = self.ext.padded;
+        packet.ext.fragment_compressed = self.ext.fragment_compressed;
+        packet.ext.decompressed_size = self.ext.decompressed_size;
+        packet.ext.nonce_counter = None;
+        packet.flags.set_encryption(EncryptionLevel::None);
+        packet.reseal();
+        Ok(packet)
+    }
+
+    fn nonce(&self) -> [u8; NONCE_SIZE] {
+        match self.ext.nonce_counter {
+            Some(counter) => counter_nonce(&self.session_id, counter),
+            None => packet_nonce(&self.session_id, self.sequence),
+        }
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; KEY_SIZE] = [0x42; KEY_SIZE];
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn test_encrypt_decrypt_roundtrip() {
+        for level in [EncryptionLevel::ChaCha20, EncryptionLevel::Aes256] {
+            let mut packet = Packet::new(SessionId::new(), Intent::Search, b"private query".to_vec());
+            packet.encrypt_payload(level, &KEY).unwrap();
+
+            assert_ne!(packet.payload, b"private query".to_vec());
+            assert_eq!(packet.flags.encryption(), level);
+
+            let recovered = Packet::from_bytes(&packet.to_bytes()).unwrap();
+            assert_eq!(recovered.decrypt_payload(&KEY).unwrap(), b"private query".to_vec());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn test_nonce_counter_survives_sequence_reset() {
+        let session_id = SessionId::new();
+        let mut counter = NonceCounter::new();
+
+        // same session, same sequence (say, after a reconnect), same plaintext
+        let mut first = Packet::new(session_id, Intent::Search, b"same query".to_vec());
+        let mut second = first.clone();
+        first.encrypt_payload_counted(EncryptionLevel::ChaCha20, &KEY, &mut counter).unwrap();
+        second.encrypt_payload_counted(EncryptionLevel::ChaCha20, &KEY, &mut counter).unwrap();
+
+        assert_eq!(first.sequence, second.sequence);
+        assert_ne!(first.ext.nonce_counter, second.ext.nonce_counter);
+        assert_ne!(first.payload, second.payload);
+
+        // the counter goes over the wire, so the receiver decrypts without extra state
+        for packet in [first, second] {
+            let recovered = Packet::from_bytes(&packet.to_bytes()).unwrap();
+            assert_eq!(recovered.decrypt_payload(&KEY).unwrap(), b"same query".to_vec());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn test_into_plaintext() {
+        let mut packet = Packet::new(SessionId::new(), Intent::Search, b"private query".to_vec());
+        packet.pad(64).unwrap();
+        let padded = packet.payload.clone();
+        packet
+            .encrypt_payload_counted(EncryptionLevel::ChaCha20, &KEY, &mut NonceCounter::new())
+            .unwrap();
+
+        let received = Packet::from_bytes(&packet.to_bytes()).unwrap();
+        assert!(received.clone().into_plaintext(&[0u8; KEY_SIZE]).is_err());
+
+        let plain = received.into_plaintext(&KEY).unwrap();
+        assert_eq!(plain.flags.encryption(), EncryptionLevel::None);
+        assert_eq!(plain.payload, padded);
+        assert_eq!(plain.ext.nonce_counter, None);
+        assert!(plain.verify());
+
+        let recovered = Packet::from_bytes(&plain.to_bytes()).unwrap();
+        assert_eq!(recovered.unpadded_payload().unwrap(), b"private query");
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn test_wrong_key_fails() {
+        let mut packet = Packet::new(SessionId::new(), Intent::Search, b"q".to_vec());
+        packet.encrypt_payload(EncryptionLevel::ChaCha20, &KEY).unwrap();
+
+        assert!(matches!(
+            packet.decrypt_payload(&[0u8; KEY_SIZE]),
+            Err(PacketError::DecryptionFailed)
+        ));
+    }
+
+    #[test]
+    #[cfg(not(feature = "encryption"))]
+    fn test_encrypted_packet_unsupported() {
+        // what an encrypting peer would send, header only matters here
+        let mut packet = Packet::new(SessionId::new(), Intent::Search, vec![0xAB; 32]);
+        packet.flags.set_encryption(EncryptionLevel::ChaCha20);
+        packet.reseal();
+
+        assert!(matches!(
+            Packet::from_bytes(&packet.to_bytes()),
+            Err(PacketError::EncryptionUnsupported)
+        ));
+        assert!(matches!(
+            packet.encrypt_payload(EncryptionLevel::Aes256, &KEY),
+            Err(PacketError::EncryptionUnsupported)
+        ));
+
+        // plaintext still works
+        let plain = Packet::new(SessionId::new(), Intent::Search, b"q".to_vec());
+        assert_eq!(plain.flags.encryption(), EncryptionLevel::None);
+        assert!(Packet::from_bytes(&plain.to_bytes()).is_ok());
+    }
+
+    #[test]
+    fn test_nonce_depends_on_sequence() {
+        let session = SessionId::new();
+        assert_ne!(packet_nonce(&session, 1), packet_nonce(&session, 2));
+    }
+}
@@ -0,0 +1,405 @@
+//the extended header, optional extra fields that sit between the fixed header and the payload
+//only there when flag bit 7 is set, so packets that don't need it keep the plain 36 byte layout
+//
+//wire layout:
+// 2 bytes   | block length (u16, big-endian) -size of all entries that follow
+// entries   | type (1 byte) | length (2 bytes, big-endian) | value (length bytes)
+//
+//unknown entry types from newer peers are kept as-is so a relay doesn't drop them
+
+use std::ops::RangeInclusive;
+
+use super::hasher::IntegrityAlgorithm;
+use super::packet::*;
+use super::types::{Sequence, TimeResolution};
+use crate::payload::nack::{decode_ranges, encode_ranges};
+
+pub const EXT_LENGTH_SIZE: usize = 2; // the u16 block length in front of the entries
+const ENTRY_HEADER_SIZE: usize = 3; // type + u16 length
+
+// entry types
+const EXT_SIGNATURE: u8 = 0x01;
+const EXT_PAYLOAD_CRC: u8 = 0x02;
+const EXT_PADDED: u8 = 0x03;
+const EXT_FINGERPRINT: u8 = 0x04;
+const EXT_FRAGMENT: u8 = 0x05;
+const EXT_TOTAL_SIZE: u8 = 0x06;
+const EXT_NONCE_COUNTER: u8 = 0x07;
+const EXT_COOKIE: u8 = 0x08;
+const EXT_FRAGMENT_COMPRESSED: u8 = 0x09;
+const EXT_INTEGRITY: u8 = 0x0A;
+const EXT_TIME_RESOLUTION: u8 = 0x0B;
+const EXT_ACKS: u8 = 0x0C;
+const EXT_DEADLINE: u8 = 0x0D;
+const EXT_CORRELATION_ID: u8 = 0x0E;
+const EXT_RETRANSMISSION: u8 = 0x0F;
+const EXT_DECOMPRESSED_SIZE: u8 = 0x10;
+const EXT_MIGRATION_PROOF: u8 = 0x11;
+
+pub const SIGNATURE_SIZE: usize = 64;
+pub const CORRELATION_ID_SIZE: usize = 16;
+pub const MIGRATION_PROOF_SIZE: usize = 32;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExtendedHeader {
+    /// Ed25519 signature over the packet hash
+    /// Not covered by the hash itself (it signs the hash)
+    pub signature: Option<[u8; SIGNATURE_SIZE]>,
+
+    /// CRC32 of the payload, cheap to check while the payload streams in
+    pub payload_crc: Option<u32>,
+
+    /// Payload is padded (see padding.rs), carries no value on the wire
+    pub padded: bool,
+
+    /// xxHash32 of the payload for cache lookups (see fingerprint.rs)
+    pub fingerprint: Option<u32>,
+
+    /// Which piece of which message this is (see fragment.rs)
+    pub fragment: Option<FragmentInfo>,
+
+    /// Size of the whole message in bytes, sent on the first fragment for progress reporting
+    pub total_size: Option<u64>,
+
+    /// Per-key nonce counter the payload was encrypted under (see encryption.rs)
+    pub nonce_counter: Option<u64>,
+
+    /// This fragment's payload was compressed on its own, per the flags (see FragmentStream)
+    /// Carries no value on the wire
+    pub fragment_compressed: bool,
+
+    /// Hash algorithm the packet is sealed with, None is SHA256 (see hasher.rs)
+    pub integrity: Option<IntegrityAlgorithm>,
+
+    /// Unit of the timestamp, None is milliseconds
+    pub time_resolution: Option<TimeResolution>,
+
+    /// Payload length before compression, so the receiver can size its buffer up front
+    pub decompressed_size: Option<u32>,
+
+    /// Resent after a Nack (see retransmit.rs), carries no value on the wire
+    pub retransmission: bool,
+
+    /// Tracing id shared by a request and its responses
+    pub correlation_id: Option<[u8; CORRELATION_ID_SIZE]>,
+
+    /// Wall clock time (ms) after which the packet isn't worth sending anymore
+    pub deadline_ms: Option<u64>,
+
+    /// Acks riding along on a packet going the other way, same ranges an Ack carries (see ack.rs)
+    pub acks: Option<Vec<RangeInclusive<Sequence>>>,
+
+    /// Handshake cookie, handed out in HandshakeAck and echoed in HandshakeInit (see cookie.rs)
+    pub cookie: Option<Vec<u8>>,
+
+    /// HMAC proving a HandshakeInit moves an existing session to a new address (see migration.rs)
+    pub migration_proof: Option<[u8; MIGRATION_PROOF_SIZE]>,
+
+    /// Entries this version doesn't understand, (type, value)
+    pub unknown: Vec<(u8, Vec<u8>)>,
+}
+
+impl ExtendedHeader {
+    /// Nothing to send -> packet goes out without an extended header at all
+    pub fn is_empty(&self) -> bool {
+        self.signature.is_none()
+            && self.payload_crc.is_none()
+            && !self.padded
+            && self.fingerprint.is_none()
+            && self.fragment.is_none()
+            && self.total_size.is_none()
+            && self.nonce_counter.is_none()
+            && !self.fragment_compressed
+            && self.integrity.is_none()
+            && self.time_resolution.is_none()
+            && self.acks.is_none()
+            && self.deadline_ms.is_none()
+            && self.correlation_id.is_none()
+            && !self.retransmission
+            && self.decompressed_size.is_none()
+            && self.cookie.is_none()
+            && self.migration_proof.is_none()
+            && self.unknown.is_empty()
+    }
+
+    /// Serialize the block including its u16 length prefix
+    /// The hash covers the block without the signature, so it's skippable here
+    /// Panics if an entry or the block doesn't fit its u16 length, anything that takes sizes
+    /// from the caller (piggyback_acks, try_to_bytes) checks with try_encode first
+    pub fn encode(&self, with_signature: bool) -> Vec<u8> {
+        self.try_encode(with_signature)
+            .expect("extended header entry or block over u16::MAX bytes")
+    }
+
+    /// encode() that fails with InvalidExtension instead of writing a length that doesn't fit in u16
+    pub fn try_encode(&self, with_signature: bool) -> Result<Vec<u8>, PacketError> {
+        let mut entries = Vec::new();
+
+        if with_signature {
+            if let Some(signature) = &self.signature {
+                push_entry(&mut entries, EXT_SIGNATURE, signature)?;
+            }
+        }
+
+        if let Some(crc) = self.payload_crc {
+            push_entry(&mut entries, EXT_PAYLOAD_CRC, &crc.to_be_bytes())?;
+        }
+
+        if self.padded {
+            push_entry(&mut entries, EXT_PADDED, &[])?;
+        }
+
+        if let Some(fingerprint) = self.fingerprint {
+            push_entry(&mut entries, EXT_FINGERPRINT, &fingerprint.to_be_bytes())?;
+        }
+
+        if let Some(fragment) = &self.fragment {
+            push_entry(&mut entries, EXT_FRAGMENT, &fragment.to_bytes())?;
+        }
+
+        if let Some(total_size) = self.total_size {
+            push_entry(&mut entries, EXT_TOTAL_SIZE, &total_size.to_be_bytes())?;
+        }
+
+        if let Some(counter) = self.nonce_counter {
+            push_entry(&mut entries, EXT_NONCE_COUNTER, &counter.to_be_bytes())?;
+        }
+
+        if self.fragment_compressed {
+            push_entry(&mut entries, EXT_FRAGMENT_COMPRESSED, &[])?;
+        }
+
+        if let Some(integrity) = self.integrity {
+            push_entry(&mut entries, EXT_INTEGRITY, &[integrity.to_u8()])?;
+        }
+
+        if let Some(resolution) = self.time_resolution {
+            push_entry(&mut entries, EXT_TIME_RESOLUTION, &[resolution.to_u8()])?;
+        }
+
+        if let Some(size) = self.decompressed_size {
+            push_entry(&mut entries, EXT_DECOMPRESSED_SIZE, &size.to_be_bytes())?;
+        }
+
+        if self.retransmission {
+            push_entry(&mut entries, EXT_RETRANSMISSION, &[])?;
+        }
+
+        if let Some(id) = &self.correlation_id {
+            push_entry(&mut entries, EXT_CORRELATION_ID, id)?;
+        }
+
+        if let Some(deadline) = self.deadline_ms {
+            push_entry(&mut entries, EXT_DEADLINE, &deadline.to_be_bytes())?;
+        }
+
+        if let Some(acks) = &self.acks {
+            push_entry(&mut entries, EXT_ACKS, &encode_ranges(acks))?;
+        }
+
+        if let Some(cookie) = &self.cookie {
+            push_entry(&mut entries, EXT_COOKIE, cookie)?;
+        }
+
+        if let Some(proof) = &self.migration_proof {
+            push_entry(&mut entries, EXT_MIGRATION_PROOF, proof)?;
+        }
+
+        for (kind, value) in &self.unknown {
+            push_entry(&mut entries, *kind, value)?;
+        }
+
+        let len = u16::try_from(entries.len()).map_err(|_| PacketError::InvalidExtension)?;
+        let mut block = Vec::with_capacity(EXT_LENGTH_SIZE + entries.len());
+        block.extend_from_slice(&len.to_be_bytes());
+        block.extend_from_slice(&entries);
+        Ok(block)
+    }
+
+    /// Size of the block on the wire (0 when there is no block)
+    pub fn wire_size(&self) -> usize {
+        if self.is_empty() {
+            0
+        } else {
+            self.encode(true).len()
+        }
+    }
+
+    /// Parse the entries of a block (everything after the u16 length)
+    pub fn decode(mut entries: &[u8]) -> Result<Self, PacketError> {
+        let mut ext = ExtendedHeader::default();
+
+        while !entries.is_empty() {
+            if entries.len() < ENTRY_HEADER_SIZE {
+                return Err(PacketError::InvalidExtension);
+            }
+            let kind = entries[0];
+            let len = u16::from_be_bytes([entries[1], entries[2]]) as usize;
+            let end = ENTRY_HEADER_SIZE + len;
+            if entries.len() < end {
+                return Err(PacketError::InvalidExtension);
+            }
+            let value = &entries[ENTRY_HEADER_SIZE..end];
+
+            match kind {
+                EXT_SIGNATURE => {
+                    let signature: [u8; SIGNATURE_SIZE] =
+                        value.try_into().map_err(|_| PacketError::InvalidExtension)?;
+                    ext.signature = Some(signature);
+                }
+                EXT_PAYLOAD_CRC => {
+                    let crc: [u8; 4] = value.try_into().map_err(|_| PacketError::InvalidExtension)?;
+                    ext.payload_crc = Some(u32::from_be_bytes(crc));
+                }
+                EXT_PADDED => ext.padded = true,
+                EXT_FINGERPRINT => {
+                    let fingerprint: [u8; 4] =
+                        value.try_into().map_err(|_| PacketError::InvalidExtension)?;
+                    ext.fingerprint = Some(u32::from_be_bytes(fingerprint));
+                }
+                EXT_FRAGMENT => ext.fragment = Some(FragmentInfo::from_bytes(value)?),
+                EXT_TOTAL_SIZE => {
+                    let total_size: [u8; 8] =
+                        value.try_into().map_err(|_| PacketError::InvalidExtension)?;
+                    ext.total_size = Some(u64::from_be_bytes(total_size));
+                }
+                EXT_NONCE_COUNTER => {
+                    let counter: [u8; 8] = value.try_into().map_err(|_| PacketError::InvalidExtension)?;
+                    ext.nonce_counter = Some(u64::from_be_bytes(counter));
+                }
+                EXT_COOKIE => ext.cookie = Some(value.to_vec()),
+                EXT_FRAGMENT_COMPRESSED => ext.fragment_compressed = true,
+                EXT_INTEGRITY => {
+                    let [byte] = value else {
+                        return Err(PacketError::InvalidExtension);
+                    };
+                    let integrity = IntegrityAlgorithm::from_u8(*byte).ok_or(PacketError::InvalidExtension)?;
+                    ext.integrity = Some(integrity);
+                }
+                EXT_TIME_RESOLUTION => {
+                    let [byte] = value else {
+                        return Err(PacketError::InvalidExtension);
+                    };
+                    let resolution = TimeResolution::from_u8(*byte).ok_or(PacketError::InvalidExtension)?;
+                    ext.time_resolution = Some(resolution);
+                }
+                EXT_DECOMPRESSED_SIZE => {
+                    let size: [u8; 4] = value.try_into().map_err(|_| PacketError::InvalidExtension)?;
+                    ext.decompressed_size = Some(u32::from_be_bytes(size));
+                }
+                EXT_RETRANSMISSION => ext.retransmission = true,
+                EXT_CORRELATION_ID => {
+                    let id: [u8; CORRELATION_ID_SIZE] =
+                        value.try_into().map_err(|_| PacketError::InvalidExtension)?;
+                    ext.correlation_id = Some(id);
+                }
+                EXT_DEADLINE => {
+                    let deadline: [u8; 8] = value.try_into().map_err(|_| PacketError::InvalidExtension)?;
+                    ext.deadline_ms = Some(u64::from_be_bytes(deadline));
+                }
+                EXT_ACKS => {
+                    let acks = decode_ranges(value).map_err(|_| PacketError::InvalidExtension)?;
+                    ext.acks = Some(acks);
+                }
+                EXT_MIGRATION_PROOF => {
+                    let proof: [u8; MIGRATION_PROOF_SIZE] =
+                        value.try_into().map_err(|_| PacketError::InvalidExtension)?;
+                    ext.migration_proof = Some(proof);
+                }
+                _ => ext.unknown.push((kind, value.to_vec())),
+            }
+
+            entries = &entries[end..];
+        }
+
+        Ok(ext)
+    }
+}
+
+/// Fragment entry: the message a fragment belongs to and where it goes
+/// 4 bytes group id | 2 bytes index | 2 bytes count, all big-endian
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FragmentInfo {
+    /// Random per message, shared by all its fragments
+    pub group: u32,
+    pub index: u16,
+    pub count: u16,
+}
+
+impl FragmentInfo {
+    fn to_bytes(self) -> [u8; 8] {
+        let mut out = [0u8; 8];
+        out[..4].copy_from_slice(&self.group.to_be_bytes());
+        out[4..6].copy_from_slice(&self.index.to_be_bytes());
+        out[6..].copy_from_slice(&self.count.to_be_bytes());
+        out
+    }
+
+    fn from_bytes(value: &[u8]) -> Result<Self, PacketError> {
+        let value: [u8; 8] = value.try_into().map_err(|_| PacketError::InvalidExtension)?;
+        Ok(FragmentInfo {
+            group: u32::from_be_bytes([value[0], value[1], value[2], value[3]]),
+            index: u16::from_be_bytes([value[4], value[5]]),
+            count: u16::from_be_bytes([value[6], value[7]]),
+        })
+    }
+}
+
+fn push_entry(out: &mut Vec<u8>, kind: u8, value: &[u8]) -> Result<(), PacketError> {
+    let len = u16::try_from(value.len()).map_err(|_| PacketError::InvalidExtension)?;
+    out.push(kind);
+    out.extend_from_slice(&len.to_be_bytes());
+    out.extend_from_slice(value);
+    Ok(())
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extended_header_roundtrip() {
+        let ext = ExtendedHeader {
+            signature: Some([7u8; SIGNATURE_SIZE]),
+            payload_crc: Some(0xDEADBEEF),
+            padded: true,
+            fingerprint: Some(0x0BADF00D),
+            fragment: Some(FragmentInfo {
+                group: 0xCAFE,
+                index: 2,
+                count: 5,
+            }),
+            total_size: Some(123_456),
+            nonce_counter: Some(99),
+            fragment_compressed: true,
+            integrity: Some(IntegrityAlgorithm::Crc32),
+            time_resolution: Some(TimeResolution::Micros),
+            acks: Some(vec![1..=40, 43..=43]),
+            deadline_ms: Some(1_700_000_000_250),
+            correlation_id: Some([0x3C; CORRELATION_ID_SIZE]),
+            retransmission: true,
+            decompressed_size: Some(4096),
+            cookie: Some(vec![0xC0; 40]),
+            migration_proof: Some([0x4D; MIGRATION_PROOF_SIZE]),
+            unknown: vec![(0xEE, vec![1, 2, 3])],
+        };
+
+        let block = ext.encode(true);
+        let recovered = ExtendedHeader::decode(&block[EXT_LENGTH_SIZE..]).unwrap();
+        assert_eq!(recovered, ext);
+    }
+
+    #[test]
+    fn test_extended_header_truncated_entry() {
+        // says 10 bytes of value, only 2 present
+        let entries = [0x01, 0x00, 0x0A, 0xAA, 0xBB];
+        assert!(matches!(
+            ExtendedHeader::decode(&entries),
+            Err(PacketError::InvalidExtension)
+        ));
+    }
+}
@@ -0,0 +1,69 @@
+//content fingerprint, a 4 byte xxHash32 of the payload carried in the extended header
+//caches use it as a cheap first lookup key: equal fingerprints -> then compare the real thing,
+//different fingerprints -> definitely different content. It's for dedup only, the 32 byte
+//hash is still what proves integrity (and it covers the fingerprint entry too)
+
+use xxhash_rust::xxh32::xxh32;
+
+use super::packet::*;
+
+const FINGERPRINT_SEED: u32 = 0;
+
+impl<const N: usize> Packet<N> {
+    /// Attach a fingerprint of the current payload and reseal
+    pub fn set_content_fingerprint(&mut self) {
+        self.ext.fingerprint = Some(fingerprint(&self.payload));
+        self.reseal();
+    }
+
+    /// The fingerprint the sender attached, None if it didn't
+    pub fn content_fingerprint(&self) -> Option<u32> {
+        self.ext.fingerprint
+    }
+}
+
+pub(super) fn fingerprint(payload: &[u8]) -> u32 {
+    xxh32(payload, FINGERPRINT_SEED)
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::{PacketBuilder, types::*};
+
+    fn fingerprinted(payload: &[u8]) -> Packet {
+        PacketBuilder::new(SessionId::new(), Intent::DataPush)
+            .payload(payload.to_vec())
+            .content_fingerprint()
+            .flags(Flags::new())
+            .insecure_allow_plaintext()
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_identical_payloads_share_fingerprint() {
+        let first = fingerprinted(b"the same document");
+        let second = fingerprinted(b"the same document");
+        let other = fingerprinted(b"another document");
+
+        assert_eq!(first.content_fingerprint(), second.content_fingerprint());
+        assert_ne!(first.content_fingerprint(), other.content_fingerprint());
+
+        // different sessions/timestamps, so the full hashes differ but both verify
+        assert_ne!(first.hash, second.hash);
+        let recovered = Packet::from_bytes(&first.to_bytes()).unwrap();
+        assert!(recovered.verify());
+        assert_eq!(recovered.content_fingerprint(), first.content_fingerprint());
+    }
+
+    #[test]
+    fn test_no_fingerprint_by_default() {
+        let packet = Packet::new(SessionId::new(), Intent::DataPush, b"plain".to_vec());
+        assert_eq!(packet.content_fingerprint(), None);
+    }
+}
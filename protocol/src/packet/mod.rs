@@ -0,0 +1,27 @@
+//everything about a single packet: the building blocks (types) and the wire format (packet)
+pub mod types;
+pub mod builder;
+pub mod compact;
+pub mod compression;
+pub mod cow;
+pub mod crc;
+pub mod encryption;
+pub mod extended;
+pub mod fingerprint;
+pub mod hasher;
+
+#[allow(clippy::module_inception)]
+pub mod packet;
+pub mod padding;
+pub mod signature;
+pub mod template;
+
+pub use builder::*;
+pub use compact::*;
+pub use cow::*;
+pub use crc::*;
+pub use extended::*;
+pub use hasher::*;
+pub use packet::*;
+pub use template::*;
+pub use types::*;
@@ -1,421 +1,2134 @@
-//this is the packet format showing how the data actually looks like on the wire
-//the layout is like this:
-// byte 0    | version (1 byte) -protocol version
-// byte 1-16 | session id 16 bytes -who is talking
-// byte 17   | intent (1 byte) -what do you want to do
-// byte 18   | priority (1 byte) -how important is this packet and how urgent
-// byte 19   | flags (1 byte) -extra info about the packet like compression encryption and all
-// byte 20-23| sequence number (4 bytes) -to keep track of packets
-// byte 24-27| payload length (4 bytes) -how much data is in the payload
-// byte 28-35| timestamp (8 bytes) -when was this packet sent
-// byte 36+  | payload (variable length) -the actual data being sent
-// last 32   | hash (32 bytes) -to verify data integrity
-
-//total header size is 36 bytes
-
-
-use super::types::*;//importing types from types module
-
-use std::time::{SystemTime, UNIX_EPOCH};//for timestamp generation
-
-
-
-
-
-//constants
-pub const HEADER_SIZE: usize = 36;
-pub const HASH_SIZE: usize = 32;
-pub const MIN_PACKET_SIZE: usize = HEADER_SIZE + HASH_SIZE;//minimum size of a valid packet since payload can be zero length
-pub const MAX_PAYLOAD_SIZE: usize = 10,485,760;//taking 10MB as max packet size for now
-pub const MAX_PACKET_SIZE:usize = HEADER_SIZE + MAX_PAYLOAD_SIZE + HASH_SIZE;//max packet size
-
-
-
-
-// ============================================================================
-// Flags - 1 byte
-// ============================================================================
-// Using bit flags to pack multiple booleans into 1 byte
-//
-// Bit layout:
-// 7 6 5 4 3 2 1 0 
-//           _____ Compression (3 bits(1,2,3) = 8 options)
-//       ___ Encryption (2 bits(4,5) = 4 options)
-//     _ Fragmented (1 bit(6))
-//   _ Ack Required (1 bit(7))
-//_ I am leaving this for future atp
-
-pub struct Flags(pub u8);// Doinng this for type safety, so we don't mix flags with other u8 values, voila newtype pattern
-
-impl Flags {
-    // New flags with default values
-    pub fn new() -> Self {
-        Flags(0)// Initial state with all flags cleared
-    }
-    // 8 states we are getting from 000 to 111
-    // compression type (bits 0-2)
-    pub fn set_compression(&mut self, compression: Compression) {// compression => enum
-        // Clear compression bits
-        self.0 &= 0b11111000;
-        // Set new compression
-        self.0 |= compression.to_u8() & 0b00000111;
-    }
-    
-    // Get compression type
-    pub fn compression(&self) -> Compression {
-        let comp_bits = self.0 & 0b00000111;
-        Compression::from_u8(comp_bits).unwrap_or(Compression::None)
-    }
-
-    // Set encryption level (bits 3-4)
-    pub fn set_encryption(&mut self, encryption: EncryptionLevel) {
-        // Clear encryption bits
-        self.0 &= 0b11100111;
-        // Set new encryption (shifted left 3 bits)
-        self.0 |= (encryption.to_u8() & 0b00000011) << 3; // why shift left 3? because 
-                                                          // bits 3 and 4 are for encryption and encryption.to_u8() gives us value not the position
-    }
-    
-    // Get encryption level
-    pub fn encryption(&self) -> EncryptionLevel {
-        let enc_bits = (self.0 >> 3) & 0b00000011;
-        EncryptionLevel::from_u8(enc_bits).unwrap_or(EncryptionLevel::None)
-    }
-    
-    // fragmented flag (bit 5)
-    // True if this packet is part of a larger message
-    pub fn set_fragmented(&mut self, fragmented: bool) {
-        if fragmented {
-            self.0 |= 0b00100000;
-        } else {
-            self.0 &= 0b11011111;
-        }
-    }
-    
-    // to check if packet is fragmented cause then we need to handle reassembly
-    pub fn is_fragmented(&self) -> bool {
-        (self.0 & 0b00100000) != 0
-    }
-    
-    // ack required flag (bit 6)
-    // True if sender expects acknowledgment
-    pub fn set_ack_required(&mut self, required: bool) {
-        if required {
-            self.0 |= 0b01000000;
-        } else {
-            self.0 &= 0b10111111;
-        }
-    }
-    
-    // Check if ack is required
-    pub fn ack_required(&self) -> bool {
-        (self.0 & 0b01000000) != 0
-    }
-}
-#[derive(Debug, Clone)]
-pub struct Packet{
-    pub version: u8 // maybe i will use a wrapper later if we add anything else which is also if type u8
-    
-    pub session_id: SessionId, // session identifier
-    
-    pub intent: Intent, // what this packet wants to do
-    
-    pub priority: Priority, // some might have less priority so we dont always have to hash them 
-
-    pub flags: Flags,
-
-    pub sequence: Sequence, // for reordering and duplicate detection
-
-    pub timestamp: u64, // timestamp of when this was created to hash and also to see if its a replay attack or any old session
-
-    pub payload: Vec<u8> // the actual data that the packet holds
-
-    pub hash: [u8; 32]
-}
-impl Packet {
-    pub fn new(session_id: SessionId, intent: Intent, payload: Vec<u8>) -> Self {
-        let mut flags = Flags::new();
-        flags.set_compression(Compression::Lz4);
-        flags.set_encryption(EncryptionLevel::ChaCha20);
-        let mut packet=Packet{
-            version:FDP_Version,
-            session_id,
-            intent,
-            priority: Priority::Normal,//sane default priority, we can change it later based on intent or other factors
-            flags,
-            sequence: 0, // sequence will be set by the connection manager when sending
-            timestamp:Self::current_timestamp(),
-        }   
-    }
-    /// Get current timestamp in milliseconds
-    fn current_timestamp() -> u64 {
-        SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_millis() as u64
-    }
-    
-    /// Calculate SHA256 hash of packet (except the hash field itself)
-    fn calculate_hash(&self) -> [u8; 32] {
-        use sha2::{Sha256, Digest};
-        
-        let mut hasher = Sha256::new();
-        
-        // Hash all fields except the hash itself
-        hasher.update(&[self.version]);
-        hasher.update(self.session_id.as_bytes());
-        hasher.update(&[self.intent.to_u8()]);
-        hasher.update(&[self.priority.0]);
-        hasher.update(&[self.flags.0]);
-        hasher.update(&self.sequence.to_be_bytes());
-        hasher.update(&self.timestamp.to_be_bytes());
-        hasher.update(&(self.payload.len() as u32).to_be_bytes());
-        hasher.update(&self.payload);
-        
-        let result = hasher.finalize();
-        let mut hash = [0u8; 32];
-        hash.copy_from_slice(&result);
-        hash
-    }
-    
-    /// Verify packet integrity
-    pub fn verify(&self) -> bool {
-        let calculated_hash = self.calculate_hash();
-        calculated_hash == self.hash
-    }
-    
-    /// Serialize packet to bytes for sending over network
-    /// 
-    /// This is THE critical function - it converts our struct to raw bytes
-    pub fn to_bytes(&self) -> Vec<u8> {
-        let total_size = HEADER_SIZE + self.payload.len() + HASH_SIZE;
-        let mut buffer = Vec::with_capacity(total_size);
-        
-        // Byte 0: Version
-        buffer.push(self.version);
-        
-        // Bytes 1-16: Session ID
-        buffer.extend_from_slice(self.session_id.as_bytes());
-        
-        // Byte 17: Intent
-        buffer.push(self.intent.to_u8());
-        
-        // Byte 18: Priority
-        buffer.push(self.priority.0);
-        
-        // Byte 19: Flags
-        buffer.push(self.flags.0);
-        
-        // Bytes 20-23: Sequence number (big-endian)
-        buffer.extend_from_slice(&self.sequence.to_be_bytes());
-        
-        // Bytes 24-27: Payload length (big-endian)
-        buffer.extend_from_slice(&(self.payload.len() as u32).to_be_bytes());
-        
-        // Bytes 28-35: Timestamp (big-endian)
-        buffer.extend_from_slice(&self.timestamp.to_be_bytes());
-        
-        // Bytes 36+: Payload
-        buffer.extend_from_slice(&self.payload);
-        
-        // Last 32 bytes: Hash
-        buffer.extend_from_slice(&self.hash);
-        
-        buffer
-    }
-    
-    /// Deserialize bytes back into a Packet
-    /// 
-    /// This is the reverse - turn raw bytes into our struct
-    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PacketError> {
-        // Minimum size check
-        if bytes.len() < MIN_PACKET_SIZE {
-            return Err(PacketError::TooSmall);
-        }
-        
-        // Maximum size check
-        if bytes.len() > MAX_PACKET_SIZE {
-            return Err(PacketError::TooLarge);
-        }
-        
-        // Parse header
-        let version = bytes[0];
-        
-        // Check version compatibility
-        if version != FDP_VERSION {
-            return Err(PacketError::UnsupportedVersion(version));
-        }
-        
-        // Session ID
-        let mut session_bytes = [0u8; 16];
-        session_bytes.copy_from_slice(&bytes[1..17]);
-        let session_id = SessionId::from_bytes(session_bytes);
-        
-        // Intent
-        let intent = Intent::from_u8(bytes[17])
-            .ok_or(PacketError::InvalidIntent(bytes[17]))?;
-        
-        // Priority
-        let priority = Priority(bytes[18]);
-        
-        // Flags
-        let flags = Flags(bytes[19]);
-        
-        // Sequence
-        let mut seq_bytes = [0u8; 4];
-        seq_bytes.copy_from_slice(&bytes[20..24]);
-        let sequence = u32::from_be_bytes(seq_bytes);
-        
-        // Payload length
-        let mut len_bytes = [0u8; 4];
-        len_bytes.copy_from_slice(&bytes[24..28]);
-        let payload_len = u32::from_be_bytes(len_bytes) as usize;
-        
-        // Timestamp
-        let mut time_bytes = [0u8; 8];
-        time_bytes.copy_from_slice(&bytes[28..36]);
-        let timestamp = u64::from_be_bytes(time_bytes);
-        
-        // Verify payload length matches actual data
-        let expected_total = HEADER_SIZE + payload_len + HASH_SIZE;
-        if bytes.len() != expected_total {
-            return Err(PacketError::LengthMismatch);
-        }
-        
-        // Extract payload
-        let payload = bytes[36..36 + payload_len].to_vec();
-        
-        // Extract hash
-        let mut hash = [0u8; 32];
-        hash.copy_from_slice(&bytes[36 + payload_len..]);
-        
-        let packet = Packet {
-            version,
-            session_id,
-            intent,
-            priority,
-            flags,
-            sequence,
-            timestamp,
-            payload,
-            hash,
-        };
-        
-        // Verify integrity
-        if !packet.verify() {
-            return Err(PacketError::InvalidHash);
-        }
-        
-        Ok(packet)
-    }
-    
-    /// Get the size of this packet in bytes
-    pub fn size(&self) -> usize {
-        HEADER_SIZE + self.payload.len() + HASH_SIZE
-    }
-}
-
-#[derive(Debug)]
-pub enum PacketError {
-    TooSmall,
-    TooLarge,
-    UnsupportedVersion(u8),
-    InvalidIntent(u8),
-    LengthMismatch,
-    InvalidHash,
-}
-
-impl std::fmt::Display for PacketError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self {
-            PacketError::TooSmall => write!(f, "Packet too small"),
-            PacketError::TooLarge => write!(f, "Packet too large"),
-            PacketError::UnsupportedVersion(v) => write!(f, "Unsupported version: {}", v),
-            PacketError::InvalidIntent(i) => write!(f, "Invalid intent: {}", i),
-            PacketError::LengthMismatch => write!(f, "Payload length mismatch"),
-            PacketError::InvalidHash => write!(f, "Hash verification failed"),
-        }
-    }
-}
-
-impl std::error::Error for PacketError {}
-
-// ============================================================================
-// TESTS
-// ============================================================================
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    
-    #[test]
-    fn test_packet_roundtrip() {
-        // Create a packet
-        let session = SessionId::new();
-        let payload = b"Hello, FDP!".to_vec();
-        let packet = Packet::new(session, Intent::Search, payload.clone());
-        
-        // Serialize to bytes
-        let bytes = packet.to_bytes();
-        
-        // Deserialize back
-        let recovered = Packet::from_bytes(&bytes).unwrap();
-        
-        // Verify everything matches
-        assert_eq!(packet.version, recovered.version);
-        assert_eq!(packet.session_id, recovered.session_id);
-        assert_eq!(packet.intent, recovered.intent);
-        assert_eq!(packet.payload, recovered.payload);
-        assert_eq!(packet.hash, recovered.hash);
-    }
-    
-    #[test]
-    fn test_flags() {
-        let mut flags = Flags::new();
-        
-        // Set compression
-        flags.set_compression(Compression::Zstd);
-        assert_eq!(flags.compression(), Compression::Zstd);
-        
-        // Set encryption
-        flags.set_encryption(EncryptionLevel::Aes256);
-        assert_eq!(flags.encryption(), EncryptionLevel::Aes256);
-        
-        // Set fragmented
-        flags.set_fragmented(true);
-        assert!(flags.is_fragmented());
-        
-        // Set ack required
-        flags.set_ack_required(true);
-        assert!(flags.ack_required());
-        
-        // Make sure compression didn't change when we set other flags
-        assert_eq!(flags.compression(), Compression::Zstd);
-    }
-    
-    #[test]
-    fn test_hash_verification() {
-        let session = SessionId::new();
-        let packet = Packet::new(session, Intent::Ping, vec![1, 2, 3]);
-        
-        // Should verify correctly
-        assert!(packet.verify());
-        
-        // Tamper with payload
-        let mut tampered = packet.clone();
-        tampered.payload[0] = 99;
-        
-        // Should fail verification
-        assert!(!tampered.verify());
-    }
-    
-    #[test]
-    fn test_packet_size() {
-        let session = SessionId::new();
-        let payload = vec![0u8; 1000]; // 1KB payload
-        let packet = Packet::new(session, Intent::DataPush, payload);
-        
-        let expected_size = HEADER_SIZE + 1000 + HASH_SIZE;
-        assert_eq!(packet.size(), expected_size);
-    }
+//this is the packet format showing how the data actually looks like on the wire
+//the layout is like this:
+// byte 0    | version (1 byte) -protocol version
+// byte 1-16 | session id 16 bytes -who is talking
+// byte 17   | intent (1 byte) -what do you want to do
+// byte 18   | priority (1 byte) -how important is this packet and how urgent
+// byte 19   | flags (1 byte) -extra info about the packet like compression encryption and all
+// byte 20-23| sequence number (4 bytes) -to keep track of packets
+// byte 24-27| payload length (4 bytes) -how much data is in the payload
+// byte 28-35| timestamp (8 bytes) -when was this packet sent
+// byte 36+  | payload (variable length) -the actual data being sent
+// last 32   | hash (32 bytes) -to verify data integrity
+
+//total header size is 36 bytes (with the default 16 byte session id, see header_size for other lengths)
+//if flag bit 7 is set an extended header (see extended.rs) sits between the header and the payload
+
+
+use super::types::*;//importing types from types module
+use super::extended::*;
+use super::hasher::{AnyHasher, Crc32, Hasher, IntegrityAlgorithm};
+use super::compact::COMPACT_VERSION;
+
+use std::time::{SystemTime, UNIX_EPOCH};//for timestamp generation
+use std::io::IoSlice;//for vectored writes
+use std::ops::Range;
+use std::borrow::Cow;
+use subtle::ConstantTimeEq;//hash comparisons that don't leak timing
+use hmac::Mac;
+
+
+
+
+
+//constants
+pub const HEADER_SIZE: usize = header_size(SESSION_ID_SIZE);
+pub const HASH_SIZE: usize = 32;
+pub const MIN_PACKET_SIZE: usize = HEADER_SIZE + HASH_SIZE;//minimum size of a valid packet since payload can be zero length
+pub const MAX_PAYLOAD_SIZE: usize = 10_485_760;//taking 10MB as max packet size for now
+pub const MAX_PACKET_SIZE:usize = HEADER_SIZE + MAX_PAYLOAD_SIZE + HASH_SIZE;//max packet size
+
+/// Fixed header size for a given session id length
+/// everything but the session id is 20 bytes: version, intent, priority, flags, sequence, length, timestamp
+pub const fn header_size(session_id_len: usize) -> usize {
+    20 + session_id_len
+}
+
+
+
+
+// ============================================================================
+// Flags - 1 byte
+// ============================================================================
+// Using bit flags to pack multiple booleans into 1 byte
+//
+// Bit layout:
+// 7 6 5 4 3 2 1 0 
+//           _____ Compression (3 bits(1,2,3) = 8 options)
+//       ___ Encryption (2 bits(4,5) = 4 options)
+//     _ Fragmented (1 bit(6))
+//   _ Ack Required (1 bit(7))
+//_ Extended header present (1 bit(8)) - set on the wire when Packet::ext has anything in it
+//
+// Every setter clears and sets only its own bits, a bit we don't know about is carried through untouched
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Flags(pub u8);// Doinng this for type safety, so we don't mix flags with other u8 values, voila newtype pattern
+
+impl Flags {
+    // New flags with default values
+    pub fn new() -> Self {
+        Flags(0)// Initial state with all flags cleared
+    }
+    // 8 states we are getting from 000 to 111
+    // compression type (bits 0-2)
+    // Auto has no wire value, resolve it against the payload first (compress_payload does)
+    // panics on Auto in every build, writing it would mislabel the payload as uncompressed
+    pub fn set_compression(&mut self, compression: Compression) {// compression => enum
+        assert_ne!(compression, Compression::Auto, "Auto is resolved before it reaches the flags");
+        // Clear compression bits
+        self.0 &= 0b11111000;
+        // Set new compression
+        self.0 |= compression.to_u8() & 0b00000111;
+    }
+    
+    // Get compression type
+    pub fn compression(&self) -> Compression {
+        let comp_bits = self.0 & 0b00000111;
+        Compression::from_u8(comp_bits).unwrap_or(Compression::None)
+    }
+
+    // Set encryption level (bits 3-4)
+    pub fn set_encryption(&mut self, encryption: EncryptionLevel) {
+        // Clear encryption bits
+        self.0 &= 0b11100111;
+        // Set new encryption (shifted left 3 bits)
+        self.0 |= (encryption.to_u8() & 0b00000011) << 3; // why shift left 3? because 
+                                                          // bits 3 and 4 are for encryption and encryption.to_u8() gives us value not the position
+    }
+    
+    // Get encryption level
+    pub fn encryption(&self) -> EncryptionLevel {
+        let enc_bits = (self.0 >> 3) & 0b00000011;
+        EncryptionLevel::from_u8(enc_bits).unwrap_or(EncryptionLevel::None)
+    }
+    
+    // fragmented flag (bit 5)
+    // True if this packet is part of a larger message
+    pub fn set_fragmented(&mut self, fragmented: bool) {
+        if fragmented {
+            self.0 |= 0b00100000;
+        } else {
+            self.0 &= 0b11011111;
+        }
+    }
+    
+    // to check if packet is fragmented cause then we need to handle reassembly
+    pub fn is_fragmented(&self) -> bool {
+        (self.0 & 0b00100000) != 0
+    }
+    
+    // ack required flag (bit 6)
+    // True if sender expects acknowledgment
+    pub fn set_ack_required(&mut self, required: bool) {
+        if required {
+            self.0 |= 0b01000000;
+        } else {
+            self.0 &= 0b10111111;
+        }
+    }
+    
+    // Check if ack is required
+    pub fn ack_required(&self) -> bool {
+        (self.0 & 0b01000000) != 0
+    }
+    
+    // extended header flag (bit 7)
+    // Packet keeps this in sync with its ext field when it goes on the wire
+    pub fn set_extended(&mut self, extended: bool) {
+        if extended {
+            self.0 |= 0b10000000;
+        } else {
+            self.0 &= 0b01111111;
+        }
+    }
+    
+    // Check if an extended header follows the fixed header
+    pub fn has_extended(&self) -> bool {
+        (self.0 & 0b10000000) != 0
+    }
+    
+    // what Packet::new and PacketBuilder start with: Lz4 + ChaCha20,
+    // no encryption when the crate is built without the ciphers
+    pub fn packet_default() -> Self {
+        let mut flags = Flags::new();
+        flags.set_compression(Compression::Lz4);
+        #[cfg(feature = "encryption")]
+        flags.set_encryption(EncryptionLevel::ChaCha20);
+        flags
+    }
+    
+    // compression() and encryption() quietly fall back to None on bit patterns we don't know,
+    // this is the strict version: every sub-field has to decode to something real
+    // decoding every field and setting it again gives back the same byte exactly when this passes,
+    // otherwise compression 5-7 and encryption 3 come back canonicalized to None (0)
+    pub fn validate(&self) -> Result<(), PacketError> {
+        if Compression::from_u8(self.0 & 0b00000111).is_none() {
+            return Err(PacketError::InvalidFlags(self.0));
+        }
+        if EncryptionLevel::from_u8((self.0 >> 3) & 0b00000011).is_none() {
+            return Err(PacketError::InvalidFlags(self.0));
+        }
+        Ok(())
+    }
+}
+
+impl Default for Flags {
+    fn default() -> Self {
+        Flags::new()
+    }
+}
+
+// N is the session id length, 16 unless a deployment picked something else (see SessionId)
+// peers have to agree on it, it changes where every field after the session id sits
+#[derive(Debug, Clone)]
+pub struct Packet<const N: usize = SESSION_ID_SIZE>{
+    pub version: u8, // maybe i will use a wrapper later if we add anything else which is also if type u8
+    
+    pub session_id: SessionId<N>, // session identifier
+    
+    pub intent: Intent, // what this packet wants to do
+    
+    pub priority: Priority, // some might have less priority so we dont always have to hash them 
+
+    pub flags: Flags,
+
+    pub sequence: Sequence, // for reordering and duplicate detection
+
+    pub timestamp: u64, // timestamp of when this was created to hash and also to see if its a replay attack or any old session
+
+    pub ext: ExtendedHeader, // optional extra fields, empty for most packets
+
+    pub payload: Vec<u8>, // the actual data that the packet holds
+
+    pub hash: [u8; 32],
+
+    // set by the typed setters below, cleared by reseal()
+    // not on the wire, it only catches "changed a field, forgot to reseal" before sending
+    dirty: bool,
+    
+    // fixed header + extended header exactly as they go on the wire, rebuilt by reseal()
+    // kept around so to_iovecs can hand out a slice of it
+    header: Vec<u8>,
+    
+    // payload length before compress_payload ran, local only (see compression_ratio)
+    pub(super) uncompressed_len: Option<usize>,
+}
+impl<const N: usize> Packet<N> {
+    /// Fixed header size for this session id length (36 for the default)
+    pub const HEADER_LEN: usize = header_size(N);
+    
+    pub fn new(session_id: SessionId<N>, intent: Intent, payload: Vec<u8>) -> Self {
+        let flags = Flags::packet_default();
+        let mut packet=Packet{
+            version:FDP_VERSION,
+            session_id,
+            intent,
+            priority: Priority::NORMAL,//sane default priority, we can change it later based on intent or other factors
+            flags,
+            sequence: 0, // sequence will be set by the connection manager when sending
+            timestamp:Self::current_timestamp(TimeResolution::Millis),
+            ext: ExtendedHeader::default(),
+            payload,
+            hash: [0u8; 32],
+            dirty: false,
+            header: Vec::new(),
+            uncompressed_len: None,
+        };
+        packet.reseal();
+        packet
+    }
+    
+    /// Assemble a packet from raw field values, hash included, without checking or resealing anything
+    /// Meant for crafting broken packets in tests, everything else should go through new() or the builder
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_parts(
+        version: u8,
+        session_id: SessionId<N>,
+        intent: Intent,
+        priority: Priority,
+        flags: Flags,
+        sequence: Sequence,
+        timestamp: u64,
+        payload: Vec<u8>,
+        hash: [u8; 32],
+    ) -> Self {
+        let mut packet = Packet {
+            version,
+            session_id,
+            intent,
+            priority,
+            flags,
+            sequence,
+            timestamp,
+            ext: ExtendedHeader::default(),
+            payload,
+            hash,
+            dirty: false,
+            header: Vec::new(),
+            uncompressed_len: None,
+        };
+        packet.refresh_header();
+        packet
+    }
+    /// Get current timestamp in the given resolution
+    fn current_timestamp(resolution: TimeResolution) -> u64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap();
+        match resolution {
+            TimeResolution::Millis => now.as_millis() as u64,
+            TimeResolution::Micros => now.as_micros() as u64,
+        }
+    }
+    
+    /// Unit of `timestamp` (from the extended header, milliseconds if absent)
+    pub fn time_resolution(&self) -> TimeResolution {
+        self.ext.time_resolution.unwrap_or_default()
+    }
+    
+    /// Stamp the packet with the current time in `resolution` and reseal
+    /// The resolution goes in the extended header so the receiver reads the timestamp right
+    pub fn restamp(&mut self, resolution: TimeResolution) {
+        // milliseconds is what no entry means, keep such packets without one
+        self.ext.time_resolution = (resolution != TimeResolution::Millis).then_some(resolution);
+        self.timestamp = Self::current_timestamp(resolution);
+        self.reseal();
+    }
+    
+    /// The timestamp in milliseconds, whatever resolution it was sent in
+    pub fn timestamp_ms(&self) -> u64 {
+        self.timestamp / self.time_resolution().per_ms()
+    }
+    
+    /// Mark the packet as a resend and reseal, so the hash stays valid with the marker
+    /// A signature made before has to be redone, it signed the old hash
+    pub fn mark_retransmission(&mut self) {
+        self.ext.retransmission = true;
+        self.reseal();
+    }
+    
+    /// Sent before, this is a copy going out again (for stats and duplicate handling)
+    pub fn is_retransmission(&self) -> bool {
+        self.ext.retransmission
+    }
+    
+    /// Tracing id, responses built with error_for/success_for/pong_for carry the request's
+    pub fn correlation_id(&self) -> Option<[u8; CORRELATION_ID_SIZE]> {
+        self.ext.correlation_id
+    }
+    
+    /// Tag the packet with a tracing id, reseals
+    pub fn set_correlation_id(&mut self, id: [u8; CORRELATION_ID_SIZE]) {
+        self.ext.correlation_id = Some(id);
+        self.reseal();
+    }
+    
+    /// Don't send this packet after `deadline_ms` (wall clock), reseals
+    pub fn set_deadline(&mut self, deadline_ms: u64) {
+        self.ext.deadline_ms = Some(deadline_ms);
+        self.reseal();
+    }
+    
+    pub fn deadline_ms(&self) -> Option<u64> {
+        self.ext.deadline_ms
+    }
+    
+    /// Past its deadline as of `now_ms`, packets without one never are
+    pub fn is_past_deadline(&self, now_ms: u64) -> bool {
+        self.ext.deadline_ms.is_some_and(|deadline| now_ms > deadline)
+    }
+    
+    /// How long ago (ms) this packet was created, as of `now_ms`
+    /// Timestamps from the future (clock skew) count as age 0
+    pub fn age_ms(&self, now_ms: u64) -> u64 {
+        now_ms.saturating_sub(self.timestamp_ms())
+    }
+    
+    /// Older than `max_age_ms` as of `now_ms`, too old to act on (replays, stale requests)
+    /// Past its own deadline (a TTL from PacketBuilder) counts as expired too
+    pub fn is_expired(&self, max_age_ms: u64, now_ms: u64) -> bool {
+        self.age_ms(now_ms) > max_age_ms || self.is_past_deadline(now_ms)
+    }
+    
+    /// Calculate the hash of packet (except the hash field itself), SHA256 unless sealed with another Hasher
+    fn calculate_hash<H: Hasher>(&self) -> [u8; 32] {
+        let mut hasher = H::default();
+        self.hash_fields(|data| hasher.update(data));
+        hasher.finalize()
+    }
+    
+    /// calculate_hash for HMAC-SHA256, left unfinished so verify_hmac can compare in constant time
+    fn calculate_hmac(&self, key: &[u8; 32]) -> hmac::Hmac<sha2::Sha256> {
+        let mut mac = hmac::Hmac::<sha2::Sha256>::new_from_slice(key).expect("HMAC takes any key size");
+        self.hash_fields(|data| mac.update(data));
+        mac
+    }
+    
+    fn hash_fields(&self, mut update: impl FnMut(&[u8])) {
+        // Hash all fields except the hash itself
+        update(&[self.version]);
+        update(self.session_id.as_bytes());
+        update(&[self.intent.to_u8()]);
+        update(&[self.priority.0]);
+        update(&[self.wire_flags().0]);
+        update(&self.sequence.to_be_bytes());
+        update(&self.timestamp.to_be_bytes());
+        update(&(self.payload.len() as u32).to_be_bytes());
+        if !self.ext.is_empty() {
+            // the signature signs this hash, so it can't be part of it
+            update(&self.ext.encode(false));
+        }
+        update(&self.payload);
+    }
+    
+    /// Flags as they go on the wire, with the extended header bit matching ext
+    pub fn wire_flags(&self) -> Flags {
+        let mut flags = self.flags;
+        flags.set_extended(!self.ext.is_empty());
+        flags
+    }
+    
+    /// Recompute the hash after changing any field
+    /// Fields are public, so anyone mutating a packet has to call this before sending
+    /// Uses the packet's integrity algorithm, SHA256 unless set_integrity picked another
+    pub fn reseal(&mut self) {
+        match self.integrity_algorithm() {
+            IntegrityAlgorithm::Sha256 => self.reseal_with_hasher::<sha2::Sha256>(),
+            IntegrityAlgorithm::Crc32 => self.reseal_with_hasher::<Crc32>(),
+            // no key here: the old HMAC is stale, the packet stays dirty until seal_hmac
+            IntegrityAlgorithm::HmacSha256 => {
+                self.dirty = true;
+                self.refresh_header();
+            }
+        }
+    }
+    
+    /// reseal() with a hasher the wire doesn't name, the receiver has to verify_with_hasher the same one
+    pub fn reseal_with_hasher<H: Hasher>(&mut self) {
+        self.hash = self.calculate_hash::<H>();
+        self.dirty = false;
+        self.refresh_header();
+    }
+    
+    /// Rebuild the cached wire header without touching the hash
+    /// (for the signature, which lives in the header but outside the hash)
+    pub(crate) fn refresh_header(&mut self) {
+        self.header = self.encode_header(true);
+    }
+    
+    /// Changed through a setter and not resealed yet?
+    /// Writing to the pub fields directly doesn't count, prefer the setters
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+    
+    pub fn set_priority(&mut self, priority: Priority) {
+        self.priority = priority;
+        self.dirty = true;
+    }
+    
+    /// set_priority with Priority::from_class
+    pub fn set_priority_by_class(&mut self, class: PriorityClass) {
+        self.set_priority(Priority::from_class(class));
+    }
+    
+    pub fn set_flags(&mut self, flags: Flags) {
+        self.flags = flags;
+        self.dirty = true;
+    }
+    
+    pub fn set_sequence(&mut self, sequence: Sequence) {
+        self.sequence = sequence;
+        self.dirty = true;
+    }
+    
+    pub fn set_payload(&mut self, payload: Vec<u8>) {
+        self.payload = payload;
+        self.dirty = true;
+    }
+    
+    /// Swap in new flags after construction, checked and resealed
+    /// The way to change compression/encryption indicators on a built packet
+    pub fn with_flags(mut self, flags: Flags) -> Result<Self, PacketError> {
+        flags.validate()?;
+        self.flags = flags;
+        self.reseal();
+        Ok(self)
+    }
+    
+    /// Same headers, new payload, fresh hash -for middleware that transforms payloads
+    /// CRC and fingerprint (if present) are recomputed for the new payload; the signature,
+    /// the padded/fragment-compressed markers and the decompressed size don't carry over,
+    /// they described the old payload
+    pub fn rebuild_with_payload(&self, new_payload: Vec<u8>) -> Result<Packet<N>, PacketError> {
+        if new_payload.len() > MAX_PAYLOAD_SIZE {
+            return Err(PacketError::TooLarge);
+        }
+        
+        let mut ext = self.ext.clone();
+        ext.signature = None;
+        ext.padded = false;
+        ext.fragment_compressed = false;
+        ext.decompressed_size = None;
+        if ext.payload_crc.is_some() {
+            ext.payload_crc = Some(crc32fast::hash(&new_payload));
+        }
+        if ext.fingerprint.is_some() {
+            ext.fingerprint = Some(super::fingerprint::fingerprint(&new_payload));
+        }
+        
+        let mut packet = Packet {
+            version: self.version,
+            session_id: self.session_id,
+            intent: self.intent,
+            priority: self.priority,
+            flags: self.flags,
+            sequence: self.sequence,
+            timestamp: self.timestamp,
+            ext,
+            payload: new_payload,
+            hash: [0u8; 32],
+            dirty: false,
+            header: Vec::new(),
+            uncompressed_len: None,
+        };
+        packet.reseal();
+        Ok(packet)
+    }
+    
+    /// Move the packet to another session (e.g. after a migration) and reseal
+    /// The hash is fresh, but a signature attached before this no longer matches it:
+    /// the packet has to be signed again before it goes out
+    pub fn retarget(&mut self, new: SessionId<N>) {
+        self.session_id = new;
+        self.reseal();
+    }
+    
+    /// Check the fields make sense together (things the wire format alone can't rule out)
+    pub fn validate_semantics(&self) -> Result<(), PacketError> {
+        // a response asking to be acked gets an ack, which... loops
+        if self.flags.ack_required() && self.intent.is_response() {
+            return Err(PacketError::SemanticMismatch("ack_required on a response intent"));
+        }
+        Ok(())
+    }
+    
+    /// Every structural and semantic check in one call, first failure wins
+    /// Separate from verify(): this says the packet makes sense, verify() says it wasn't damaged
+    /// Meant as a gate before a packet goes on a send queue
+    pub fn validate(&self) -> Result<(), PacketError> {
+        if !is_version_supported(self.version) {
+            return Err(PacketError::UnsupportedVersion {
+                got: self.version,
+                supported: SUPPORTED_VERSIONS,
+            });
+        }
+        
+        // Custom(byte) can be built with any byte, it has to stay in its range
+        // or it would go out looking like a protocol intent
+        if let Intent::Custom(byte) = self.intent {
+            if !Intent::is_custom(byte) {
+                return Err(PacketError::InvalidIntent(byte));
+            }
+        }
+        
+        self.flags.validate()?;
+        
+        if self.payload.len() > MAX_PAYLOAD_SIZE {
+            return Err(PacketError::TooLarge);
+        }
+        
+        // entry and block lengths on the wire are u16s
+        if !self.ext.is_empty() {
+            self.ext.try_encode(true)?;
+        }
+        
+        self.validate_semantics()
+    }
+    
+    /// Which algorithm the hash was made with (from the extended header, SHA256 if absent)
+    pub fn integrity_algorithm(&self) -> IntegrityAlgorithm {
+        self.ext.integrity.unwrap_or_default()
+    }
+    
+    /// Seal with another integrity algorithm from now on, named in the extended header
+    pub fn set_integrity(&mut self, algorithm: IntegrityAlgorithm) {
+        // SHA256 is what no entry means, keep such packets without one
+        self.ext.integrity = (algorithm != IntegrityAlgorithm::Sha256).then_some(algorithm);
+        self.reseal();
+    }
+    
+    /// Verify packet integrity with the algorithm the packet names
+    pub fn verify(&self) -> bool {
+        self.verify_with(self.integrity_algorithm())
+    }
+    
+    /// verify() with a given algorithm, whatever the packet says
+    pub fn verify_with(&self, algorithm: IntegrityAlgorithm) -> bool {
+        match algorithm {
+            IntegrityAlgorithm::Sha256 => self.verify_with_hasher::<sha2::Sha256>(),
+            IntegrityAlgorithm::Crc32 => self.verify_with_hasher::<Crc32>(),
+            // can't be checked without the key, see verify_hmac
+            IntegrityAlgorithm::HmacSha256 => false,
+        }
+    }
+    
+    /// Seal with HMAC-SHA256 under the session key, named in the extended header
+    /// Only someone holding the key can make a hash that verify_hmac accepts
+    pub fn seal_hmac(&mut self, key: &[u8; 32]) {
+        self.ext.integrity = Some(IntegrityAlgorithm::HmacSha256);
+        self.hash = self.calculate_hmac(key).finalize().into_bytes().into();
+        self.dirty = false;
+        self.refresh_header();
+    }
+    
+    /// seal_hmac for builder-style chains
+    pub fn with_hmac(mut self, key: &[u8; 32]) -> Self {
+        self.seal_hmac(key);
+        self
+    }
+    
+    /// Check an HMAC-SHA256 sealed packet against the session key
+    /// False for packets sealed any other way, so stripping the HMAC can't downgrade a packet
+    pub fn verify_hmac(&self, key: &[u8; 32]) -> bool {
+        if self.integrity_algorithm() != IntegrityAlgorithm::HmacSha256 {
+            return false;
+        }
+        // verify_slice compares in constant time
+        self.calculate_hmac(key).verify_slice(&self.hash).is_ok()
+    }
+    
+    /// verify() for packets sealed with reseal_with_hasher
+    pub fn verify_with_hasher<H: Hasher>(&self) -> bool {
+        let calculated_hash = self.calculate_hash::<H>();
+        // constant time, so a forger can't learn how many leading bytes they got right
+        let valid: bool = calculated_hash.ct_eq(&self.hash).into();
+        
+        #[cfg(feature = "tracing")]
+        if !valid {
+            tracing::warn!(session = %self.session_id, intent = ?self.intent, "packet hash verification failed");
+        }
+        
+        valid
+    }
+    
+    /// Serialize, refusing if a setter changed the packet since the last reseal
+    /// (the hash would be stale and the receiver would drop it) or if an extended header
+    /// entry is too big for its u16 length (InvalidExtension)
+    pub fn try_to_bytes(&self) -> Result<Vec<u8>, PacketError> {
+        if self.dirty {
+            return Err(PacketError::UnsealedPacket);
+        }
+        if !self.ext.is_empty() {
+            self.ext.try_encode(true)?;
+        }
+        Ok(self.to_bytes())
+    }
+    
+    /// Serialize packet to bytes for sending over network
+    /// 
+    /// This is THE critical function - it converts our struct to raw bytes
+    /// Debug builds panic on an unsealed packet, use try_to_bytes to get an error instead
+    pub fn to_bytes(&self) -> Vec<u8> {
+        debug_assert!(!self.dirty, "serializing a packet that was changed without reseal()");
+        
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!(
+            "fdp_to_bytes",
+            intent = ?self.intent,
+            payload_len = self.payload.len(),
+            compression = ?self.flags.compression(),
+            encryption = ?self.flags.encryption(),
+        )
+        .entered();
+        
+        let total_size = self.size();
+        let mut buffer = Vec::with_capacity(total_size);
+        
+        buffer.extend_from_slice(&self.encode_header(true));
+        
+        // Bytes 36+: Payload
+        buffer.extend_from_slice(&self.payload);
+        
+        // Last 32 bytes: Hash
+        buffer.extend_from_slice(&self.hash);
+        
+        buffer
+    }
+    
+    /// The packet as three slices for write_vectored: header, payload, hash
+    /// Same bytes as to_bytes() without gluing them into one allocation
+    ///
+    /// The slices borrow the packet, so it can't be changed while they're alive.
+    /// The header slice is the copy cached by the last reseal(), a packet whose pub
+    /// fields were written directly without resealing hands out a stale header.
+    pub fn to_iovecs(&self) -> [IoSlice<'_>; 3] {
+        debug_assert!(!self.dirty, "writing a packet that was changed without reseal()");
+        [
+            IoSlice::new(&self.header),
+            IoSlice::new(&self.payload),
+            IoSlice::new(&self.hash),
+        ]
+    }
+    
+    /// The packet with the per-send bits taken out: timestamp zeroed, no signature, no hash
+    /// Two packets carrying the same thing give the same bytes no matter when they were built,
+    /// not parseable, only meant for hashing into a cache/dedup key
+    pub fn canonical_bytes(&self) -> Vec<u8> {
+        let mut buffer = self.encode_header(false);
+        buffer[12 + N..20 + N].fill(0);
+        buffer.extend_from_slice(&self.payload);
+        buffer
+    }
+    
+    /// SHA256 of canonical_bytes(), stable across timestamps
+    pub fn content_key(&self) -> [u8; 32] {
+        use sha2::{Sha256, Digest};
+        
+        Sha256::digest(self.canonical_bytes()).into()
+    }
+    
+    /// Header fields as a one-line JSON object, for CLI debugging without serde
+    /// Payload bytes are left out, only the length is there
+    pub fn to_debug_json(&self) -> String {
+        // every value is a number, a bool, or hex/enum names that never need escaping
+        format!(
+            concat!(
+                "{{\"version\":{},\"session\":\"{}\",\"intent\":\"{:?}\",\"priority\":{},",
+                "\"flags\":{{\"compression\":\"{:?}\",\"encryption\":\"{:?}\",",
+                "\"fragmented\":{},\"ack_required\":{},\"extended\":{}}},",
+                "\"sequence\":{},\"timestamp\":{},\"payload_len\":{},\"hash\":\"{}\"}}"
+            ),
+            self.version,
+            self.session_id,
+            self.intent,
+            self.priority.0,
+            self.flags.compression(),
+            self.flags.encryption(),
+            self.flags.is_fragmented(),
+            self.flags.ack_required(),
+            !self.ext.is_empty(),
+            self.sequence,
+            self.timestamp,
+            self.payload.len(),
+            self.hash_hex(),
+        )
+    }
+    
+    /// One line with only what differs from a Packet::new packet, so odd ones stand out in logs
+    /// Intent and payload length are always there; session, timestamp and hash never are
+    pub fn debug_nondefault(&self) -> String {
+        use std::fmt::Write;
+        
+        let defaults = Flags::packet_default();
+        let mut out = format!("{:?} len={}", self.intent, self.payload.len());
+        if self.version != FDP_VERSION {
+            let _ = write!(out, " version={}", self.version);
+        }
+        if self.priority != Priority::NORMAL {
+            let _ = write!(out, " priority={}", self.priority.0);
+        }
+        if self.sequence != 0 {
+            let _ = write!(out, " seq={}", self.sequence);
+        }
+        if self.flags.compression() != defaults.compression() {
+            let _ = write!(out, " compression={:?}", self.flags.compression());
+        }
+        if self.flags.encryption() != defaults.encryption() {
+            let _ = write!(out, " encryption={:?}", self.flags.encryption());
+        }
+        if self.flags.is_fragmented() {
+            out.push_str(" fragmented");
+        }
+        if self.flags.ack_required() {
+            out.push_str(" ack_required");
+        }
+        if self.time_resolution() != TimeResolution::Millis {
+            let _ = write!(out, " resolution={:?}", self.time_resolution());
+        }
+        if !self.ext.is_empty() {
+            out.push_str(" ext");
+        }
+        out
+    }
+    
+    /// The hash as lowercase hex, 64 characters, for logs and dedup keys
+    pub fn hash_hex(&self) -> String {
+        use std::fmt::Write;
+        
+        // same rendering as SessionId's Display
+        self.hash.iter().fold(String::with_capacity(64), |mut out, byte| {
+            let _ = write!(out, "{:02x}", byte);
+            out
+        })
+    }
+    
+    /// Fixed header plus the extended header block, everything in front of the payload
+    fn encode_header(&self, with_signature: bool) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(Self::HEADER_LEN + self.ext.wire_size());
+        
+        // (byte positions are for the default 16 byte session id)
+        // Byte 0: Version
+        buffer.push(self.version);
+        
+        // Bytes 1-16: Session ID
+        buffer.extend_from_slice(self.session_id.as_bytes());
+        
+        // Byte 17: Intent
+        buffer.push(self.intent.to_u8());
+        
+        // Byte 18: Priority
+        buffer.push(self.priority.0);
+        
+        // Byte 19: Flags
+        buffer.push(self.wire_flags().0);
+        
+        // Bytes 20-23: Sequence number (big-endian)
+        buffer.extend_from_slice(&self.sequence.to_be_bytes());
+        
+        // Bytes 24-27: Payload length (big-endian)
+        buffer.extend_from_slice(&(self.payload.len() as u32).to_be_bytes());
+        
+        // Bytes 28-35: Timestamp (big-endian)
+        buffer.extend_from_slice(&self.timestamp.to_be_bytes());
+        
+        // Extended header, only when there is something in it
+        if !self.ext.is_empty() {
+            buffer.extend_from_slice(&self.ext.encode(with_signature));
+        }
+        
+        buffer
+    }
+    
+    /// from_bytes for any session id length, e.g. Packet::<8>::decode(bytes)
+    /// Compact control packets (see compact.rs) are recognized by their version byte
+    pub fn decode(bytes: &[u8]) -> Result<Self, PacketError> {
+        if bytes.first() == Some(&COMPACT_VERSION) {
+            return Self::decode_compact(bytes, true);
+        }
+        let packet = Self::decode_unchecked(bytes)?;
+        
+        // Cheap CRC first (if the sender attached one), no point hashing a payload we know is broken
+        packet.check_payload_crc()?;
+        
+        // Verify integrity
+        if packet.integrity_algorithm().is_keyed() {
+            return Err(PacketError::KeyRequired);
+        }
+        if !packet.verify() {
+            return Err(PacketError::InvalidHash);
+        }
+        
+        Ok(packet)
+    }
+    
+    /// from_bytes_unchecked for any session id length
+    pub fn decode_unchecked(bytes: &[u8]) -> Result<Self, PacketError> {
+        if bytes.first() == Some(&COMPACT_VERSION) {
+            return Self::decode_compact(bytes, false);
+        }
+        
+        // fields get filled in once we've parsed them
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!(
+            "fdp_from_bytes",
+            len = bytes.len(),
+            intent = tracing::field::Empty,
+            payload_len = tracing::field::Empty,
+            compression = tracing::field::Empty,
+            encryption = tracing::field::Empty,
+        )
+        .entered();
+        
+        // size, version, intent and length checks all live in check_header
+        let layout = Self::check_header(bytes)?;
+        
+        let mut packet = Self::parse_fields(bytes, &layout)?;
+        packet.payload = bytes[layout.payload].to_vec();
+        
+        #[cfg(feature = "tracing")]
+        {
+            span.record("intent", tracing::field::debug(&packet.intent));
+            span.record("payload_len", packet.payload.len());
+            span.record("compression", tracing::field::debug(packet.flags.compression()));
+            span.record("encryption", tracing::field::debug(packet.flags.encryption()));
+        }
+        
+        Ok(packet)
+    }
+    
+    /// Parse and hash-check a packet without copying the payload out of `bytes`
+    /// The returned packet has an empty payload, the range says where the real one is
+    pub(super) fn decode_in_place(bytes: &[u8]) -> Result<(Self, Range<usize>), PacketError> {
+        let layout = Self::check_header(bytes)?;
+        let packet = Self::parse_fields(bytes, &layout)?;
+        
+        if !bool::from(Self::wire_hash(bytes, &layout)?.ct_eq(&packet.hash)) {
+            return Err(PacketError::InvalidHash);
+        }
+        
+        Ok((packet, layout.payload))
+    }
+    
+    /// Everything but the payload, from a buffer check_header already approved
+    fn parse_fields(bytes: &[u8], layout: &Layout) -> Result<Self, PacketError> {
+        // Parse header
+        let version = bytes[0];
+        
+        // Session ID
+        let mut session_bytes = [0u8; N];
+        session_bytes.copy_from_slice(&bytes[1..1 + N]);
+        let session_id = SessionId::from_bytes(session_bytes);
+        
+        // Intent
+        let intent = parse_intent(bytes[1 + N])?;
+        
+        // Priority
+        let priority = Priority(bytes[2 + N]);
+        
+        // Flags
+        let flags = Flags(bytes[3 + N]);
+        
+        // built without the ciphers, an encrypted payload is just noise to us
+        #[cfg(not(feature = "encryption"))]
+        if flags.encryption() != EncryptionLevel::None {
+            return Err(PacketError::EncryptionUnsupported);
+        }
+        
+        // Sequence
+        let mut seq_bytes = [0u8; 4];
+        seq_bytes.copy_from_slice(&bytes[4 + N..8 + N]);
+        let sequence = u32::from_be_bytes(seq_bytes);
+        
+        // Timestamp
+        let mut time_bytes = [0u8; 8];
+        time_bytes.copy_from_slice(&bytes[12 + N..20 + N]);
+        let timestamp = u64::from_be_bytes(time_bytes);
+        
+        // Extended header
+        let ext = match &layout.ext {
+            Some(range) => ExtendedHeader::decode(&bytes[range.clone()])?,
+            None => ExtendedHeader::default(),
+        };
+        
+        // Extract hash
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(&bytes[layout.payload.end..]);
+        
+        let packet = Packet {
+            version,
+            session_id,
+            intent,
+            priority,
+            flags,
+            sequence,
+            timestamp,
+            ext,
+            payload: Vec::new(),
+            hash,
+            dirty: false,
+            header: bytes[..layout.payload.start].to_vec(),
+            uncompressed_len: None,
+        };
+        
+        Ok(packet)
+    }
+    
+    /// Validate the fixed header of a serialized packet without touching the payload
+    /// Returns where the extended header entries and the payload live in the buffer
+    fn check_header(bytes: &[u8]) -> Result<Layout, PacketError> {
+        let header_len = Self::HEADER_LEN;
+        
+        // Minimum size check
+        if bytes.len() < header_len + HASH_SIZE {
+            return Err(PacketError::TooSmall);
+        }
+        
+        // Maximum size check
+        if bytes.len() > header_len + MAX_PAYLOAD_SIZE + HASH_SIZE {
+            return Err(PacketError::TooLarge);
+        }
+        
+        // version, intent, advertised payload length
+        let payload_len = Self::check_fixed_header(bytes)?;
+        
+        // Extended header length, if there is one
+        let mut ext_size = 0;
+        if Flags(bytes[3 + N]).has_extended() {
+            if bytes.len() < header_len + EXT_LENGTH_SIZE + HASH_SIZE {
+                return Err(PacketError::LengthMismatch);
+            }
+            let entries_len = u16::from_be_bytes([bytes[header_len], bytes[header_len + 1]]) as usize;
+            ext_size = EXT_LENGTH_SIZE + entries_len;
+        }
+        
+        // Verify payload length matches actual data
+        // checked, a crafted length must not wrap the sum on 32-bit targets
+        let expected_total = header_len
+            .checked_add(ext_size)
+            .and_then(|total| total.checked_add(payload_len))
+            .and_then(|total| total.checked_add(HASH_SIZE))
+            .ok_or(PacketError::TooLarge)?;
+        if bytes.len() != expected_total {
+            return Err(PacketError::LengthMismatch);
+        }
+        
+        let payload_start = header_len + ext_size;
+        Ok(Layout {
+            ext: (ext_size > 0).then_some(header_len + EXT_LENGTH_SIZE..payload_start),
+            payload: payload_start..payload_start + payload_len,
+        })
+    }
+    
+    /// The checks that only need the fixed header, returns the advertised payload length
+    /// Lets a stream reader reject a packet before reading (or allocating for) its payload
+    pub(crate) fn check_fixed_header(header: &[u8]) -> Result<usize, PacketError> {
+        if header.len() < Self::HEADER_LEN {
+            return Err(PacketError::TooSmall);
+        }
+        
+        // Check version compatibility
+        let version = header[0];
+        if !is_version_supported(version) {
+            return Err(PacketError::UnsupportedVersion {
+                got: version,
+                supported: SUPPORTED_VERSIONS,
+            });
+        }
+        
+        // Intent has to be one we know about
+        parse_intent(header[1 + N])?;
+        
+        // Payload length
+        let mut len_bytes = [0u8; 4];
+        len_bytes.copy_from_slice(&header[8 + N..12 + N]);
+        let payload_len = u32::from_be_bytes(len_bytes) as usize;
+        if payload_len > MAX_PAYLOAD_SIZE {
+            return Err(PacketError::TooLarge);
+        }
+        Ok(payload_len)
+    }
+    
+    /// Hash a serialized packet straight from its wire bytes
+    /// Same field order as calculate_hash, so the result matches for a parsed packet
+    fn wire_hash(bytes: &[u8], layout: &Layout) -> Result<[u8; 32], PacketError> {
+        let ext = match &layout.ext {
+            Some(range) => Some(ExtendedHeader::decode(&bytes[range.clone()])?),
+            None => None,
+        };
+        let mut hasher = Self::wire_header_hasher(bytes, ext.as_ref())?;
+        hasher.update(&bytes[layout.payload.clone()]);
+        
+        Ok(hasher.finalize())
+    }
+    
+    /// The packet's hasher fed with everything the hash covers before the payload, straight
+    /// from the fixed header bytes; the caller feeds the payload and finishes it
+    /// KeyRequired for HMAC sealed packets
+    pub(crate) fn wire_header_hasher(header: &[u8], ext: Option<&ExtendedHeader>) -> Result<AnyHasher, PacketError> {
+        let algorithm = ext.and_then(|ext| ext.integrity).unwrap_or_default();
+        let mut hasher = AnyHasher::new(algorithm)?;
+        hasher.update(&header[0..4 + N]); // version, session id, intent, priority, flags
+        hasher.update(&header[4 + N..8 + N]); // sequence
+        hasher.update(&header[12 + N..20 + N]); // timestamp
+        hasher.update(&header[8 + N..12 + N]); // payload length
+        if let Some(ext) = ext {
+            // same as calculate_hash: the block minus the signature
+            hasher.update(&ext.encode(false));
+        }
+        Ok(hasher)
+    }
+    
+    /// Get the size of this packet in bytes
+    pub fn size(&self) -> usize {
+        Self::HEADER_LEN + self.ext.wire_size() + self.payload.len() + HASH_SIZE
+    }
+}
+
+// parsing entry points for the default layout
+// (the generic ones can't be called as plain Packet::... because N wouldn't be known)
+impl Packet {
+    /// Deserialize bytes back into a Packet
+    /// 
+    /// This is the reverse - turn raw bytes into our struct
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PacketError> {
+        Self::decode(bytes)
+    }
+    
+    /// from_bytes for packets sealed with HMAC under the session key (see seal_hmac)
+    /// Anything not HMAC sealed is refused too, a forger could have stripped it
+    pub fn from_bytes_with_key(bytes: &[u8], key: &[u8; 32]) -> Result<Self, PacketError> {
+        let packet = Self::decode_unchecked(bytes)?;
+        packet.check_payload_crc()?;
+        if !packet.verify_hmac(key) {
+            return Err(PacketError::InvalidHash);
+        }
+        Ok(packet)
+    }
+    
+    /// Same as from_bytes but WITHOUT checking the hash
+    /// 
+    /// Only for input that is already trusted (loopback, or a channel that was
+    /// authenticated some other way). The structure is still fully checked, but
+    /// a corrupted or forged payload will come through as if it were fine.
+    pub fn from_bytes_unchecked(bytes: &[u8]) -> Result<Self, PacketError> {
+        Self::decode_unchecked(bytes)
+    }
+    
+    /// from_bytes for the packet at the start of `bytes`, whatever follows it
+    /// Returns the packet and how many bytes it took, for stream decoders whose buffer
+    /// can hold the next packet (or the start of it) behind this one
+    pub fn from_bytes_prefix(bytes: &[u8]) -> Result<(Self, usize), PacketError> {
+        let len = Self::wire_len(bytes)?;
+        Ok((Self::from_bytes(&bytes[..len])?, len))
+    }
+    
+    /// from_bytes_prefix handing back the unparsed rest of the buffer instead of a length
+    pub fn from_bytes_with_tail(bytes: &[u8]) -> Result<(Self, &[u8]), PacketError> {
+        let (packet, len) = Self::from_bytes_prefix(bytes)?;
+        Ok((packet, &bytes[len..]))
+    }
+    
+    /// Length of the packet at the start of `bytes`, from its header alone
+    /// UnexpectedEof when the buffer holds less than that
+    pub(crate) fn wire_len(bytes: &[u8]) -> Result<usize, PacketError> {
+        if bytes.first() == Some(&COMPACT_VERSION) {
+            if bytes.len() < Self::COMPACT_LEN {
+                return Err(PacketError::UnexpectedEof {
+                    read: bytes.len(),
+                    needed: Self::COMPACT_LEN,
+                });
+            }
+            return Ok(Self::COMPACT_LEN);
+        }
+        if bytes.len() < HEADER_SIZE {
+            return Err(PacketError::UnexpectedEof {
+                read: bytes.len(),
+                needed: HEADER_SIZE,
+            });
+        }
+        let payload_len = Self::check_fixed_header(bytes)?;
+
+        let mut ext_size = 0;
+        if Flags(bytes[3 + SESSION_ID_SIZE]).has_extended() {
+            let Some(len) = bytes.get(HEADER_SIZE..HEADER_SIZE + EXT_LENGTH_SIZE) else {
+                return Err(PacketError::UnexpectedEof {
+                    read: bytes.len(),
+                    needed: HEADER_SIZE + EXT_LENGTH_SIZE,
+                });
+            };
+            ext_size = EXT_LENGTH_SIZE + u16::from_be_bytes([len[0], len[1]]) as usize;
+        }
+
+        let len = HEADER_SIZE + ext_size + payload_len + HASH_SIZE;
+        if bytes.len() < len {
+            return Err(PacketError::UnexpectedEof {
+                read: bytes.len(),
+                needed: len,
+            });
+        }
+        Ok(len)
+    }
+    
+    /// Forward a serialized packet without turning it back into a struct
+    /// 
+    /// A proxy only needs the header to be sane, so we check that and hand the bytes on.
+    /// If `new_session` is a different session the id is patched in place and the hash
+    /// is recomputed; the old hash is checked first so we never relabel a corrupted
+    /// packet as valid. Same (or no) session means no hashing and no copy, the input comes
+    /// back borrowed.
+    /// 
+    /// A rewrite changes the hash, and an Ed25519 signature signs the hash: a signed packet
+    /// comes out with a signature that no longer verifies, the sender has to sign for the
+    /// new session itself.
+    pub fn forward(bytes: &[u8], new_session: Option<SessionId>) -> Result<Cow<'_, [u8]>, PacketError> {
+        let layout = Self::check_header(bytes)?;
+        
+        let session = match new_session {
+            Some(session) if session.as_bytes()[..] != bytes[1..17] => session,
+            // nothing to rewrite, the bytes go out exactly as they came in
+            _ => return Ok(Cow::Borrowed(bytes)),
+        };
+        
+        let hash_start = layout.payload.end;
+        if !bool::from(Self::wire_hash(bytes, &layout)?[..].ct_eq(&bytes[hash_start..])) {
+            return Err(PacketError::InvalidHash);
+        }
+        
+        let mut out = bytes.to_vec();
+        out[1..17].copy_from_slice(session.as_bytes());
+        let hash = Self::wire_hash(&out, &layout)?;
+        out[hash_start..].copy_from_slice(&hash);
+        
+        Ok(Cow::Owned(out))
+    }
+    
+}
+
+/// verify() every packet, results in the same order as `packets`
+/// With the `parallel` feature the hashing is spread over rayon's thread pool, otherwise
+/// it's a plain loop
+pub fn verify_batch(packets: &[Packet]) -> Vec<bool> {
+    #[cfg(feature = "parallel")]
+    {
+        use rayon::prelude::*;
+        packets.par_iter().map(Packet::verify).collect()
+    }
+    #[cfg(not(feature = "parallel"))]
+    {
+        packets.iter().map(Packet::verify).collect()
+    }
+}
+
+// intent byte off the wire, telling "reserved but not assigned" apart from plain garbage
+pub(super) fn parse_intent(byte: u8) -> Result<Intent, PacketError> {
+    Intent::from_u8(byte).ok_or(if Intent::is_reserved(byte) {
+        PacketError::ReservedIntent(byte)
+    } else {
+        PacketError::InvalidIntent(byte)
+    })
+}
+
+// where the variable-length parts of a serialized packet are, worked out by check_header
+struct Layout {
+    ext: Option<std::ops::Range<usize>>, // extended header entries (after the u16 length)
+    payload: std::ops::Range<usize>,     // hash starts right after this
+}
+
+#[derive(Debug)]
+pub enum PacketError {
+    TooSmall,
+    TooLarge,
+    UnsupportedVersion { got: u8, supported: &'static [u8] },
+    InvalidIntent(u8),
+    ReservedIntent(u8), // inside a reserved range but not assigned (yet), likely a newer peer
+    LengthMismatch,
+    InvalidHash,
+    CompressionFailed,
+    DecompressionFailed,
+    MissingDictionary,
+    InvalidExtension,
+    PayloadCrcMismatch,
+    IntentNotAllowed(Intent),
+    InvalidPadding,
+    InvalidFragment, // bad index/count, or a fragment that doesn't match its group
+    UnsealedPacket,
+    EncryptionFailed,
+    DecryptionFailed,
+    EncryptionRequired,
+    EncryptionUnsupported, // built without the `encryption` feature
+    InvalidPayload,
+    UnexpectedIntent(Intent),
+    SemanticMismatch(&'static str),
+    BaseHashMismatch,
+    Io(std::io::ErrorKind), // the stream under a reader/writer failed (or ended early)
+    InvalidFlags(u8),
+    InvalidFrame, // unknown frame kind or a truncated packet inside a frame
+    InvalidCookie, // handshake cookie missing, forged, for another address or expired
+    InvalidMigration, // migration proof missing, forged or replayed, or the session isn't known
+    PlaintextNotAllowed, // EncryptionLevel::None without PacketBuilder::insecure_allow_plaintext
+    TimestampRegression { newest: u64, got: u64 }, // ms, further behind the session's newest than the skew allows
+    TooManyFragments, // fragment count over the reassembler's limit
+    UnexpectedEof { read: usize, needed: usize }, // stream ended mid-packet, read == 0 is a clean close
+    KeyRequired, // sealed with HMAC, only verify_hmac / from_bytes_with_key can check it
+    SessionMismatch, // packet arrived on another session's channel
+    VersionMismatch { negotiated: u8, got: u8 }, // supported, but not the version the session agreed on
+    Backpressure(SessionId), // the session's handler is behind and its channel is full, the packet was dropped
+}
+
+impl std::fmt::Display for PacketError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PacketError::TooSmall => write!(f, "Packet too small"),
+            PacketError::TooLarge => write!(f, "Packet too large"),
+            PacketError::UnsupportedVersion { got, supported } => {
+                write!(f, "Unsupported version: {} (supported: {:?})", got, supported)
+            }
+            PacketError::InvalidIntent(i) => write!(f, "Invalid intent: {}", i),
+            PacketError::ReservedIntent(i) => write!(
+                f,
+                "Unassigned intent {:#04x} in the reserved {} range",
+                i,
+                Intent::reserved_range(*i).unwrap_or("?")
+            ),
+            PacketError::LengthMismatch => write!(f, "Payload length mismatch"),
+            PacketError::InvalidHash => write!(f, "Hash verification failed"),
+            PacketError::CompressionFailed => write!(f, "Compression failed"),
+            PacketError::DecompressionFailed => write!(f, "Decompression failed"),
+            PacketError::MissingDictionary => write!(f, "Zstd dictionary required but not provided"),
+            PacketError::InvalidExtension => write!(f, "Malformed extended header"),
+            PacketError::PayloadCrcMismatch => write!(f, "Payload CRC mismatch"),
+            PacketError::IntentNotAllowed(i) => write!(f, "Intent not allowed: {:?}", i),
+            PacketError::InvalidPadding => write!(f, "Invalid payload padding"),
+            PacketError::InvalidFragment => write!(f, "Invalid fragment"),
+            PacketError::UnsealedPacket => write!(f, "Packet changed since last reseal"),
+            PacketError::EncryptionFailed => write!(f, "Encryption failed"),
+            PacketError::DecryptionFailed => write!(f, "Decryption failed"),
+            PacketError::EncryptionRequired => write!(f, "Payload must be encrypted"),
+            PacketError::EncryptionUnsupported => write!(f, "Encrypted packet, but encryption support is not compiled in"),
+            PacketError::InvalidPayload => write!(f, "Payload does not match its intent's format"),
+            PacketError::UnexpectedIntent(i) => write!(f, "Unexpected intent: {:?}", i),
+            PacketError::SemanticMismatch(reason) => write!(f, "Semantic mismatch: {}", reason),
+            PacketError::BaseHashMismatch => write!(f, "Delta base does not match local data"),
+            PacketError::Io(kind) => write!(f, "I/O error: {}", kind),
+            PacketError::InvalidFlags(b) => write!(f, "Invalid flags: {:#010b}", b),
+            PacketError::InvalidFrame => write!(f, "Malformed frame"),
+            PacketError::InvalidCookie => write!(f, "Invalid or expired handshake cookie"),
+            PacketError::InvalidMigration => write!(f, "Invalid session migration"),
+            PacketError::PlaintextNotAllowed => write!(f, "Plaintext packet without explicit opt-in"),
+            PacketError::TooManyFragments => write!(f, "Too many fragments in one message"),
+            PacketError::TimestampRegression { newest, got } => {
+                write!(f, "Timestamp went backwards: {} after {}", got, newest)
+            }
+            PacketError::UnexpectedEof { read, needed } => {
+                write!(f, "Stream ended after {} of {} bytes", read, needed)
+            }
+            PacketError::SessionMismatch => write!(f, "Packet belongs to another session"),
+            PacketError::VersionMismatch { negotiated, got } => {
+                write!(f, "Version {} on a session that negotiated {}", got, negotiated)
+            }
+            PacketError::KeyRequired => write!(f, "Packet integrity is keyed, the session key is needed to check it"),
+            PacketError::Backpressure(session_id) => write!(f, "Session {} is not keeping up, packet dropped", session_id),
+        }
+    }
+}
+
+impl std::error::Error for PacketError {}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_packet_roundtrip() {
+        // Create a packet
+        let session = SessionId::new();
+        let payload = b"Hello, FDP!".to_vec();
+        let packet = Packet::new(session, Intent::Search, payload.clone());
+        
+        // Serialize to bytes
+        let bytes = packet.to_bytes();
+        
+        // Deserialize back
+        let recovered = Packet::from_bytes(&bytes).unwrap();
+        
+        // Verify everything matches
+        assert_eq!(packet.version, recovered.version);
+        assert_eq!(packet.session_id, recovered.session_id);
+        assert_eq!(packet.intent, recovered.intent);
+        assert_eq!(packet.payload, recovered.payload);
+        assert_eq!(packet.hash, recovered.hash);
+    }
+    
+    // random valid packets, the property is the roundtrip above for every one of them
+    fn arbitrary_packet() -> impl proptest::strategy::Strategy<Value = Packet> {
+        use proptest::prelude::*;
+        
+        let intents: Vec<Intent> = (0..=u8::MAX).filter_map(Intent::from_u8).collect();
+        // encrypted flags don't parse without the ciphers compiled in
+        let encryption_levels = if cfg!(feature = "encryption") { 3u8 } else { 1 };
+        let payload = prop_oneof![
+            // the boundaries: empty, one byte, around the header size
+            prop::sample::select(vec![0usize, 1, HEADER_SIZE - 1, HEADER_SIZE, HEADER_SIZE + 1])
+                .prop_flat_map(|len| prop::collection::vec(any::<u8>(), len)),
+            prop::collection::vec(any::<u8>(), 0..4096),
+        ];
+        
+        (
+            any::<[u8; 16]>(),
+            prop::sample::select(intents),
+            any::<u8>(),
+            0u8..=4,
+            0..encryption_levels,
+            any::<(bool, bool)>(),
+            any::<(Sequence, u64)>(),
+            payload,
+        )
+            .prop_map(
+                |(session, intent, priority, compression, encryption, (fragmented, ack), (sequence, timestamp), payload)| {
+                    let mut packet = Packet::new(SessionId::from_bytes(session), intent, payload);
+                    packet.priority = Priority(priority);
+                    packet.flags = Flags::new();
+                    packet.flags.set_compression(Compression::from_u8(compression).unwrap());
+                    packet.flags.set_encryption(EncryptionLevel::from_u8(encryption).unwrap());
+                    packet.flags.set_fragmented(fragmented);
+                    packet.flags.set_ack_required(ack);
+                    packet.sequence = sequence;
+                    packet.timestamp = timestamp;
+                    packet.reseal();
+                    packet
+                },
+            )
+    }
+    
+    proptest::proptest! {
+        #[test]
+        fn test_packet_roundtrip_property(packet in arbitrary_packet()) {
+            let bytes = packet.to_bytes();
+            let recovered = Packet::from_bytes(&bytes).unwrap();
+            
+            proptest::prop_assert!(recovered.verify());
+            proptest::prop_assert_eq!(recovered.version, packet.version);
+            proptest::prop_assert_eq!(recovered.session_id, packet.session_id);
+            proptest::prop_assert_eq!(recovered.intent, packet.intent);
+            proptest::prop_assert_eq!(recovered.priority, packet.priority);
+            proptest::prop_assert_eq!(recovered.flags.0, packet.flags.0);
+            proptest::prop_assert_eq!(recovered.sequence, packet.sequence);
+            proptest::prop_assert_eq!(recovered.timestamp, packet.timestamp);
+            proptest::prop_assert_eq!(&recovered.payload, &packet.payload);
+            proptest::prop_assert_eq!(recovered.hash, packet.hash);
+            proptest::prop_assert_eq!(recovered.to_bytes(), bytes);
+        }
+    }
+    
+    #[test]
+    fn test_flags() {
+        let mut flags = Flags::new();
+        
+        // Set compression
+        flags.set_compression(Compression::Zstd);
+        assert_eq!(flags.compression(), Compression::Zstd);
+        
+        // Set encryption
+        flags.set_encryption(EncryptionLevel::Aes256);
+        assert_eq!(flags.encryption(), EncryptionLevel::Aes256);
+        
+        // Set fragmented
+        flags.set_fragmented(true);
+        assert!(flags.is_fragmented());
+        
+        // Set ack required
+        flags.set_ack_required(true);
+        assert!(flags.ack_required());
+        
+        // Make sure compression didn't change when we set other flags
+        assert_eq!(flags.compression(), Compression::Zstd);
+    }
+    
+    #[test]
+    fn test_flags_validate() {
+        assert!(Flags::new().validate().is_ok());
+        
+        // compression value 7 doesn't exist
+        assert!(matches!(Flags(0b00000111).validate(), Err(PacketError::InvalidFlags(0b00000111))));
+        
+        // encryption value 3 doesn't exist
+        assert!(matches!(Flags(0b00011000).validate(), Err(PacketError::InvalidFlags(_))));
+    }
+    
+    #[test]
+    fn test_flag_setters_leave_other_bits_alone() {
+        // bit 7 set from outside (a newer peer, or the extended flag) has to survive every setter
+        let mut flags = Flags(0b10000000);
+        flags.set_compression(Compression::Brotli);
+        flags.set_compression(Compression::None);
+        flags.set_encryption(EncryptionLevel::Aes256);
+        flags.set_encryption(EncryptionLevel::None);
+        flags.set_fragmented(true);
+        flags.set_fragmented(false);
+        flags.set_ack_required(true);
+        flags.set_ack_required(false);
+        assert_eq!(flags.0, 0b10000000);
+        
+        // and every setter only ever touches its own bits
+        let mut flags = Flags(0xFF);
+        flags.set_compression(Compression::None);
+        assert_eq!(flags.0, 0b11111000);
+        flags.set_encryption(EncryptionLevel::None);
+        assert_eq!(flags.0, 0b11100000);
+        flags.set_fragmented(false);
+        assert_eq!(flags.0, 0b11000000);
+        flags.set_ack_required(false);
+        assert_eq!(flags.0, 0b10000000);
+    }
+    
+    #[test]
+    fn test_flags_decode_encode_all_bytes() {
+        for byte in 0..=u8::MAX {
+            let flags = Flags(byte);
+            let mut rebuilt = Flags::new();
+            rebuilt.set_compression(flags.compression());
+            rebuilt.set_encryption(flags.encryption());
+            rebuilt.set_fragmented(flags.is_fragmented());
+            rebuilt.set_ack_required(flags.ack_required());
+            rebuilt.set_extended(flags.has_extended());
+            
+            if flags.validate().is_ok() {
+                assert_eq!(rebuilt.0, byte, "{:#010b} did not round-trip", byte);
+            } else {
+                // only the unknown sub-field values get canonicalized, the single-bit flags stay
+                let mut expected = byte;
+                if Compression::from_u8(byte & 0b00000111).is_none() {
+                    expected &= 0b11111000;
+                }
+                if EncryptionLevel::from_u8((byte >> 3) & 0b00000011).is_none() {
+                    expected &= 0b11100111;
+                }
+                assert_eq!(rebuilt.0, expected, "{:#010b} canonicalized wrong", byte);
+            }
+        }
+    }
+    
+    #[test]
+    fn test_validate() {
+        let good = Packet::new(SessionId::new(), Intent::Search, b"fine".to_vec());
+        assert!(good.validate().is_ok());
+        
+        let mut bad_version = good.clone();
+        bad_version.version = 9;
+        assert!(matches!(
+            bad_version.validate(),
+            Err(PacketError::UnsupportedVersion { got: 9, .. })
+        ));
+        
+        let mut bad_custom = good.clone();
+        bad_custom.intent = Intent::Custom(0x01);
+        assert!(matches!(bad_custom.validate(), Err(PacketError::InvalidIntent(0x01))));
+        
+        let mut bad_flags = good.clone();
+        bad_flags.flags = Flags(0b00000111);
+        assert!(matches!(bad_flags.validate(), Err(PacketError::InvalidFlags(0b00000111))));
+        
+        let mut too_big = good.clone();
+        too_big.payload = vec![0u8; MAX_PAYLOAD_SIZE + 1];
+        assert!(matches!(too_big.validate(), Err(PacketError::TooLarge)));
+        
+        let mut bad_ext = good.clone();
+        bad_ext.ext.unknown.push((0xEE, vec![0u8; u16::MAX as usize]));
+        assert!(matches!(bad_ext.validate(), Err(PacketError::InvalidExtension)));
+        
+        let mut acked_pong = Packet::new(SessionId::new(), Intent::Pong, vec![]);
+        acked_pong.flags.set_ack_required(true);
+        assert!(matches!(acked_pong.validate(), Err(PacketError::SemanticMismatch(_))));
+    }
+    
+    #[test]
+    fn test_rebuild_with_payload() {
+        let mut original = Packet::new(SessionId::new(), Intent::DataPush, b"raw image".to_vec());
+        original.set_sequence(42);
+        original.set_priority(Priority::LOW);
+        original.set_payload_crc();
+        
+        let rebuilt = original.rebuild_with_payload(b"re-encoded image".to_vec()).unwrap();
+        assert_eq!(rebuilt.session_id, original.session_id);
+        assert_eq!(rebuilt.intent, original.intent);
+        assert_eq!(rebuilt.priority, Priority::LOW);
+        assert_eq!(rebuilt.sequence, 42);
+        assert_eq!(rebuilt.timestamp, original.timestamp);
+        assert_eq!(rebuilt.payload, b"re-encoded image");
+        assert!(rebuilt.verify());
+        
+        // CRC follows the new payload, so it still parses
+        assert!(Packet::from_bytes(&rebuilt.to_bytes()).is_ok());
+        
+        assert!(matches!(
+            original.rebuild_with_payload(vec![0u8; MAX_PAYLOAD_SIZE + 1]),
+            Err(PacketError::TooLarge)
+        ));
+    }
+    
+    #[test]
+    fn test_huge_payload_len_is_too_large() {
+        // advertise u32::MAX bytes of payload on an otherwise valid packet
+        let mut bytes = Packet::new(SessionId::new(), Intent::Search, vec![]).to_bytes();
+        bytes[8 + SESSION_ID_SIZE..12 + SESSION_ID_SIZE].copy_from_slice(&u32::MAX.to_be_bytes());
+        
+        assert!(matches!(Packet::from_bytes(&bytes), Err(PacketError::TooLarge)));
+        assert!(matches!(
+            Packet::from_bytes_unchecked(&bytes),
+            Err(PacketError::TooLarge)
+        ));
+    }
+    
+    #[test]
+    fn test_from_parts_keeps_wrong_hash() {
+        let good = Packet::new(SessionId::new(), Intent::Search, b"query".to_vec());
+        let forged = Packet::from_parts(
+            good.version,
+            good.session_id,
+            good.intent,
+            good.priority,
+            good.flags,
+            good.sequence,
+            good.timestamp,
+            good.payload.clone(),
+            [0xAB; 32],
+        );
+        assert!(!forged.verify());
+        assert!(Packet::from_bytes(&forged.to_bytes()).is_err());
+        
+        // same fields with the right hash is just the original packet
+        let rebuilt = Packet::from_parts(
+            good.version,
+            good.session_id,
+            good.intent,
+            good.priority,
+            good.flags,
+            good.sequence,
+            good.timestamp,
+            good.payload.clone(),
+            good.hash,
+        );
+        assert!(rebuilt.verify());
+        assert_eq!(rebuilt.to_bytes(), good.to_bytes());
+    }
+    
+    #[test]
+    fn test_verify_catches_any_hash_byte() {
+        // the comparison is constant time, make sure it still looks at every byte
+        let packet = Packet::new(SessionId::new(), Intent::Search, b"tag".to_vec());
+        assert!(packet.verify());
+        
+        for i in [0, 15, 31] {
+            let mut tampered = packet.clone();
+            tampered.hash[i] ^= 0x01;
+            assert!(!tampered.verify());
+        }
+    }
+    
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn test_with_flags() {
+        let packet = Packet::new(SessionId::new(), Intent::Search, b"reflag me".to_vec());
+        
+        let mut flags = Flags::new();
+        flags.set_compression(Compression::Zstd);
+        flags.set_encryption(EncryptionLevel::Aes256);
+        let packet = packet.with_flags(flags).unwrap();
+        assert!(packet.verify());
+        
+        let recovered = Packet::from_bytes(&packet.to_bytes()).unwrap();
+        assert_eq!(recovered.flags.compression(), Compression::Zstd);
+        assert_eq!(recovered.flags.encryption(), EncryptionLevel::Aes256);
+        assert!(recovered.verify());
+        
+        // junk flags are refused
+        assert!(recovered.with_flags(Flags(0b00000110)).is_err());
+    }
+    
+    #[test]
+    fn test_hash_verification() {
+        let session = SessionId::new();
+        let packet = Packet::new(session, Intent::Ping, vec![1, 2, 3]);
+        
+        // Should verify correctly
+        assert!(packet.verify());
+        
+        // Tamper with payload
+        let mut tampered = packet.clone();
+        tampered.payload[0] = 99;
+        
+        // Should fail verification
+        assert!(!tampered.verify());
+    }
+    
+    #[test]
+    fn test_packet_size() {
+        let session = SessionId::new();
+        let payload = vec![0u8; 1000]; // 1KB payload
+        let packet = Packet::new(session, Intent::DataPush, payload);
+        
+        let expected_size = HEADER_SIZE + 1000 + HASH_SIZE;
+        assert_eq!(packet.size(), expected_size);
+    }
+    
+    #[test]
+    fn test_unsupported_version_lists_supported() {
+        let mut packet = Packet::new(SessionId::new(), Intent::Ping, vec![]);
+        packet.version = 2;
+        packet.reseal();
+        
+        match Packet::from_bytes(&packet.to_bytes()) {
+            Err(PacketError::UnsupportedVersion { got, supported }) => {
+                assert_eq!(got, 2);
+                assert_eq!(supported, &[1]);
+            }
+            other => panic!("expected UnsupportedVersion, got {:?}", other),
+        }
+    }
+    
+    #[test]
+    fn test_age_ms() {
+        let mut packet = Packet::new(SessionId::new(), Intent::Ping, vec![]);
+        packet.timestamp = 10_000;
+        
+        assert_eq!(packet.age_ms(10_250), 250);
+        assert_eq!(packet.age_ms(10_000), 0);
+        
+        // sender clock ahead of ours
+        assert_eq!(packet.age_ms(9_000), 0);
+    }
+    
+    #[test]
+    fn test_short_session_id_roundtrip() {
+        // embedded deployment with 8 byte session ids
+        let session = SessionId::<8>::from_bytes([1, 2, 3, 4, 5, 6, 7, 8]);
+        let packet = Packet::new(session, Intent::Search, b"small".to_vec());
+        
+        assert_eq!(Packet::<8>::HEADER_LEN, 28);
+        let bytes = packet.to_bytes();
+        assert_eq!(bytes.len(), 28 + 5 + HASH_SIZE);
+        assert_eq!(bytes.len(), packet.size());
+        
+        let recovered = Packet::<8>::decode(&bytes).unwrap();
+        assert_eq!(recovered.session_id, session);
+        assert_eq!(recovered.intent, Intent::Search);
+        assert_eq!(recovered.payload, b"small".to_vec());
+        assert!(recovered.verify());
+    }
+    
+    #[test]
+    fn test_default_layout_unchanged() {
+        // the generic session id must not move anything for the 16 byte default
+        let packet = Packet::new(SessionId::from_bytes([9; 16]), Intent::Ping, vec![]);
+        let bytes = packet.to_bytes();
+        assert_eq!(HEADER_SIZE, 36);
+        assert_eq!(&bytes[1..17], &[9; 16]);
+        assert_eq!(bytes[17], Intent::Ping.to_u8());
+        assert_eq!(bytes.len(), 36 + HASH_SIZE);
+    }
+    
+    #[test]
+    fn test_to_iovecs_matches_to_bytes() {
+        let mut packet = Packet::new(SessionId::new(), Intent::Search, b"scatter gather".to_vec());
+        packet.set_payload_crc(); // make sure the extended header lands in the header slice
+        
+        let joined: Vec<u8> = packet.to_iovecs().iter().flat_map(|slice| slice.to_vec()).collect();
+        assert_eq!(joined, packet.to_bytes());
+        
+        // and the same for a parsed packet
+        let recovered = Packet::from_bytes(&packet.to_bytes()).unwrap();
+        let joined: Vec<u8> = recovered.to_iovecs().iter().flat_map(|slice| slice.to_vec()).collect();
+        assert_eq!(joined, packet.to_bytes());
+    }
+    
+    #[test]
+    fn test_content_key_ignores_timestamp() {
+        let session_id = SessionId::new();
+        let mut first = Packet::new(session_id, Intent::FetchDocument, b"doc 42".to_vec());
+        let mut second = Packet::new(session_id, Intent::FetchDocument, b"doc 42".to_vec());
+        first.timestamp = 1_000;
+        first.reseal();
+        second.timestamp = 2_000;
+        second.reseal();
+        
+        assert_ne!(first.to_bytes(), second.to_bytes());
+        assert_eq!(first.canonical_bytes(), second.canonical_bytes());
+        assert_eq!(first.content_key(), second.content_key());
+        
+        // different content is a different key
+        let other = Packet::new(session_id, Intent::FetchDocument, b"doc 43".to_vec());
+        assert_ne!(first.content_key(), other.content_key());
+    }
+    
+    #[test]
+    fn test_custom_hasher() {
+        // "hash" is just the number of bytes fed in
+        #[derive(Default)]
+        struct CountingHasher(u64);
+        
+        impl Hasher for CountingHasher {
+            fn update(&mut self, data: &[u8]) {
+                self.0 += data.len() as u64;
+            }
+            
+            fn finalize(self) -> [u8; 32] {
+                let mut out = [0u8; 32];
+                out[..8].copy_from_slice(&self.0.to_be_bytes());
+                out
+            }
+        }
+        
+        let mut packet = Packet::new(SessionId::new(), Intent::Search, b"mock".to_vec());
+        packet.reseal_with_hasher::<CountingHasher>();
+        
+        let mut expected = CountingHasher::default();
+        expected.update(&[0u8; Packet::<16>::HEADER_LEN + 4]);
+        assert_eq!(packet.hash, expected.finalize());
+        
+        assert!(packet.verify_with_hasher::<CountingHasher>());
+        assert!(!packet.verify()); // not what a SHA256 receiver expects
+    }
+    
+    #[test]
+    fn test_debug_nondefault() {
+        let plain = Packet::new(SessionId::new(), Intent::Search, b"hello".to_vec());
+        assert_eq!(plain.debug_nondefault(), "Search len=5");
+        
+        let mut odd = plain.clone();
+        odd.priority = Priority::CRITICAL;
+        odd.sequence = 9;
+        odd.flags.set_compression(Compression::Brotli);
+        odd.flags.set_encryption(EncryptionLevel::Aes256);
+        odd.flags.set_ack_required(true);
+        odd.reseal();
+        assert_eq!(
+            odd.debug_nondefault(),
+            "Search len=5 priority=255 seq=9 compression=Brotli encryption=Aes256 ack_required"
+        );
+    }
+    
+    #[test]
+    fn test_debug_json() {
+        let mut packet = Packet::new(SessionId::from_bytes([0xAB; 16]), Intent::SearchSuggest, b"rust".to_vec());
+        packet.sequence = 12;
+        packet.set_payload_crc();
+        
+        let json: serde_json::Value = serde_json::from_str(&packet.to_debug_json()).unwrap();
+        assert_eq!(json["intent"], "SearchSuggest");
+        assert_eq!(json["session"], "ab".repeat(16));
+        assert_eq!(json["sequence"], 12);
+        assert_eq!(json["payload_len"], 4);
+        assert_eq!(json["flags"]["extended"], true);
+        assert_eq!(json["hash"], packet.hash_hex());
+        
+        // custom intents render with their byte
+        let custom = Packet::new(SessionId::new(), Intent::Custom(0x90), vec![]);
+        assert!(serde_json::from_str::<serde_json::Value>(&custom.to_debug_json()).is_ok());
+    }
+    
+    #[test]
+    fn test_microsecond_timestamps() {
+        let millis = Packet::new(SessionId::new(), Intent::Ping, vec![]);
+        let mut micros = millis.clone();
+        micros.restamp(TimeResolution::Micros);
+        
+        // same instant, give or take the time between the two stamps
+        let ratio = micros.timestamp as f64 / millis.timestamp as f64;
+        assert!((999.9..1000.1).contains(&ratio), "ratio {}", ratio);
+        
+        let recovered = Packet::from_bytes(&micros.to_bytes()).unwrap();
+        assert_eq!(recovered.time_resolution(), TimeResolution::Micros);
+        
+        // freshness is in ms either way
+        let mut packet = Packet::new(SessionId::new(), Intent::Ping, vec![]);
+        packet.ext.time_resolution = Some(TimeResolution::Micros);
+        packet.timestamp = 10_000_000; // 10s in micros
+        packet.reseal();
+        assert_eq!(packet.timestamp_ms(), 10_000);
+        assert_eq!(packet.age_ms(10_250), 250);
+    }
+    
+    #[test]
+    fn test_verify_batch() {
+        let mut packets: Vec<Packet> = (0..64)
+            .map(|i| Packet::new(SessionId::new(), Intent::DataPush, vec![i as u8; 1024]))
+            .collect();
+        packets[37].payload[100] ^= 0x01;
+        
+        let results = verify_batch(&packets);
+        assert_eq!(results.len(), 64);
+        let failed: Vec<usize> = results.iter().enumerate().filter(|(_, ok)| !**ok).map(|(i, _)| i).collect();
+        assert_eq!(failed, vec![37]);
+    }
+    
+    #[test]
+    fn test_from_bytes_prefix() {
+        let first = Packet::new(SessionId::new(), Intent::DataPush, b"first".to_vec());
+        let mut second = Packet::new(SessionId::new(), Intent::Search, b"second".to_vec());
+        second.set_payload_crc(); // extended header, so its length comes from there too
+        let mut buffer = first.to_bytes();
+        buffer.extend_from_slice(&second.to_bytes());
+        
+        let (parsed, used) = Packet::from_bytes_prefix(&buffer).unwrap();
+        assert_eq!(parsed.payload, b"first");
+        assert_eq!(used, first.size());
+        
+        let rest = &buffer[used..];
+        let (parsed, used) = Packet::from_bytes_prefix(rest).unwrap();
+        assert_eq!(parsed.payload, b"second");
+        assert_eq!(used, rest.len());
+        
+        // only part of the next packet arrived yet
+        assert!(matches!(
+            Packet::from_bytes_prefix(&rest[..rest.len() - 1]),
+            Err(PacketError::UnexpectedEof { .. })
+        ));
+    }
+    
+    #[test]
+    fn test_from_bytes_with_tail() {
+        let packet = Packet::new(SessionId::new(), Intent::DataPush, b"whole".to_vec());
+        let mut buffer = packet.to_bytes();
+        // the start of a packet that hasn't fully arrived
+        buffer.extend_from_slice(&[FDP_VERSION, 0xAB, 0xCD]);
+        
+        let (parsed, tail) = Packet::from_bytes_with_tail(&buffer).unwrap();
+        assert_eq!(parsed.payload, b"whole");
+        assert_eq!(tail, [FDP_VERSION, 0xAB, 0xCD]);
+        assert_eq!(tail.as_ptr(), buffer[packet.size()..].as_ptr());
+        
+        let exact = packet.to_bytes();
+        let (_, tail) = Packet::from_bytes_with_tail(&exact).unwrap();
+        assert!(tail.is_empty());
+    }
+    
+    #[test]
+    fn test_hash_hex() {
+        let packet = Packet::new(SessionId::new(), Intent::Search, b"hex".to_vec());
+        let hex = packet.hash_hex();
+        assert_eq!(hex.len(), 64);
+        assert_eq!(hex, hex.to_lowercase());
+        
+        let decoded: Vec<u8> = (0..32)
+            .map(|i| u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap())
+            .collect();
+        assert_eq!(decoded, packet.hash);
+    }
+    
+    #[test]
+    fn test_verify_with_integrity_algorithm() {
+        let sha = Packet::new(SessionId::new(), Intent::Search, b"sha tagged".to_vec());
+        let mut crc = Packet::new(SessionId::new(), Intent::Search, b"crc tagged".to_vec());
+        crc.set_integrity(IntegrityAlgorithm::Crc32);
+        assert_eq!(crc.hash[4..], [0u8; 28]);
+        
+        for packet in [sha, crc] {
+            // verify() picks the algorithm off the wire, on both parse paths
+            let bytes = packet.to_bytes();
+            let recovered = Packet::from_bytes(&bytes).unwrap();
+            let algorithm = recovered.integrity_algorithm();
+            assert_eq!(algorithm, packet.integrity_algorithm());
+            assert!(recovered.verify());
+            assert!(recovered.verify_with(algorithm));
+            
+            let wrong = if algorithm == IntegrityAlgorithm::Sha256 {
+                IntegrityAlgorithm::Crc32
+            } else {
+                IntegrityAlgorithm::Sha256
+            };
+            assert!(!recovered.verify_with(wrong));
+        }
+    }
+    
+    #[test]
+    fn test_hmac_integrity() {
+        let key = [0x42; 32];
+        let packet = Packet::new(SessionId::new(), Intent::Search, b"keyed".to_vec()).with_hmac(&key);
+        assert_eq!(packet.integrity_algorithm(), IntegrityAlgorithm::HmacSha256);
+        let bytes = packet.to_bytes();
+        
+        let recovered = Packet::from_bytes_with_key(&bytes, &key).unwrap();
+        assert!(recovered.verify_hmac(&key));
+        assert!(!recovered.verify_hmac(&[0x43; 32]));
+        assert!(matches!(Packet::from_bytes_with_key(&bytes, &[0x43; 32]), Err(PacketError::InvalidHash)));
+        
+        // nothing without the key can vouch for it
+        assert!(!recovered.verify());
+        assert!(matches!(Packet::from_bytes(&bytes), Err(PacketError::KeyRequired)));
+        
+        // rewriting the payload and recomputing a plain hash doesn't get past the key
+        let mut forged = recovered.clone();
+        forged.payload = b"forge".to_vec();
+        forged.set_integrity(IntegrityAlgorithm::Sha256);
+        assert!(forged.verify());
+        assert!(!forged.verify_hmac(&key));
+        assert!(Packet::from_bytes_with_key(&forged.to_bytes(), &key).is_err());
+        
+        // and tampering with the wire bytes breaks the HMAC
+        let mut tampered = bytes.clone();
+        let payload_at = tampered.len() - HASH_SIZE - 1;
+        tampered[payload_at] ^= 0x01;
+        assert!(matches!(Packet::from_bytes_with_key(&tampered, &key), Err(PacketError::InvalidHash)));
+        
+        // a plain reseal can't redo the HMAC, the packet stays unsendable until it's sealed again
+        let mut changed = recovered;
+        changed.set_payload(b"new".to_vec());
+        changed.reseal();
+        assert!(matches!(changed.try_to_bytes(), Err(PacketError::UnsealedPacket)));
+        changed.seal_hmac(&key);
+        assert!(Packet::from_bytes_with_key(&changed.try_to_bytes().unwrap(), &key).is_ok());
+    }
+    
+    #[test]
+    fn test_forward_unchanged_session() {
+        let session = SessionId::new();
+        let packet = Packet::new(session, Intent::Search, b"forward me".to_vec());
+        let bytes = packet.to_bytes();
+        
+        // No rewrite requested -> the same bytes, not a copy
+        let forwarded = Packet::forward(&bytes, None).unwrap();
+        assert!(matches!(forwarded, Cow::Borrowed(_)));
+        assert_eq!(forwarded, bytes);
+        
+        // Rewriting to the same session is also a no-op
+        let forwarded = Packet::forward(&bytes, Some(session)).unwrap();
+        assert!(matches!(forwarded, Cow::Borrowed(_)));
+        assert_eq!(forwarded, bytes);
+    }
+    
+    #[test]
+    fn test_forward_rewrites_session() {
+        let packet = Packet::new(SessionId::new(), Intent::DataPush, vec![7u8; 64]);
+        let bytes = packet.to_bytes();
+        
+        let relay_session = SessionId::from_bytes([0xAB; 16]);
+        let forwarded = Packet::forward(&bytes, Some(relay_session)).unwrap();
+        
+        // Hash changed along with the session id
+        assert_ne!(forwarded[forwarded.len() - HASH_SIZE..], bytes[bytes.len() - HASH_SIZE..]);
+        
+        // And the result is a perfectly valid packet for the new session
+        let recovered = Packet::from_bytes(&forwarded).unwrap();
+        assert_eq!(recovered.session_id, relay_session);
+        assert_eq!(recovered.payload, packet.payload);
+        assert!(recovered.verify());
+    }
+    
+    #[test]
+    fn test_from_bytes_unchecked() {
+        let packet = Packet::new(SessionId::new(), Intent::Search, b"trusted".to_vec());
+        let mut bytes = packet.to_bytes();
+        
+        // Corrupt the payload: checked parse refuses, unchecked lets it through
+        bytes[HEADER_SIZE] ^= 0xFF;
+        assert!(matches!(Packet::from_bytes(&bytes), Err(PacketError::InvalidHash)));
+        let parsed = Packet::from_bytes_unchecked(&bytes).unwrap();
+        assert_eq!(parsed.intent, Intent::Search);
+        
+        // Structural problems are still caught
+        assert!(matches!(Packet::from_bytes_unchecked(&bytes[..20]), Err(PacketError::TooSmall)));
+        
+        let mut bad_intent = packet.to_bytes();
+        bad_intent[17] = 0x5A;
+        assert!(matches!(
+            Packet::from_bytes_unchecked(&bad_intent),
+            Err(PacketError::InvalidIntent(0x5A))
+        ));
+        
+        let mut reserved_intent = packet.to_bytes();
+        reserved_intent[17] = 0x0F;
+        assert!(matches!(
+            Packet::from_bytes_unchecked(&reserved_intent),
+            Err(PacketError::ReservedIntent(0x0F))
+        ));
+        
+        let mut bad_length = packet.to_bytes();
+        bad_length.push(0);
+        assert!(matches!(
+            Packet::from_bytes_unchecked(&bad_length),
+            Err(PacketError::LengthMismatch)
+        ));
+    }
+    
+    #[test]
+    fn test_unsealed_packet_refused() {
+        let mut packet = Packet::new(SessionId::new(), Intent::Search, b"q".to_vec());
+        packet.set_priority(Priority::CRITICAL);
+        assert!(packet.is_dirty());
+        
+        // Forgot to reseal
+        assert!(matches!(packet.try_to_bytes(), Err(PacketError::UnsealedPacket)));
+        
+        packet.reseal();
+        assert!(!packet.is_dirty());
+        let recovered = Packet::from_bytes(&packet.try_to_bytes().unwrap()).unwrap();
+        assert_eq!(recovered.priority, Priority::CRITICAL);
+    }
+    
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "without reseal")]
+    fn test_unsealed_to_bytes_panics_in_debug() {
+        let mut packet = Packet::new(SessionId::new(), Intent::Search, vec![]);
+        packet.set_sequence(7);
+        packet.to_bytes();
+    }
+    
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_verify_failure_traced_with_session() {
+        use std::sync::{Arc, Mutex};
+        use tracing::field::{Field, Visit};
+        use tracing_subscriber::layer::{Context, SubscriberExt};
+        use tracing_subscriber::Layer;
+        
+        // collects the `session` field of every event
+        struct SessionCapture(Arc<Mutex<Vec<String>>>);
+        
+        struct SessionVisitor<'a>(&'a mut Option<String>);
+        impl Visit for SessionVisitor<'_> {
+            fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+                if field.name() == "session" {
+                    *self.0 = Some(format!("{:?}", value));
+                }
+            }
+        }
+        
+        impl<S: tracing::Subscriber> Layer<S> for SessionCapture {
+            fn on_event(&self, event: &tracing::Event<'_>, _ctx: Context<'_, S>) {
+                let mut session = None;
+                event.record(&mut SessionVisitor(&mut session));
+                if let Some(session) = session {
+                    self.0.lock().unwrap().push(session);
+                }
+            }
+        }
+        
+        let captured = Arc::new(Mutex::new(Vec::new()));
+        let subscriber = tracing_subscriber::registry().with(SessionCapture(captured.clone()));
+        
+        let mut packet = Packet::new(SessionId::from_bytes([0xAB; 16]), Intent::Search, vec![1, 2, 3]);
+        packet.payload[0] = 99; // tamper
+        
+        tracing::subscriber::with_default(subscriber, || {
+            assert!(!packet.verify());
+        });
+        
+        let captured = captured.lock().unwrap();
+        assert_eq!(captured.len(), 1);
+        assert_eq!(captured[0], packet.session_id.to_string());
+    }
+    
+    #[test]
+    fn test_extended_header_roundtrip() {
+        let mut packet = Packet::new(SessionId::new(), Intent::Search, b"with extras".to_vec());
+        packet.ext.unknown.push((0xE0, vec![9, 9, 9]));
+        packet.reseal();
+        
+        let bytes = packet.to_bytes();
+        assert!(Flags(bytes[19]).has_extended());
+        assert_eq!(bytes.len(), packet.size());
+        
+        let recovered = Packet::from_bytes(&bytes).unwrap();
+        assert_eq!(recovered.ext, packet.ext);
+        assert_eq!(recovered.payload, packet.payload);
+        
+        // no extended header -> plain layout, flag clear
+        let plain = Packet::new(SessionId::new(), Intent::Search, vec![1]);
+        let bytes = plain.to_bytes();
+        assert!(!Flags(bytes[19]).has_extended());
+        assert_eq!(bytes.len(), HEADER_SIZE + 1 + HASH_SIZE);
+    }
+    
+    #[test]
+    fn test_forward_with_extended_header() {
+        let mut packet = Packet::new(SessionId::new(), Intent::Search, vec![5; 10]);
+        packet.ext.unknown.push((0xE0, vec![1]));
+        packet.reseal();
+        
+        let bytes = packet.to_bytes();
+        let forwarded = Packet::forward(&bytes, Some(SessionId::from_bytes([3; 16]))).unwrap();
+        let recovered = Packet::from_bytes(&forwarded).unwrap();
+        assert_eq!(recovered.ext, packet.ext);
+    }
+    
+    #[test]
+    fn test_forward_rejects_bad_input() {
+        let packet = Packet::new(SessionId::new(), Intent::Ping, vec![1, 2, 3]);
+        let mut bytes = packet.to_bytes();
+        
+        // Truncated
+        assert!(matches!(Packet::forward(&bytes[..10], None), Err(PacketError::TooSmall)));
+        
+        // Corrupted payload must not get a fresh hash during a rewrite
+        bytes[HEADER_SIZE] ^= 0xFF;
+        let result = Packet::forward(&bytes, Some(SessionId::from_bytes([1; 16])));
+        assert!(matches!(result, Err(PacketError::InvalidHash)));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,147 @@
+//payload padding against traffic analysis
+//even encrypted, the payload length says a lot (a short search vs a long one, which page was fetched)
+//padding rounds every payload up to a multiple of a block size so packets of one size class look alike
+//
+//padded payload layout:
+// 4 bytes   | real payload length (u32, big-endian)
+// n bytes   | the real payload
+// rest      | random bytes up to the block boundary
+//
+//the real length lives inside the payload rather than in the extended header on purpose:
+//the extended header goes in the clear, the payload gets encrypted, so the length stays hidden
+//the extended header only carries the "padded" marker
+
+use rand::RngCore;
+
+use super::packet::*;
+
+const PAD_LENGTH_SIZE: usize = 4;
+
+impl Packet {
+    /// Pad the payload up to the next multiple of `block_size` and reseal
+    /// Pad before encrypting so the length prefix ends up encrypted too
+    pub fn pad(&mut self, block_size: usize) -> Result<(), PacketError> {
+        if block_size == 0 || self.ext.padded {
+            return Err(PacketError::InvalidPadding);
+        }
+
+        let needed = PAD_LENGTH_SIZE + self.payload.len();
+        let padded_len = needed.div_ceil(block_size) * block_size;
+        if padded_len > MAX_PAYLOAD_SIZE {
+            return Err(PacketError::TooLarge);
+        }
+
+        let mut padded = Vec::with_capacity(padded_len);
+        padded.extend_from_slice(&(self.payload.len() as u32).to_be_bytes());
+        padded.extend_from_slice(&self.payload);
+        padded.resize(padded_len, 0);
+        rand::thread_rng().fill_bytes(&mut padded[needed..]);
+
+        self.payload = padded;
+        self.ext.padded = true;
+        self.reseal();
+        Ok(())
+    }
+
+    /// The payload without padding (unpadded packets return the payload as-is)
+    pub fn unpadded_payload(&self) -> Result<&[u8], PacketError> {
+        if !self.ext.padded {
+            return Ok(&self.payload);
+        }
+        if self.payload.len() < PAD_LENGTH_SIZE {
+            return Err(PacketError::InvalidPadding);
+        }
+
+        let mut len_bytes = [0u8; PAD_LENGTH_SIZE];
+        len_bytes.copy_from_slice(&self.payload[..PAD_LENGTH_SIZE]);
+        let real_len = u32::from_be_bytes(len_bytes) as usize;
+
+        self.payload
+            .get(PAD_LENGTH_SIZE..PAD_LENGTH_SIZE + real_len)
+            .ok_or(PacketError::InvalidPadding)
+    }
+
+    /// Remove the padding in place and reseal
+    pub fn strip_padding(&mut self) -> Result<(), PacketError> {
+        if !self.ext.padded {
+            return Ok(());
+        }
+        self.payload = self.unpadded_payload()?.to_vec();
+        self.ext.padded = false;
+        self.reseal();
+        Ok(())
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::builder::PacketBuilder;
+    use crate::packet::types::*;
+
+    #[test]
+    fn test_pad_100_bytes_to_256_block() {
+        let original: Vec<u8> = (0..100u8).collect();
+        let packet = PacketBuilder::new(SessionId::new(), Intent::Search)
+            .payload(original.clone())
+            .pad_to(256)
+            .flags(Flags::new())
+            .insecure_allow_plaintext()
+            .build()
+            .unwrap();
+
+        assert_eq!(packet.payload.len(), 256);
+        assert!(packet.ext.padded);
+
+        // receiver side
+        let recovered = Packet::from_bytes(&packet.to_bytes()).unwrap();
+        assert!(recovered.ext.padded);
+        assert_eq!(recovered.unpadded_payload().unwrap(), &original[..]);
+    }
+
+    #[test]
+    fn test_same_size_class() {
+        // different lengths, same block -> same wire size
+        let short = PacketBuilder::new(SessionId::new(), Intent::Search)
+            .payload(vec![1; 10])
+            .pad_to(128)
+            .flags(Flags::new())
+            .insecure_allow_plaintext()
+            .build()
+            .unwrap();
+        let long = PacketBuilder::new(SessionId::new(), Intent::Search)
+            .payload(vec![1; 120])
+            .pad_to(128)
+            .flags(Flags::new())
+            .insecure_allow_plaintext()
+            .build()
+            .unwrap();
+        assert_eq!(short.size(), long.size());
+    }
+
+    #[test]
+    fn test_strip_padding() {
+        let mut packet = Packet::new(SessionId::new(), Intent::DataPush, b"secret".to_vec());
+        packet.pad(64).unwrap();
+        packet.strip_padding().unwrap();
+
+        assert_eq!(packet.payload, b"secret".to_vec());
+        assert!(!packet.ext.padded);
+        assert!(packet.verify());
+    }
+
+    #[test]
+    fn test_bad_padding_rejected() {
+        let mut packet = Packet::new(SessionId::new(), Intent::DataPush, vec![1, 2, 3]);
+        assert!(matches!(packet.pad(0), Err(PacketError::InvalidPadding)));
+
+        // length prefix pointing past the end
+        packet.payload = vec![0, 0, 1, 0, 9, 9];
+        packet.ext.padded = true;
+        assert!(matches!(packet.unpadded_payload(), Err(PacketError::InvalidPadding)));
+    }
+}
@@ -0,0 +1,111 @@
+//Ed25519 signatures for authenticity
+//the hash only tells us the packet wasn't damaged, anyone who tampers can just recompute it
+//a signature over the hash proves the packet came from whoever holds the signing key
+//it's carried in the extended header and is the one thing the hash doesn't cover
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+
+use super::extended::SIGNATURE_SIZE;
+use super::packet::*;
+
+impl Packet {
+    /// Sign the packet, storing the signature in the extended header
+    /// Reseals first, so sign after all other changes
+    pub fn sign(&mut self, key: &SigningKey) {
+        // the slot has to exist before hashing, it flips the extended header flag
+        self.ext.signature = Some([0u8; SIGNATURE_SIZE]);
+        self.reseal();
+        self.ext.signature = Some(key.sign(&self.hash).to_bytes());
+        self.refresh_header();
+    }
+
+    /// Check the packet is intact and signed by the holder of `key`
+    /// Unsigned packets never pass
+    pub fn verify_signature(&self, key: &VerifyingKey) -> bool {
+        let Some(signature) = &self.ext.signature else {
+            return false;
+        };
+        self.verify() && key.verify(&self.hash, &Signature::from_bytes(signature)).is_ok()
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::types::*;
+
+    fn signing_key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    #[test]
+    fn test_retarget_invalidates_signature() {
+        let key = signing_key(1);
+        let mut packet = Packet::new(SessionId::new(), Intent::Search, b"migrating".to_vec());
+        packet.sign(&key);
+
+        let new_session = SessionId::new();
+        packet.retarget(new_session);
+
+        assert_eq!(packet.session_id, new_session);
+        assert!(packet.verify());
+        assert!(!packet.verify_signature(&key.verifying_key()));
+
+        // re-signing fixes it
+        packet.sign(&key);
+        assert!(packet.verify_signature(&key.verifying_key()));
+    }
+
+    #[test]
+    fn test_forward_rewrite_invalidates_signature() {
+        let key = signing_key(1);
+        let mut packet = Packet::new(SessionId::new(), Intent::Search, b"relayed".to_vec());
+        packet.sign(&key);
+
+        let bytes = packet.to_bytes();
+        let forwarded = Packet::forward(&bytes, Some(SessionId::new())).unwrap();
+        let recovered = Packet::from_bytes(&forwarded).unwrap();
+        assert!(recovered.verify());
+        assert!(!recovered.verify_signature(&key.verifying_key()));
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let key = signing_key(1);
+        let mut packet = Packet::new(SessionId::new(), Intent::Search, b"signed query".to_vec());
+        packet.sign(&key);
+
+        assert!(packet.verify_signature(&key.verifying_key()));
+
+        // survives the wire
+        let recovered = Packet::from_bytes(&packet.to_bytes()).unwrap();
+        assert!(recovered.verify_signature(&key.verifying_key()));
+
+        // but not someone else's key
+        assert!(!recovered.verify_signature(&signing_key(2).verifying_key()));
+    }
+
+    #[test]
+    fn test_tampered_payload_fails_signature() {
+        let key = signing_key(1);
+        let mut packet = Packet::new(SessionId::new(), Intent::DataPush, vec![1, 2, 3, 4]);
+        packet.sign(&key);
+
+        // attacker changes the payload and fixes up the hash
+        packet.payload[0] = 99;
+        packet.reseal();
+
+        assert!(packet.verify());
+        assert!(!packet.verify_signature(&key.verifying_key()));
+    }
+
+    #[test]
+    fn test_unsigned_packet_fails_signature() {
+        let packet = Packet::new(SessionId::new(), Intent::Ping, vec![]);
+        assert!(!packet.verify_signature(&signing_key(1).verifying_key()));
+    }
+}
@@ -0,0 +1,119 @@
+//header template for sending lots of packets with the same session/intent/priority/flags
+//cloning a whole packet just to reuse its header drags the payload along, the template only
+//keeps the header fields and stamps out packets with a fresh timestamp and the next sequence
+
+use super::packet::*;
+use super::types::*;
+
+#[derive(Debug, Clone)]
+pub struct PacketTemplate {
+    session_id: SessionId,
+    intent: Intent,
+    priority: Priority,
+    flags: Flags,
+    next_sequence: Sequence,
+}
+
+impl PacketTemplate {
+    /// Priority from the intent, plain flags (no compression, no encryption): instantiate
+    /// puts payloads in as given, so the flags can't promise anything about them by default
+    pub fn new(session_id: SessionId, intent: Intent) -> Self {
+        PacketTemplate {
+            session_id,
+            intent,
+            priority: Priority::for_intent(intent),
+            flags: Flags::new(),
+            next_sequence: 0,
+        }
+    }
+
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// What the payloads handed to instantiate already are (compressed, encrypted),
+    /// the template doesn't compress or encrypt anything itself
+    pub fn flags(mut self, flags: Flags) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    /// Sequence the next instantiated packet gets, counts up from there
+    pub fn starting_at(mut self, sequence: Sequence) -> Self {
+        self.next_sequence = sequence;
+        self
+    }
+
+    /// A sealed packet with these headers around `payload`
+    pub fn instantiate(&mut self, payload: Vec<u8>) -> Packet {
+        let mut packet = Packet::new(self.session_id, self.intent, payload);
+        packet.priority = self.priority;
+        packet.flags = self.flags;
+        packet.sequence = self.next_sequence;
+        packet.reseal();
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        packet
+    }
+}
+
+impl Packet {
+    /// Template with this packet's headers, the payload isn't copied
+    /// Instantiated packets continue after this packet's sequence and keep its flags, so
+    /// their payloads have to be encoded the same way
+    pub fn clone_header_only(&self) -> PacketTemplate {
+        PacketTemplate::new(self.session_id, self.intent)
+            .priority(self.priority)
+            .flags(self.flags)
+            .starting_at(self.sequence.wrapping_add(1))
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_template_instantiate() {
+        let session_id = SessionId::new();
+        let mut template = PacketTemplate::new(session_id, Intent::DataPush)
+            .priority(Priority::HIGH)
+            .starting_at(10);
+
+        let packets: Vec<Packet> = [&b"one"[..], b"two", b"three"]
+            .iter()
+            .map(|payload| template.instantiate(payload.to_vec()))
+            .collect();
+
+        for packet in &packets {
+            assert_eq!(packet.session_id, session_id);
+            assert_eq!(packet.intent, Intent::DataPush);
+            assert_eq!(packet.priority, Priority::HIGH);
+            // plaintext in, plaintext on the label
+            assert_eq!(packet.flags.compression(), Compression::None);
+            assert_eq!(packet.flags.encryption(), EncryptionLevel::None);
+            assert!(packet.verify());
+        }
+        let sequences: Vec<Sequence> = packets.iter().map(|p| p.sequence).collect();
+        assert_eq!(sequences, vec![10, 11, 12]);
+        assert_ne!(packets[0].payload, packets[1].payload);
+        assert_ne!(packets[0].hash, packets[1].hash);
+        assert_ne!(packets[1].hash, packets[2].hash);
+    }
+
+    #[test]
+    fn test_clone_header_only() {
+        let mut original = Packet::new(SessionId::new(), Intent::SearchStream, vec![0u8; 4096]);
+        original.set_sequence(7);
+        original.reseal();
+
+        let next = original.clone_header_only().instantiate(b"small".to_vec());
+        assert_eq!(next.session_id, original.session_id);
+        assert_eq!(next.flags, original.flags);
+        assert_eq!(next.sequence, 8);
+    }
+}
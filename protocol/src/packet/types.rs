@@ -1,309 +1,815 @@
-//the core data types we will be using in our packet structure, like flags, compression types, encryption levels, etc. This is where we define the building blocks of our protocol.
-//zero copy, avoid unnecessary copying of data, we will be using references and slices to handle payloads and other data efficiently.
-//each connection has a unique id
-use std::fmt;
-
-// ============================================================================
-// PROTOCOL VERSION
-// ============================================================================
-// We start at version 1. Future versions can add features while staying compatible
-pub const FDP_VERSION: u8 = 1;
-
-// ============================================================================
-// INTENT TYPES - What the user WANTS to do
-// ============================================================================
-// This is REVOLUTIONARY compared to HTTP's GET/POST/PUT/DELETE
-// We express SEMANTIC meaning, not just CRUD operations
-//
-// Each Intent is 1 byte (u8), so we can have 256 different intents
-#[repr(u8)]  // This means: store as a single byte
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum Intent {
-    // ---------- BASIC OPERATIONS ----------
-    /// Ping to check if connection is alive
-    Ping = 0x01,
-    
-    /// Response to a ping
-    Pong = 0x02,
-    
-    /// Establish a new session
-    HandshakeInit = 0x03,
-    
-    /// Acknowledge handshake
-    HandshakeAck = 0x04,
-    
-    /// Gracefully close session
-    Close = 0x05,
-    
-    // ---------- SEARCH OPERATIONS ----------
-    /// Perform a search query
-    /// Payload: search terms + filters
-    Search = 0x10,
-    
-    /// Get suggested completions as user types
-    SearchSuggest = 0x11,
-    
-    /// Fetch a specific document by hash
-    FetchDocument = 0x12,
-    
-    /// Subscribe to search result updates (real-time)
-    SearchStream = 0x13,
-    
-    // ---------- DATA SYNC ----------
-    /// Request specific data by content hash
-    DataRequest = 0x20,
-    
-    /// Push data to receiver
-    DataPush = 0x21,
-    
-    /// Notify about data changes (delta sync)
-    DataDelta = 0x22,
-    
-    /// Verify data integrity
-    DataVerify = 0x23,
-    
-    // ---------- RANKING & PERSONALIZATION ----------
-    /// Upload user ranking preferences (encrypted)
-    RankingUpdate = 0x30,
-    
-    /// Request personalized ranking for results
-    RankingRequest = 0x31,
-    
-    // ---------- EDGE/CACHE ----------
-    /// Request from edge cache
-    CacheQuery = 0x40,
-    
-    /// Invalidate cached data
-    CacheInvalidate = 0x41,
-    
-    // ---------- ERROR & STATUS ----------
-    /// Generic error response
-    Error = 0xF0,
-    
-    /// Success acknowledgment
-    Success = 0xF1,
-}
-
-impl Intent {
-    /// Convert a byte to an Intent
-    /// Returns None if the byte doesn't match any known Intent
-    pub fn from_u8(byte: u8) -> Option<Self> {
-        match byte {
-            0x01 => Some(Intent::Ping),
-            0x02 => Some(Intent::Pong),
-            0x03 => Some(Intent::HandshakeInit),
-            0x04 => Some(Intent::HandshakeAck),
-            0x05 => Some(Intent::Close),
-            0x10 => Some(Intent::Search),
-            0x11 => Some(Intent::SearchSuggest),
-            0x12 => Some(Intent::FetchDocument),
-            0x13 => Some(Intent::SearchStream),
-            0x20 => Some(Intent::DataRequest),
-            0x21 => Some(Intent::DataPush),
-            0x22 => Some(Intent::DataDelta),
-            0x23 => Some(Intent::DataVerify),
-            0x30 => Some(Intent::RankingUpdate),
-            0x31 => Some(Intent::RankingRequest),
-            0x40 => Some(Intent::CacheQuery),
-            0x41 => Some(Intent::CacheInvalidate),
-            0xF0 => Some(Intent::Error),
-            0xF1 => Some(Intent::Success),
-            _ => None,
-        }
-    }
-    
-    /// Convert Intent to byte for sending over network
-    pub fn to_u8(self) -> u8 {
-        self as u8
-    }
-}
-
-// ============================================================================
-// COMPRESSION TYPES
-// ============================================================================
-// Different compression algorithms, ranked by speed vs compression ratio
-#[repr(u8)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum Compression {
-    /// No compression (useful for already compressed data like images)
-    None = 0x00,
-    
-    /// LZ4 - SUPER FAST, decent compression (~2-3x)
-    /// Best for: real-time communication, small packets
-    Lz4 = 0x01,
-    
-    /// Zstd - FAST, good compression (~3-5x)
-    /// Best for: general purpose, balanced speed/ratio
-    Zstd = 0x02,
-    
-    /// Brotli - SLOWER, best compression (~4-6x)
-    /// Best for: static content, one-time transfers
-    Brotli = 0x03,
-}
-
-impl Compression {
-    pub fn from_u8(byte: u8) -> Option<Self> {
-        match byte {
-            0x00 => Some(Compression::None),
-            0x01 => Some(Compression::Lz4),
-            0x02 => Some(Compression::Zstd),
-            0x03 => Some(Compression::Brotli),
-            _ => None,
-        }
-    }
-    
-    pub fn to_u8(self) -> u8 {
-        self as u8
-    }
-}
-
-// ============================================================================
-// ENCRYPTION LEVEL
-// ============================================================================
-#[repr(u8)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum EncryptionLevel {
-    /// NO ENCRYPTION - Only use for testing on localhost!
-    /// NEVER use on real network
-    None = 0x00,
-    
-    /// ChaCha20-Poly1305 - Fast, secure, modern
-    /// This is what we use by default
-    ChaCha20 = 0x01,
-    
-    /// AES-256-GCM - Industry standard, hardware accelerated on most CPUs
-    Aes256 = 0x02,
-}
-
-impl EncryptionLevel {
-    pub fn from_u8(byte: u8) -> Option<Self> {
-        match byte {
-            0x00 => Some(EncryptionLevel::None),
-            0x01 => Some(EncryptionLevel::ChaCha20),
-            0x02 => Some(EncryptionLevel::Aes256),
-            _ => None,
-        }
-    }
-    
-    pub fn to_u8(self) -> u8 {
-        self as u8
-    }
-}
-
-// ============================================================================
-// SESSION ID - Unique identifier for each connection
-// ============================================================================
-// 16 bytes = 128 bits = enough for 2^128 unique sessions
-// This is more IDs than atoms in the universe, so we'll never run out
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub struct SessionId(pub [u8; 16]);
-
-impl SessionId {
-    /// Create a new random session ID
-    pub fn new() -> Self {
-        use std::time::{SystemTime, UNIX_EPOCH};
-        
-        // For now, use timestamp + random bytes
-        // In production, use a proper UUID library
-        let mut bytes = [0u8; 16];
-        
-        // First 8 bytes: timestamp (nanoseconds)
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_nanos() as u64;
-        
-        bytes[0..8].copy_from_slice(&timestamp.to_be_bytes());
-        
-        // Last 8 bytes: random (in production, use crypto RNG)
-        // For now, use timestamp again (NOT SECURE, just for prototype)
-        bytes[8..16].copy_from_slice(&timestamp.to_le_bytes());
-        
-        SessionId(bytes)
-    }
-    
-    /// Create from existing bytes
-    pub fn from_bytes(bytes: [u8; 16]) -> Self {
-        SessionId(bytes)
-    }
-    
-    /// Get the raw bytes
-    pub fn as_bytes(&self) -> &[u8; 16] {
-        &self.0
-    }
-}
-
-impl fmt::Display for SessionId {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        // Display as hex string: "1a2b3c4d..."
-        for byte in &self.0 {
-            write!(f, "{:02x}", byte)?;
-        }
-        Ok(())
-    }
-}
-
-// ============================================================================
-// PRIORITY LEVELS
-// ============================================================================
-// Higher number = higher priority
-// This lets urgent packets skip ahead in the queue
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Priority(pub u8);
-
-impl Priority {
-    /// Lowest priority - background tasks
-    pub const LOWEST: Priority = Priority(0);
-    
-    /// Low priority - prefetching, caching
-    pub const LOW: Priority = Priority(64);
-    
-    /// Normal priority - user-initiated actions
-    pub const NORMAL: Priority = Priority(128);
-    
-    /// High priority - important user interactions
-    pub const HIGH: Priority = Priority(192);
-    
-    /// Critical priority - system messages, errors
-    pub const CRITICAL: Priority = Priority(255);
-}
-
-// ============================================================================
-// TESTS - Make sure our types work correctly
-// ============================================================================
-#[cfg(test)]
-mod tests {
-    use super::*;
-    
-    #[test]
-    fn test_intent_roundtrip() {
-        // Test that we can convert Intent to byte and back
-        let intent = Intent::Search;
-        let byte = intent.to_u8();
-        let recovered = Intent::from_u8(byte).unwrap();
-        assert_eq!(intent, recovered);
-    }
-    
-    #[test]
-    fn test_session_id_creation() {
-        let id1 = SessionId::new();
-        let id2 = SessionId::new();
-        
-        // Two IDs should be different (probability of collision is astronomically low)
-        assert_ne!(id1, id2);
-    }
-    
-    #[test]
-    fn test_priority_ordering() {
-        assert!(Priority::CRITICAL > Priority::HIGH);
-        assert!(Priority::HIGH > Priority::NORMAL);
-        assert!(Priority::NORMAL > Priority::LOW);
-        assert!(Priority::LOW > Priority::LOWEST);
-    }
-    
-    #[test]
-    fn test_compression_bytes() {
-        assert_eq!(Compression::Lz4.to_u8(), 0x01);
-        assert_eq!(Compression::from_u8(0x02).unwrap(), Compression::Zstd);
-    }
+//the core data types we will be using in our packet structure, like flags, compression types, encryption levels, etc. This is where we define the building blocks of our protocol.
+//zero copy, avoid unnecessary copying of data, we will be using references and slices to handle payloads and other data efficiently.
+//each connection has a unique id
+use std::fmt;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+// ============================================================================
+// PROTOCOL VERSION
+// ============================================================================
+// We start at version 1. Future versions can add features while staying compatible
+pub const FDP_VERSION: u8 = 1;
+
+// Every version this build can parse, handed back to peers that send something else
+// so they can pick one we both speak
+pub const SUPPORTED_VERSIONS: &[u8] = &[FDP_VERSION];
+
+/// Can we parse packets of this version?
+pub fn is_version_supported(version: u8) -> bool {
+    SUPPORTED_VERSIONS.contains(&version)
+}
+
+// ============================================================================
+// INTENT TYPES - What the user WANTS to do
+// ============================================================================
+// This is REVOLUTIONARY compared to HTTP's GET/POST/PUT/DELETE
+// We express SEMANTIC meaning, not just CRUD operations
+//
+// Each Intent is 1 byte (u8) on the wire, so we can have 256 different intents
+// 0x80-0xEF belong to applications (Intent::Custom), the protocol never assigns those
+#[repr(u8)]  // so the wire bytes can be written next to the variants below
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Intent {
+    // ---------- BASIC OPERATIONS ----------
+    /// Ping to check if connection is alive
+    Ping = 0x01,
+    
+    /// Response to a ping
+    Pong = 0x02,
+    
+    /// Establish a new session
+    HandshakeInit = 0x03,
+    
+    /// Acknowledge handshake
+    HandshakeAck = 0x04,
+    
+    /// Gracefully close session
+    Close = 0x05,
+    
+    /// Acknowledge received sequences, several at once
+    /// Payload: list of sequence ranges (see payload/ack.rs)
+    Ack = 0x06,
+    
+    /// Ask the sender to resend specific missing sequences
+    /// Payload: list of sequence ranges (see payload/nack.rs)
+    Nack = 0x07,
+    
+    /// Abort an in-flight request (a long Search or DataPush)
+    /// Payload: sequence of the request to abort (u32, big-endian)
+    Cancel = 0x08,
+    
+    /// Tell the sender how many more packets we can take
+    /// Payload: window size (u32, big-endian)
+    WindowUpdate = 0x09,
+    
+    // ---------- SEARCH OPERATIONS ----------
+    /// Perform a search query
+    /// Payload: search terms + filters
+    Search = 0x10,
+    
+    /// Get suggested completions as user types
+    SearchSuggest = 0x11,
+    
+    /// Fetch a specific document by hash
+    FetchDocument = 0x12,
+    
+    /// Subscribe to search result updates (real-time)
+    SearchStream = 0x13,
+    
+    // ---------- DATA SYNC ----------
+    /// Request specific data by content hash
+    DataRequest = 0x20,
+    
+    /// Push data to receiver
+    DataPush = 0x21,
+    
+    /// Notify about data changes (delta sync)
+    DataDelta = 0x22,
+    
+    /// Verify data integrity
+    DataVerify = 0x23,
+    
+    // ---------- RANKING & PERSONALIZATION ----------
+    /// Upload user ranking preferences (encrypted)
+    RankingUpdate = 0x30,
+    
+    /// Request personalized ranking for results
+    RankingRequest = 0x31,
+    
+    // ---------- EDGE/CACHE ----------
+    /// Request from edge cache
+    CacheQuery = 0x40,
+    
+    /// Invalidate cached data
+    CacheInvalidate = 0x41,
+    
+    // ---------- APPLICATION DEFINED ----------
+    /// Anything in 0x80-0xEF, meaning is up to the application
+    /// The protocol only routes these, it never looks inside
+    /// (the 0x80 is just a placeholder discriminant, the wire byte is the one carried)
+    Custom(u8) = 0x80,
+    
+    // ---------- ERROR & STATUS ----------
+    /// Generic error response
+    Error = 0xF0,
+    
+    /// Success acknowledgment
+    Success = 0xF1,
+}
+
+impl Intent {
+    /// Convert a byte to an Intent
+    /// Returns None if the byte doesn't match any known Intent
+    pub fn from_u8(byte: u8) -> Option<Self> {
+        match byte {
+            0x01 => Some(Intent::Ping),
+            0x02 => Some(Intent::Pong),
+            0x03 => Some(Intent::HandshakeInit),
+            0x04 => Some(Intent::HandshakeAck),
+            0x05 => Some(Intent::Close),
+            0x06 => Some(Intent::Ack),
+            0x07 => Some(Intent::Nack),
+            0x08 => Some(Intent::Cancel),
+            0x09 => Some(Intent::WindowUpdate),
+            0x10 => Some(Intent::Search),
+            0x11 => Some(Intent::SearchSuggest),
+            0x12 => Some(Intent::FetchDocument),
+            0x13 => Some(Intent::SearchStream),
+            0x20 => Some(Intent::DataRequest),
+            0x21 => Some(Intent::DataPush),
+            0x22 => Some(Intent::DataDelta),
+            0x23 => Some(Intent::DataVerify),
+            0x30 => Some(Intent::RankingUpdate),
+            0x31 => Some(Intent::RankingRequest),
+            0x40 => Some(Intent::CacheQuery),
+            0x41 => Some(Intent::CacheInvalidate),
+            0xF0 => Some(Intent::Error),
+            0xF1 => Some(Intent::Success),
+            byte if Self::is_custom(byte) => Some(Intent::Custom(byte)),
+            _ => None,
+        }
+    }
+    
+    /// Convert Intent to byte for sending over network
+    pub fn to_u8(self) -> u8 {
+        match self {
+            Intent::Ping => 0x01,
+            Intent::Pong => 0x02,
+            Intent::HandshakeInit => 0x03,
+            Intent::HandshakeAck => 0x04,
+            Intent::Close => 0x05,
+            Intent::Ack => 0x06,
+            Intent::Nack => 0x07,
+            Intent::Cancel => 0x08,
+            Intent::WindowUpdate => 0x09,
+            Intent::Search => 0x10,
+            Intent::SearchSuggest => 0x11,
+            Intent::FetchDocument => 0x12,
+            Intent::SearchStream => 0x13,
+            Intent::DataRequest => 0x20,
+            Intent::DataPush => 0x21,
+            Intent::DataDelta => 0x22,
+            Intent::DataVerify => 0x23,
+            Intent::RankingUpdate => 0x30,
+            Intent::RankingRequest => 0x31,
+            Intent::CacheQuery => 0x40,
+            Intent::CacheInvalidate => 0x41,
+            Intent::Error => 0xF0,
+            Intent::Success => 0xF1,
+            Intent::Custom(byte) => byte,
+        }
+    }
+    
+    /// Application-defined intent, None if `byte` is outside 0x80-0xEF
+    pub fn custom(byte: u8) -> Option<Self> {
+        Self::is_custom(byte).then_some(Intent::Custom(byte))
+    }
+    
+    /// Is this byte in the application-defined range?
+    pub fn is_custom(byte: u8) -> bool {
+        (0x80..=0xEF).contains(&byte)
+    }
+    
+    /// Is this byte inside one of the ranges set aside for a group of intents?
+    /// True for assigned intents too, false means nobody has claimed the byte yet
+    ///
+    /// 0x00-0x0F control, 0x10-0x1F search, 0x20-0x2F data sync,
+    /// 0x30-0x3F ranking, 0x40-0x4F edge/cache, 0xF0-0xFF error/status,
+    /// 0x50-0x7F free, 0x80-0xEF application-defined (see is_custom)
+    pub fn is_reserved(byte: u8) -> bool {
+        Self::reserved_range(byte).is_some()
+    }
+    
+    /// Name of the reserved range a byte falls in, None when it's free
+    pub fn reserved_range(byte: u8) -> Option<&'static str> {
+        match byte {
+            0x00..=0x0F => Some("control"),
+            0x10..=0x1F => Some("search"),
+            0x20..=0x2F => Some("data"),
+            0x30..=0x3F => Some("ranking"),
+            0x40..=0x4F => Some("cache"),
+            0xF0..=0xFF => Some("status"),
+            _ => None,
+        }
+    }
+    
+    /// Intents that answer another packet
+    /// Asking for an ack on these makes the two sides ack each other forever
+    pub fn is_response(self) -> bool {
+        matches!(self, Intent::Pong | Intent::HandshakeAck | Intent::Ack | Intent::Success)
+    }
+    
+    /// How long (ms) a packet of this intent is worth delivering, PacketBuilder turns it into a deadline
+    /// None for everything that's still better late than never
+    pub fn default_ttl(self) -> Option<u64> {
+        match self {
+            // typeahead, the user has typed on by then
+            Intent::SearchSuggest => Some(500),
+            _ => None,
+        }
+    }
+    
+    /// How delay-sensitive this intent is, for a transport/QoS layer picking a queue or DSCP mark
+    /// Priority orders packets in our own queue, this is the hint for everything below it
+    pub fn latency_class(self) -> LatencyClass {
+        match self {
+            Intent::Ping
+            | Intent::Pong
+            | Intent::HandshakeInit
+            | Intent::HandshakeAck
+            | Intent::Close
+            | Intent::Ack
+            | Intent::Nack
+            | Intent::WindowUpdate
+            | Intent::Cancel
+            | Intent::Search
+            | Intent::SearchSuggest
+            | Intent::FetchDocument
+            | Intent::RankingRequest
+            | Intent::CacheQuery
+            | Intent::Error
+            | Intent::Success => LatencyClass::Interactive,
+            
+            Intent::SearchStream
+            | Intent::DataRequest
+            | Intent::DataPush
+            | Intent::DataDelta
+            | Intent::Custom(_) => LatencyClass::Bulk,
+            
+            Intent::DataVerify
+            | Intent::RankingUpdate
+            | Intent::CacheInvalidate => LatencyClass::Background,
+        }
+    }
+}
+
+// ============================================================================
+// LATENCY CLASS
+// ============================================================================
+// Coarse delivery expectation per intent, maps onto DSCP / per-class queues
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LatencyClass {
+    /// Someone is waiting on it (typing, ping), aim for well under 100ms
+    Interactive,
+    
+    /// Throughput matters more than latency
+    Bulk,
+    
+    /// Whenever there's spare capacity
+    Background,
+}
+
+// ============================================================================
+// COMPRESSION TYPES
+// ============================================================================
+// Different compression algorithms, ranked by speed vs compression ratio
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// No compression (useful for already compressed data like images)
+    None = 0x00,
+    
+    /// LZ4 - SUPER FAST, decent compression (~2-3x)
+    /// Best for: real-time communication, small packets
+    Lz4 = 0x01,
+    
+    /// Zstd - FAST, good compression (~3-5x)
+    /// Best for: general purpose, balanced speed/ratio
+    Zstd = 0x02,
+    
+    /// Brotli - SLOWER, best compression (~4-6x)
+    /// Best for: static content, one-time transfers
+    Brotli = 0x03,
+    
+    /// Zstd with a session dictionary both ends already hold
+    /// Best for: lots of small, similar payloads (search queries)
+    ZstdDict = 0x04,
+    
+    /// Pick per payload: None for formats that are compressed already, Zstd for the rest
+    /// Local only, it's resolved to one of the above before encoding and never goes on the
+    /// wire (from_u8 doesn't know it)
+    Auto = 0x07,
+}
+
+impl Compression {
+    pub fn from_u8(byte: u8) -> Option<Self> {
+        match byte {
+            0x00 => Some(Compression::None),
+            0x01 => Some(Compression::Lz4),
+            0x02 => Some(Compression::Zstd),
+            0x03 => Some(Compression::Brotli),
+            0x04 => Some(Compression::ZstdDict),
+            _ => None,
+        }
+    }
+    
+    pub fn to_u8(self) -> u8 {
+        self as u8
+    }
+}
+
+// ============================================================================
+// ENCRYPTION LEVEL
+// ============================================================================
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncryptionLevel {
+    /// NO ENCRYPTION - Only use for testing on localhost!
+    /// NEVER use on real network
+    None = 0x00,
+    
+    /// ChaCha20-Poly1305 - Fast, secure, modern
+    /// This is what we use by default
+    ChaCha20 = 0x01,
+    
+    /// AES-256-GCM - Industry standard, hardware accelerated on most CPUs
+    Aes256 = 0x02,
+}
+
+impl EncryptionLevel {
+    pub fn from_u8(byte: u8) -> Option<Self> {
+        match byte {
+            0x00 => Some(EncryptionLevel::None),
+            0x01 => Some(EncryptionLevel::ChaCha20),
+            0x02 => Some(EncryptionLevel::Aes256),
+            _ => None,
+        }
+    }
+    
+    pub fn to_u8(self) -> u8 {
+        self as u8
+    }
+}
+
+// ============================================================================
+// TIME RESOLUTION
+// ============================================================================
+// Unit of Packet::timestamp. Milliseconds unless the packet says otherwise in its extended
+// header, microseconds are for latency measurements. The 8 byte field holds either for
+// a few hundred thousand years
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimeResolution {
+    #[default]
+    Millis,
+    Micros,
+}
+
+impl TimeResolution {
+    pub fn from_u8(byte: u8) -> Option<Self> {
+        match byte {
+            0x00 => Some(TimeResolution::Millis),
+            0x01 => Some(TimeResolution::Micros),
+            _ => None,
+        }
+    }
+    
+    pub fn to_u8(self) -> u8 {
+        match self {
+            TimeResolution::Millis => 0x00,
+            TimeResolution::Micros => 0x01,
+        }
+    }
+    
+    /// Ticks per millisecond
+    pub fn per_ms(self) -> u64 {
+        match self {
+            TimeResolution::Millis => 1,
+            TimeResolution::Micros => 1_000,
+        }
+    }
+}
+
+// ============================================================================
+// SEQUENCE NUMBER
+// ============================================================================
+// 4 bytes on the wire, used for ordering and duplicate detection
+// Sequences wrap around, so per-session logic (dedup, monitor, migration) compares them with
+// seq_cmp instead of < and >
+pub type Sequence = u32;
+
+const HALF_SEQUENCE_SPACE: Sequence = 1 << 31;
+
+/// Serial number comparison (RFC 1982): `a` is after `b` when it's less than half the
+/// sequence space ahead of it, so u32::MAX comes before 0
+/// Exactly half the space apart is undefined in the RFC, here the numerically smaller one
+/// counts as before, so swapping the arguments always flips the answer
+/// Only for comparing sequences of one session that are close together: it isn't transitive
+/// across the whole space, so never sort or heap by it
+pub fn seq_cmp(a: Sequence, b: Sequence) -> std::cmp::Ordering {
+    match a.wrapping_sub(b) {
+        HALF_SEQUENCE_SPACE => a.cmp(&b),
+        distance => (distance as i32).cmp(&0),
+    }
+}
+
+// ============================================================================
+// SESSION ID - Unique identifier for each connection
+// ============================================================================
+// 16 bytes = 128 bits = enough for 2^128 unique sessions
+// This is more IDs than atoms in the universe, so we'll never run out
+//
+// The length is a const generic so deployments can pick something else:
+// 8 bytes for embedded links where every header byte counts, 32 for federations
+// that want extra collision resistance. Plain `SessionId` is the 16 byte default
+// and the wire layout for it is exactly what it always was.
+pub const SESSION_ID_SIZE: usize = 16;
+
+// Ord compares the bytes lexicographically, so ids can key a BTreeMap
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SessionId<const N: usize = SESSION_ID_SIZE>(pub [u8; N]);
+
+impl SessionId {
+    /// Create a new random session ID
+    pub fn new() -> Self {
+        use std::time::{SystemTime, UNIX_EPOCH};
+        
+        // For now, use timestamp + random bytes
+        // In production, use a proper UUID library
+        let mut bytes = [0u8; 16];
+        
+        // First 8 bytes: timestamp (nanoseconds)
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64;
+        
+        bytes[0..8].copy_from_slice(&timestamp.to_be_bytes());
+        
+        // Last 8 bytes: random (in production, use crypto RNG)
+        // For now, use timestamp again (NOT SECURE, just for prototype)
+        bytes[8..16].copy_from_slice(&timestamp.to_le_bytes());
+        
+        SessionId(bytes)
+    }
+    
+    /// Random session ID starting with a 2 byte namespace (tenant, federation member),
+    /// so an edge node can route on the prefix without a lookup
+    pub fn new_in_namespace(prefix: u16) -> Self {
+        let mut bytes: [u8; 16] = rand::random();
+        bytes[..2].copy_from_slice(&prefix.to_be_bytes());
+        SessionId(bytes)
+    }
+    
+    /// The namespace prefix, only meaningful for ids made by new_in_namespace
+    pub fn namespace(&self) -> u16 {
+        u16::from_be_bytes([self.0[0], self.0[1]])
+    }
+}
+
+impl<const N: usize> SessionId<N> {
+    /// Create from existing bytes
+    pub fn from_bytes(bytes: [u8; N]) -> Self {
+        SessionId(bytes)
+    }
+    
+    /// Get the raw bytes
+    pub fn as_bytes(&self) -> &[u8; N] {
+        &self.0
+    }
+    
+    /// Id of logical channel `channel` inside this session
+    /// Both ends compute the same one from the parent id, no round trip needed to open a channel
+    pub fn derive_child(&self, channel: u32) -> SessionId<N> {
+        // HKDF-Expand with the parent id as the key: block i is
+        // HMAC(parent, previous block | label | channel | i), as many blocks as N needs
+        let mut bytes = [0u8; N];
+        let mut previous: Vec<u8> = Vec::new();
+        for (i, chunk) in bytes.chunks_mut(32).enumerate() {
+            let mut mac = Hmac::<Sha256>::new_from_slice(&self.0).expect("HMAC takes any key size");
+            mac.update(&previous);
+            mac.update(b"fdp child session");
+            mac.update(&channel.to_be_bytes());
+            mac.update(&[i as u8 + 1]);
+            previous = mac.finalize().into_bytes().to_vec();
+            chunk.copy_from_slice(&previous[..chunk.len()]);
+        }
+        SessionId(bytes)
+    }
+}
+
+impl Default for SessionId {
+    fn default() -> Self {
+        SessionId::new()
+    }
+}
+
+impl<const N: usize> fmt::Display for SessionId<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Display as hex string: "1a2b3c4d..."
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+// ============================================================================
+// PRIORITY LEVELS
+// ============================================================================
+// Higher number = higher priority
+// This lets urgent packets skip ahead in the queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Priority(pub u8);
+
+/// How urgent something is, see Priority::from_class for the numbers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PriorityClass {
+    /// Sync, prefetch, anything nobody is waiting on
+    Background,
+    /// User-initiated actions
+    Normal,
+    /// Someone is waiting on the other end (typing, scrolling)
+    Interactive,
+    /// Session control and errors
+    System,
+}
+
+impl Priority {
+    /// Lowest priority - background tasks
+    pub const LOWEST: Priority = Priority(0);
+    
+    /// Low priority - prefetching, caching
+    pub const LOW: Priority = Priority(64);
+    
+    /// Normal priority - user-initiated actions
+    pub const NORMAL: Priority = Priority(128);
+    
+    /// High priority - important user interactions
+    pub const HIGH: Priority = Priority(192);
+    
+    /// Critical priority - system messages, errors
+    pub const CRITICAL: Priority = Priority(255);
+    
+    /// Sensible default priority for an intent
+    /// Session control and errors jump the queue, interactive search comes next,
+    /// bulk data and background updates go last
+    pub fn for_intent(intent: Intent) -> Priority {
+        match intent {
+            Intent::Error
+            | Intent::Close
+            | Intent::WindowUpdate
+            | Intent::HandshakeInit
+            | Intent::HandshakeAck => Priority::CRITICAL,
+            
+            Intent::Ping
+            | Intent::Pong
+            | Intent::Ack
+            | Intent::Nack
+            | Intent::Cancel
+            | Intent::Search
+            | Intent::SearchSuggest => Priority::HIGH,
+            
+            Intent::FetchDocument
+            | Intent::SearchStream
+            | Intent::DataRequest
+            | Intent::RankingRequest
+            | Intent::CacheQuery
+            | Intent::Success
+            | Intent::Custom(_) => Priority::NORMAL,
+            
+            Intent::DataPush
+            | Intent::DataDelta
+            | Intent::DataVerify
+            | Intent::RankingUpdate
+            | Intent::CacheInvalidate => Priority::LOW,
+        }
+    }
+    
+    /// The priority for a qualitative urgency, instead of a magic number
+    pub fn from_class(class: PriorityClass) -> Priority {
+        match class {
+            PriorityClass::Background => Priority::LOWEST,
+            PriorityClass::Normal => Priority::NORMAL,
+            PriorityClass::Interactive => Priority::HIGH,
+            PriorityClass::System => Priority::CRITICAL,
+        }
+    }
+    
+    /// Keep a priority inside [min, max]
+    /// Unlike Ord::clamp this never panics; if the bounds are inverted, min wins
+    pub fn clamp(self, min: Priority, max: Priority) -> Priority {
+        if self < min {
+            min
+        } else if self > max {
+            max
+        } else {
+            self
+        }
+    }
+}
+
+// ============================================================================
+// TESTS - Make sure our types work correctly
+// ============================================================================
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_intent_roundtrip() {
+        // Test that we can convert Intent to byte and back
+        let intent = Intent::Search;
+        let byte = intent.to_u8();
+        let recovered = Intent::from_u8(byte).unwrap();
+        assert_eq!(intent, recovered);
+        
+        // the flow control pair, Cancel has the value the spec gives it
+        assert_eq!(Intent::from_u8(0x08), Some(Intent::Cancel));
+        assert_eq!(Intent::from_u8(0x09), Some(Intent::WindowUpdate));
+        assert_eq!(Intent::WindowUpdate.to_u8(), 0x09);
+    }
+    
+    #[test]
+    fn test_intent_reserved_ranges() {
+        assert!(Intent::is_reserved(0x0F));
+        assert_eq!(Intent::reserved_range(0x0F), Some("control"));
+        assert_eq!(Intent::from_u8(0x0F), None); // reserved, but nothing assigned yet
+        
+        assert!(!Intent::is_reserved(0x50));
+        assert_eq!(Intent::reserved_range(0x50), None);
+        
+        // assigned intents sit inside their range
+        assert_eq!(Intent::reserved_range(Intent::DataPush.to_u8()), Some("data"));
+    }
+    
+    #[test]
+    fn test_custom_intent() {
+        let custom = Intent::from_u8(0x90).unwrap();
+        assert_eq!(custom, Intent::Custom(0x90));
+        assert_eq!(custom.to_u8(), 0x90);
+        assert_eq!(Intent::custom(0x90), Some(custom));
+        
+        // outside the application range
+        assert_eq!(Intent::custom(0x50), None);
+        assert_eq!(Intent::custom(0xF0), None);
+        
+        // protocol bytes still get their named variants
+        assert_eq!(Intent::from_u8(0xF0), Some(Intent::Error));
+        assert_eq!(Intent::from_u8(0x10), Some(Intent::Search));
+        for byte in 0..=u8::MAX {
+            if let Some(intent) = Intent::from_u8(byte) {
+                assert_eq!(intent.to_u8(), byte);
+            }
+        }
+    }
+    
+    #[test]
+    fn test_latency_class() {
+        assert_eq!(Intent::SearchSuggest.latency_class(), LatencyClass::Interactive);
+        assert_eq!(Intent::Ping.latency_class(), LatencyClass::Interactive);
+        assert_eq!(Intent::DataPush.latency_class(), LatencyClass::Bulk);
+        assert_eq!(Intent::Custom(0x90).latency_class(), LatencyClass::Bulk);
+        assert_eq!(Intent::CacheInvalidate.latency_class(), LatencyClass::Background);
+    }
+    
+    #[test]
+    fn test_session_id_creation() {
+        let id1 = SessionId::new();
+        let id2 = SessionId::new();
+        
+        // Two IDs should be different (probability of collision is astronomically low)
+        assert_ne!(id1, id2);
+    }
+    
+    #[test]
+    fn test_session_id_namespace() {
+        let a = SessionId::new_in_namespace(0xBEEF);
+        let b = SessionId::new_in_namespace(0xBEEF);
+        
+        assert_eq!(a.namespace(), 0xBEEF);
+        assert_eq!(a.as_bytes()[..2], b.as_bytes()[..2]);
+        assert_ne!(a, b);
+        assert_eq!(SessionId::new_in_namespace(7).namespace(), 7);
+    }
+    
+    #[test]
+    fn test_derive_child_session_id() {
+        let parent = SessionId::from_bytes([7u8; 16]);
+        
+        let first = parent.derive_child(1);
+        assert_eq!(first, SessionId::from_bytes([7u8; 16]).derive_child(1));
+        assert_ne!(first, parent.derive_child(2));
+        assert_ne!(first, parent);
+        assert_ne!(first, SessionId::from_bytes([8u8; 16]).derive_child(1));
+        
+        // longer ids are filled past one HMAC block
+        let wide_parent = SessionId::<40>::from_bytes([7u8; 40]);
+        let wide = wide_parent.derive_child(1);
+        assert_eq!(wide, wide_parent.derive_child(1));
+        assert_ne!(wide.as_bytes()[32..], [0u8; 8]);
+    }
+    
+    #[test]
+    fn test_session_id_ordering() {
+        use std::collections::BTreeMap;
+        
+        let mut raw = [[9u8; 16], [0u8; 16], [3u8; 16], [3u8; 16]];
+        raw[3][15] = 4; // differs only in the last byte
+        
+        let map: BTreeMap<SessionId, usize> = raw
+            .iter()
+            .enumerate()
+            .map(|(i, bytes)| (SessionId::from_bytes(*bytes), i))
+            .collect();
+        
+        let mut sorted = raw.to_vec();
+        sorted.sort();
+        let keys: Vec<[u8; 16]> = map.keys().map(|id| *id.as_bytes()).collect();
+        assert_eq!(keys, sorted);
+    }
+    
+    #[test]
+    fn test_priority_ordering() {
+        assert!(Priority::CRITICAL > Priority::HIGH);
+        assert!(Priority::HIGH > Priority::NORMAL);
+        assert!(Priority::NORMAL > Priority::LOW);
+        assert!(Priority::LOW > Priority::LOWEST);
+    }
+    
+    #[test]
+    fn test_priority_for_intent() {
+        assert_eq!(Priority::for_intent(Intent::Error), Priority::CRITICAL);
+        assert_eq!(Priority::for_intent(Intent::SearchSuggest), Priority::HIGH);
+        assert_eq!(Priority::for_intent(Intent::DataPush), Priority::LOW);
+    }
+    
+    #[test]
+    fn test_seq_cmp_across_wraparound() {
+        use std::cmp::Ordering;
+        
+        assert_eq!(seq_cmp(5, 5), Ordering::Equal);
+        assert_eq!(seq_cmp(1, 2), Ordering::Less);
+        assert_eq!(seq_cmp(2, 1), Ordering::Greater);
+        
+        // a plain comparison gets these backwards
+        assert_eq!(seq_cmp(0, u32::MAX), Ordering::Greater);
+        assert_eq!(seq_cmp(u32::MAX, 0), Ordering::Less);
+        assert_eq!(seq_cmp(3, u32::MAX - 3), Ordering::Greater);
+        assert_eq!(seq_cmp(u32::MAX - 10, 10), Ordering::Less);
+        
+        // up to half the space ahead is still ahead
+        assert_eq!(seq_cmp(i32::MAX as u32, 0), Ordering::Greater);
+        assert_eq!(seq_cmp(0, 1 << 31), Ordering::Less);
+        
+        // exactly half apart: still one way round, whichever order they're passed in
+        assert_eq!(seq_cmp(1 << 31, 0), Ordering::Greater);
+        assert_eq!(seq_cmp(5, 5 + (1 << 31)), Ordering::Less);
+        assert_eq!(seq_cmp(5 + (1 << 31), 5), Ordering::Greater);
+    }
+    
+    #[test]
+    fn test_priority_from_class() {
+        assert_eq!(Priority::from_class(PriorityClass::System), Priority::CRITICAL);
+        assert_eq!(Priority::from_class(PriorityClass::Interactive), Priority::HIGH);
+        assert_eq!(Priority::from_class(PriorityClass::Normal), Priority::NORMAL);
+        assert_eq!(Priority::from_class(PriorityClass::Background), Priority::LOWEST);
+    }
+    
+    #[test]
+    fn test_priority_clamp() {
+        // Out of range on both sides gets pulled back in
+        assert_eq!(Priority(250).clamp(Priority::LOW, Priority::HIGH), Priority::HIGH);
+        assert_eq!(Priority(3).clamp(Priority::LOW, Priority::HIGH), Priority::LOW);
+        
+        // In range is left alone
+        assert_eq!(Priority(100).clamp(Priority::LOW, Priority::HIGH), Priority(100));
+        
+        // Inverted bounds don't panic
+        assert_eq!(Priority(100).clamp(Priority::HIGH, Priority::LOW), Priority::HIGH);
+    }
+    
+    #[test]
+    fn test_version_supported() {
+        assert!(is_version_supported(FDP_VERSION));
+        assert!(!is_version_supported(0));
+        assert!(!is_version_supported(2));
+    }
+    
+    #[test]
+    fn test_compression_bytes() {
+        assert_eq!(Compression::Lz4.to_u8(), 0x01);
+        assert_eq!(Compression::from_u8(0x02).unwrap(), Compression::Zstd);
+    }
 }
\ No newline at end of file
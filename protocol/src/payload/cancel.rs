@@ -0,0 +1,48 @@
+//payload for Cancel, which request to abort
+//
+//Cancel payload: sequence (u32, big-endian) -the sequence of the request being cancelled,
+//in the same session as the Cancel itself
+
+use crate::packet::*;
+
+impl Packet {
+    /// Ask the peer to stop working on the request sent with `sequence`
+    pub fn cancel(session_id: SessionId, sequence: Sequence) -> Packet {
+        let mut packet = Packet::new(session_id, Intent::Cancel, sequence.to_be_bytes().to_vec());
+        packet.priority = Priority::for_intent(Intent::Cancel);
+        packet.reseal();
+        packet
+    }
+
+    /// The sequence a Cancel refers to
+    pub fn cancelled_sequence(&self) -> Result<Sequence, PacketError> {
+        if self.intent != Intent::Cancel {
+            return Err(PacketError::UnexpectedIntent(self.intent));
+        }
+        let bytes: [u8; 4] = self
+            .payload
+            .as_slice()
+            .try_into()
+            .map_err(|_| PacketError::InvalidPayload)?;
+        Ok(Sequence::from_be_bytes(bytes))
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancel_roundtrip() {
+        let cancel = Packet::cancel(SessionId::new(), 77);
+        let recovered = Packet::from_bytes(&cancel.to_bytes()).unwrap();
+        assert_eq!(recovered.cancelled_sequence().unwrap(), 77);
+
+        let ping = Packet::new(SessionId::new(), Intent::Ping, vec![]);
+        assert!(matches!(ping.cancelled_sequence(), Err(PacketError::UnexpectedIntent(Intent::Ping))));
+    }
+}
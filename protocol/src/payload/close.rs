@@ -0,0 +1,83 @@
+//payload for Close, why the session is ending
+//
+//Close payload: reason (1 byte), or nothing at all for a plain Normal close
+//unknown reason bytes are kept as Other so newer peers' reasons still come through
+
+use crate::packet::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseReason {
+    /// Done, nothing went wrong
+    Normal,
+    /// Shutting down or restarting
+    GoingAway,
+    /// Session sat idle too long
+    Idle,
+    /// The peer sent something we couldn't deal with
+    ProtocolError,
+    Other(u8),
+}
+
+impl CloseReason {
+    pub fn from_u8(byte: u8) -> Self {
+        match byte {
+            0x00 => CloseReason::Normal,
+            0x01 => CloseReason::GoingAway,
+            0x02 => CloseReason::Idle,
+            0x03 => CloseReason::ProtocolError,
+            other => CloseReason::Other(other),
+        }
+    }
+
+    pub fn to_u8(self) -> u8 {
+        match self {
+            CloseReason::Normal => 0x00,
+            CloseReason::GoingAway => 0x01,
+            CloseReason::Idle => 0x02,
+            CloseReason::ProtocolError => 0x03,
+            CloseReason::Other(byte) => byte,
+        }
+    }
+}
+
+impl Packet {
+    /// Close the session, saying why
+    pub fn close(session_id: SessionId, reason: CloseReason) -> Packet {
+        let mut packet = Packet::new(session_id, Intent::Close, vec![reason.to_u8()]);
+        packet.priority = Priority::for_intent(Intent::Close);
+        packet.reseal();
+        packet
+    }
+
+    /// Why a Close packet closes, an empty payload is a Normal close
+    pub fn close_reason(&self) -> Result<CloseReason, PacketError> {
+        if self.intent != Intent::Close {
+            return Err(PacketError::UnexpectedIntent(self.intent));
+        }
+        match self.payload.as_slice() {
+            [] => Ok(CloseReason::Normal),
+            [byte] => Ok(CloseReason::from_u8(*byte)),
+            _ => Err(PacketError::InvalidPayload),
+        }
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_close_reason_roundtrip() {
+        let close = Packet::close(SessionId::new(), CloseReason::Idle);
+        let recovered = Packet::from_bytes(&close.to_bytes()).unwrap();
+        assert_eq!(recovered.close_reason().unwrap(), CloseReason::Idle);
+
+        let bare = Packet::new(SessionId::new(), Intent::Close, vec![]);
+        assert_eq!(bare.close_reason().unwrap(), CloseReason::Normal);
+        assert_eq!(CloseReason::from_u8(0x77), CloseReason::Other(0x77));
+    }
+}
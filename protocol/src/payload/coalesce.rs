@@ -0,0 +1,110 @@
+//several small payloads of one intent in a single packet, one header and hash instead of many
+//
+//coalesced payload: (u32 big-endian length | bytes) repeated, same as a frame body
+//the packet doesn't say it's coalesced, both ends agree on it per intent like any other
+//payload format
+//
+//coalesced() hands out slices of the packet's payload instead of copying each one out
+
+use crate::packet::*;
+
+const LENGTH_SIZE: usize = 4;
+
+impl Packet {
+    /// One packet carrying all of `payloads`, in order
+    pub fn coalesce(session_id: SessionId, intent: Intent, payloads: &[&[u8]]) -> Result<Packet, PacketError> {
+        let total: usize = payloads.iter().map(|payload| LENGTH_SIZE + payload.len()).sum();
+        if total > MAX_PAYLOAD_SIZE {
+            return Err(PacketError::TooLarge);
+        }
+
+        let mut body = Vec::with_capacity(total);
+        for payload in payloads {
+            // fits, the total is under MAX_PAYLOAD_SIZE
+            body.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+            body.extend_from_slice(payload);
+        }
+        Ok(Packet::new(session_id, intent, body))
+    }
+
+    /// The payloads a coalesced packet carries, borrowed from its payload
+    /// The whole payload is checked up front, so the iterator itself can't fail
+    pub fn coalesced(&self) -> Result<CoalescedIter<'_>, PacketError> {
+        let mut count = 0;
+        let mut rest = self.payload.as_slice();
+        while !rest.is_empty() {
+            let (_, tail) = split_entry(rest).ok_or(PacketError::InvalidPayload)?;
+            rest = tail;
+            count += 1;
+        }
+        Ok(CoalescedIter {
+            rest: &self.payload,
+            remaining: count,
+        })
+    }
+}
+
+/// Iterator over the payloads of a coalesced packet, see Packet::coalesced
+#[derive(Debug, Clone)]
+pub struct CoalescedIter<'a> {
+    rest: &'a [u8],
+    remaining: usize,
+}
+
+impl<'a> Iterator for CoalescedIter<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        let (entry, tail) = split_entry(self.rest)?;
+        self.rest = tail;
+        self.remaining -= 1;
+        Some(entry)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl ExactSizeIterator for CoalescedIter<'_> {}
+
+// first entry and whatever follows it, None when empty or truncated
+fn split_entry(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    let len = u32::from_be_bytes(bytes.get(..LENGTH_SIZE)?.try_into().ok()?) as usize;
+    let end = LENGTH_SIZE.checked_add(len).filter(|&end| end <= bytes.len())?;
+    Some((&bytes[LENGTH_SIZE..end], &bytes[end..]))
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coalesced_slices_borrow_the_payload() {
+        let inputs: [&[u8]; 3] = [b"first", b"", b"third one"];
+        let packet = Packet::coalesce(SessionId::new(), Intent::DataPush, &inputs).unwrap();
+        let packet = Packet::from_bytes(&packet.to_bytes()).unwrap();
+
+        let slices: Vec<&[u8]> = packet.coalesced().unwrap().collect();
+        assert_eq!(slices, inputs);
+
+        let buffer = packet.payload.as_ptr_range();
+        for slice in &slices {
+            assert!(buffer.contains(&slice.as_ptr()));
+            assert!(slice.as_ptr_range().end <= buffer.end);
+        }
+        assert_eq!(packet.coalesced().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_truncated_coalesced_payload_rejected() {
+        let mut packet = Packet::coalesce(SessionId::new(), Intent::DataPush, &[b"abc", b"defg"]).unwrap();
+        packet.payload.pop();
+        packet.reseal();
+        assert!(matches!(packet.coalesced(), Err(PacketError::InvalidPayload)));
+    }
+}
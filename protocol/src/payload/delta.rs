@@ -0,0 +1,200 @@
+//DataDelta payload, changes to apply to a piece of data the receiver already has
+//
+//payload layout:
+// 32 bytes  | SHA-256 of the base the delta was made against
+// ops       | offset (4 bytes) | delete length (4 bytes) | insert length (4 bytes) | insert bytes
+//
+//every op is a splice: delete `delete length` bytes at `offset`, put the insert bytes there
+//that covers insert (delete 0), delete (insert nothing) and replace. ops apply in order,
+//each one sees the result of the previous one
+
+use sha2::{Digest, Sha256};
+
+use crate::packet::*;
+
+const BASE_HASH_SIZE: usize = 32;
+const OP_HEADER_SIZE: usize = 12;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeltaOp {
+    pub offset: u32,
+    pub delete: u32,
+    pub insert: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delta {
+    pub base_hash: [u8; BASE_HASH_SIZE],
+    pub ops: Vec<DeltaOp>,
+}
+
+/// SHA-256 of the base content, what a delta gets checked against
+pub fn content_hash(data: &[u8]) -> [u8; BASE_HASH_SIZE] {
+    let mut hash = [0u8; BASE_HASH_SIZE];
+    hash.copy_from_slice(&Sha256::digest(data));
+    hash
+}
+
+impl Delta {
+    /// A delta against `base`
+    pub fn new(base: &[u8], ops: Vec<DeltaOp>) -> Self {
+        Delta {
+            base_hash: content_hash(base),
+            ops,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&self.base_hash);
+        for op in &self.ops {
+            out.extend_from_slice(&op.offset.to_be_bytes());
+            out.extend_from_slice(&op.delete.to_be_bytes());
+            out.extend_from_slice(&(op.insert.len() as u32).to_be_bytes());
+            out.extend_from_slice(&op.insert);
+        }
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PacketError> {
+        if bytes.len() < BASE_HASH_SIZE {
+            return Err(PacketError::InvalidPayload);
+        }
+        let mut base_hash = [0u8; BASE_HASH_SIZE];
+        base_hash.copy_from_slice(&bytes[..BASE_HASH_SIZE]);
+
+        let mut ops = Vec::new();
+        let mut rest = &bytes[BASE_HASH_SIZE..];
+        while !rest.is_empty() {
+            if rest.len() < OP_HEADER_SIZE {
+                return Err(PacketError::InvalidPayload);
+            }
+            let read_u32 = |at: usize| u32::from_be_bytes([rest[at], rest[at + 1], rest[at + 2], rest[at + 3]]);
+            let offset = read_u32(0);
+            let delete = read_u32(4);
+            let insert_len = read_u32(8) as usize;
+
+            let end = OP_HEADER_SIZE
+                .checked_add(insert_len)
+                .filter(|end| *end <= rest.len())
+                .ok_or(PacketError::InvalidPayload)?;
+            ops.push(DeltaOp {
+                offset,
+                delete,
+                insert: rest[OP_HEADER_SIZE..end].to_vec(),
+            });
+            rest = &rest[end..];
+        }
+
+        Ok(Delta { base_hash, ops })
+    }
+}
+
+impl Packet {
+    /// Build a DataDelta packet carrying `delta`
+    pub fn data_delta(session_id: SessionId, delta: &Delta) -> Packet {
+        Packet::new(session_id, Intent::DataDelta, delta.to_bytes())
+    }
+
+    /// Apply a DataDelta packet to `base` in place, no second buffer
+    ///
+    /// `base` has to be exactly what the delta was made against (checked by hash),
+    /// and every op is bounds-checked before anything is touched, so on error
+    /// `base` is left as it was.
+    pub fn apply_delta_onto(base: &mut Vec<u8>, delta_packet: &Packet) -> Result<(), PacketError> {
+        if delta_packet.intent != Intent::DataDelta {
+            return Err(PacketError::UnexpectedIntent(delta_packet.intent));
+        }
+        let delta = Delta::from_bytes(&delta_packet.payload)?;
+
+        if content_hash(base) != delta.base_hash {
+            return Err(PacketError::BaseHashMismatch);
+        }
+
+        // dry run on lengths only
+        let mut len = base.len();
+        for op in &delta.ops {
+            let start = op.offset as usize;
+            let end = start
+                .checked_add(op.delete as usize)
+                .filter(|end| *end <= len)
+                .ok_or(PacketError::InvalidPayload)?;
+            len = len - (end - start) + op.insert.len();
+        }
+        if len > MAX_PAYLOAD_SIZE {
+            return Err(PacketError::TooLarge);
+        }
+
+        for op in &delta.ops {
+            let start = op.offset as usize;
+            let end = start + op.delete as usize;
+            base.splice(start..end, op.insert.iter().copied());
+        }
+        Ok(())
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_two_sequential_deltas() {
+        let session = SessionId::new();
+        let mut doc = b"hello world".to_vec();
+
+        // "hello world" -> "hello brave world"
+        let first = Delta::new(
+            &doc,
+            vec![DeltaOp { offset: 6, delete: 0, insert: b"brave ".to_vec() }],
+        );
+        Packet::apply_delta_onto(&mut doc, &Packet::data_delta(session, &first)).unwrap();
+        assert_eq!(doc, b"hello brave world".to_vec());
+
+        // "hello brave world" -> "HELLO brave new world!"
+        let second = Delta::new(
+            &doc,
+            vec![
+                DeltaOp { offset: 0, delete: 5, insert: b"HELLO".to_vec() },
+                DeltaOp { offset: 12, delete: 0, insert: b"new ".to_vec() },
+                DeltaOp { offset: 21, delete: 0, insert: b"!".to_vec() },
+            ],
+        );
+        let packet = Packet::from_bytes(&Packet::data_delta(session, &second).to_bytes()).unwrap();
+        Packet::apply_delta_onto(&mut doc, &packet).unwrap();
+        assert_eq!(doc, b"HELLO brave new world!".to_vec());
+    }
+
+    #[test]
+    fn test_base_hash_mismatch_rejected() {
+        let mut doc = b"version one".to_vec();
+        let delta = Delta::new(
+            b"version two",
+            vec![DeltaOp { offset: 0, delete: 7, insert: b"v".to_vec() }],
+        );
+
+        let result = Packet::apply_delta_onto(&mut doc, &Packet::data_delta(SessionId::new(), &delta));
+        assert!(matches!(result, Err(PacketError::BaseHashMismatch)));
+        assert_eq!(doc, b"version one".to_vec()); // untouched
+    }
+
+    #[test]
+    fn test_out_of_range_op_leaves_base_untouched() {
+        let mut doc = b"short".to_vec();
+        let delta = Delta::new(
+            &doc,
+            vec![
+                DeltaOp { offset: 0, delete: 1, insert: b"S".to_vec() },
+                DeltaOp { offset: 4, delete: 10, insert: vec![] },
+            ],
+        );
+
+        let result = Packet::apply_delta_onto(&mut doc, &Packet::data_delta(SessionId::new(), &delta));
+        assert!(matches!(result, Err(PacketError::InvalidPayload)));
+        assert_eq!(doc, b"short".to_vec());
+    }
+}
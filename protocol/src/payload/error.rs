@@ -0,0 +1,80 @@
+//payload for Error, ties the error to the request that caused it
+//
+//Error payload:
+// 4 bytes | sequence of the failed request (u32, big-endian)
+// 2 bytes | error code (u16, big-endian), meaning is up to the application
+// rest    | message, UTF-8
+
+use crate::packet::*;
+
+const ERROR_HEADER_SIZE: usize = 6;
+
+/// What an Error packet says
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorInfo {
+    /// Sequence of the request this answers
+    pub request_sequence: Sequence,
+    pub code: u16,
+    pub message: String,
+}
+
+impl Packet {
+    /// Error reply to `request`, same session, referencing its sequence
+    /// Keeps the request's correlation id
+    pub fn error_for(request: &Packet, code: u16, message: &str) -> Packet {
+        let mut payload = Vec::with_capacity(ERROR_HEADER_SIZE + message.len());
+        payload.extend_from_slice(&request.sequence.to_be_bytes());
+        payload.extend_from_slice(&code.to_be_bytes());
+        payload.extend_from_slice(message.as_bytes());
+
+        let mut packet = Packet::new(request.session_id, Intent::Error, payload);
+        packet.priority = Priority::CRITICAL;
+        packet.ext.correlation_id = request.ext.correlation_id;
+        packet.reseal();
+        packet
+    }
+
+    /// Read an Error packet's payload
+    pub fn error_info(&self) -> Result<ErrorInfo, PacketError> {
+        if self.intent != Intent::Error {
+            return Err(PacketError::UnexpectedIntent(self.intent));
+        }
+        if self.payload.len() < ERROR_HEADER_SIZE {
+            return Err(PacketError::InvalidPayload);
+        }
+
+        let (header, message) = self.payload.split_at(ERROR_HEADER_SIZE);
+        Ok(ErrorInfo {
+            request_sequence: u32::from_be_bytes([header[0], header[1], header[2], header[3]]),
+            code: u16::from_be_bytes([header[4], header[5]]),
+            message: String::from_utf8(message.to_vec()).map_err(|_| PacketError::InvalidPayload)?,
+        })
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_for_request() {
+        let mut request = Packet::new(SessionId::new(), Intent::FetchDocument, b"doc 9".to_vec());
+        request.set_sequence(314);
+        request.reseal();
+
+        let error = Packet::error_for(&request, 404, "no such document");
+        assert_eq!(error.session_id, request.session_id);
+        assert_eq!(error.intent, Intent::Error);
+        assert_eq!(error.priority, Priority::CRITICAL);
+
+        let recovered = Packet::from_bytes(&error.to_bytes()).unwrap();
+        let info = recovered.error_info().unwrap();
+        assert_eq!(info.request_sequence, 314);
+        assert_eq!(info.code, 404);
+        assert_eq!(info.message, "no such document");
+    }
+}
@@ -0,0 +1,21 @@
+//typed payloads for intents that have a defined format
+//the packet layer only sees bytes, these give the bytes a shape
+pub mod ack;
+pub mod cancel;
+pub mod close;
+pub mod coalesce;
+pub mod delta;
+pub mod error;
+pub mod nack;
+pub mod ping;
+pub mod ranking;
+pub mod success;
+pub mod view;
+pub mod window;
+
+pub use close::*;
+pub use coalesce::*;
+pub use delta::*;
+pub use error::*;
+pub use ranking::*;
+pub use success::*;
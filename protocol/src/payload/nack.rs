@@ -0,0 +1,90 @@
+//payload for Nack, the receiver saw gaps and asks for exactly those sequences again
+//
+//Nack payload: a list of inclusive ranges [first, last]
+// 4 bytes | first (u32, big-endian)
+// 4 bytes | last (u32, big-endian)
+//repeated, so the payload length is always a multiple of 8
+//inclusive so the last sequence before the wrap (u32::MAX) can be named, a half-open end
+//for it would have to be 2^32
+//
+//Ack (ack.rs) uses the same range list, only the meaning flips
+
+use std::ops::RangeInclusive;
+
+use crate::packet::*;
+
+const RANGE_SIZE: usize = 8;
+
+impl Packet {
+    /// Build a Nack asking for the sequences in `ranges` to be resent
+    pub fn nack(session_id: SessionId, ranges: &[RangeInclusive<Sequence>]) -> Packet {
+        let mut packet = Packet::new(session_id, Intent::Nack, encode_ranges(ranges));
+        packet.priority = Priority::for_intent(Intent::Nack);
+        packet.reseal();
+        packet
+    }
+
+    /// The missing ranges a Nack asks for
+    pub fn nack_ranges(&self) -> Result<Vec<RangeInclusive<Sequence>>, PacketError> {
+        if self.intent != Intent::Nack {
+            return Err(PacketError::UnexpectedIntent(self.intent));
+        }
+        decode_ranges(&self.payload)
+    }
+}
+
+pub(crate) fn encode_ranges(ranges: &[RangeInclusive<Sequence>]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(ranges.len() * RANGE_SIZE);
+    for range in ranges {
+        payload.extend_from_slice(&range.start().to_be_bytes());
+        payload.extend_from_slice(&range.end().to_be_bytes());
+    }
+    payload
+}
+
+pub(crate) fn decode_ranges(payload: &[u8]) -> Result<Vec<RangeInclusive<Sequence>>, PacketError> {
+    if !payload.len().is_multiple_of(RANGE_SIZE) {
+        return Err(PacketError::InvalidPayload);
+    }
+
+    payload
+        .chunks_exact(RANGE_SIZE)
+        .map(|chunk| {
+            let first = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+            let last = u32::from_be_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+            if first > last {
+                return Err(PacketError::InvalidPayload);
+            }
+            Ok(first..=last)
+        })
+        .collect()
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nack_roundtrip() {
+        let nack = Packet::nack(SessionId::new(), &[5..=6, 10..=10, u32::MAX - 1..=u32::MAX]);
+        let recovered = Packet::from_bytes(&nack.to_bytes()).unwrap();
+
+        assert_eq!(recovered.intent, Intent::Nack);
+        assert_eq!(recovered.nack_ranges().unwrap(), vec![5..=6, 10..=10, u32::MAX - 1..=u32::MAX]);
+    }
+
+    #[test]
+    fn test_nack_ranges_rejects_bad_payload() {
+        let mut nack = Packet::nack(SessionId::new(), &[5..=6, 9..=9]);
+        nack.payload.pop();
+        assert!(matches!(nack.nack_ranges(), Err(PacketError::InvalidPayload)));
+
+        // last before first
+        let backwards = Packet::nack(SessionId::new(), &[RangeInclusive::new(9, 3)]);
+        assert!(matches!(backwards.nack_ranges(), Err(PacketError::InvalidPayload)));
+    }
+}
@@ -0,0 +1,50 @@
+//Ping/Pong: the payload is opaque, whatever the Ping carried comes back in the Pong
+//(a send time, a nonce), so the pinger can match replies and measure the round trip
+
+use crate::packet::*;
+
+impl Packet {
+    /// Pong answering `ping`: same session, echoes the payload and the correlation id
+    pub fn pong_for(ping: &Packet) -> Packet {
+        let mut packet = Packet::new(ping.session_id, Intent::Pong, ping.payload.clone());
+        packet.priority = Priority::for_intent(Intent::Pong);
+        packet.ext.correlation_id = ping.ext.correlation_id;
+        packet.reseal();
+        packet
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_responses_carry_correlation_id() {
+        let id = [0xC0; CORRELATION_ID_SIZE];
+        let ping = PacketBuilder::new(SessionId::new(), Intent::Ping)
+            .payload(b"t=1234".to_vec())
+            .correlation_id(id)
+            .flags(Flags::new())
+            .insecure_allow_plaintext()
+            .build()
+            .unwrap();
+        let ping = Packet::from_bytes(&ping.to_bytes()).unwrap();
+        assert_eq!(ping.correlation_id(), Some(id));
+
+        let pong = Packet::from_bytes(&Packet::pong_for(&ping).to_bytes()).unwrap();
+        assert_eq!(pong.intent, Intent::Pong);
+        assert_eq!(pong.payload, b"t=1234");
+        assert_eq!(pong.correlation_id(), Some(id));
+
+        let error = Packet::from_bytes(&Packet::error_for(&ping, 500, "boom").to_bytes()).unwrap();
+        assert_eq!(error.correlation_id(), Some(id));
+
+        // untagged requests get untagged responses
+        let plain = Packet::new(SessionId::new(), Intent::Ping, vec![]);
+        assert_eq!(Packet::pong_for(&plain).correlation_id(), None);
+    }
+}
@@ -0,0 +1,135 @@
+//payloads for RankingUpdate and RankingRequest
+//ranking preferences are personal (they say what a user cares about), so a RankingUpdate
+//is never allowed to go out in plaintext, the helpers here refuse EncryptionLevel::None
+//
+//RankingUpdate payload: RankingPrefs, bincode encoded, then encrypted
+//RankingRequest payload: query id (8 bytes, big-endian)
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::packet::encryption::KEY_SIZE;
+use crate::packet::*;
+
+/// A user's ranking preferences, feature id -> weight
+/// What the feature ids mean is up to the search engine
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RankingPrefs {
+    pub weights: BTreeMap<u32, f32>,
+}
+
+impl RankingPrefs {
+    pub fn new() -> Self {
+        RankingPrefs::default()
+    }
+
+    pub fn set(&mut self, feature: u32, weight: f32) {
+        self.weights.insert(feature, weight);
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, PacketError> {
+        bincode::serialize(self).map_err(|_| PacketError::InvalidPayload)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PacketError> {
+        bincode::deserialize(bytes).map_err(|_| PacketError::InvalidPayload)
+    }
+}
+
+impl Packet {
+    /// Build an encrypted RankingUpdate
+    /// `encryption` should be ChaCha20 (our default), None is refused
+    pub fn ranking_update(
+        session_id: SessionId,
+        prefs: &RankingPrefs,
+        encryption: EncryptionLevel,
+        key: &[u8; KEY_SIZE],
+    ) -> Result<Packet, PacketError> {
+        if encryption == EncryptionLevel::None {
+            return Err(PacketError::EncryptionRequired);
+        }
+
+        let mut packet = Packet::new(session_id, Intent::RankingUpdate, prefs.to_bytes()?);
+        packet.priority = Priority::for_intent(Intent::RankingUpdate);
+        packet.encrypt_payload(encryption, key)?;
+        Ok(packet)
+    }
+
+    /// Read the preferences out of a RankingUpdate
+    /// A plaintext RankingUpdate is refused even if it would decode
+    pub fn ranking_prefs(&self, key: &[u8; KEY_SIZE]) -> Result<RankingPrefs, PacketError> {
+        if self.intent != Intent::RankingUpdate {
+            return Err(PacketError::UnexpectedIntent(self.intent));
+        }
+        if self.flags.encryption() == EncryptionLevel::None {
+            return Err(PacketError::EncryptionRequired);
+        }
+        RankingPrefs::from_bytes(&self.decrypt_payload(key)?)
+    }
+
+    /// Ask for personalized ranking of the results of query `query_id`
+    pub fn ranking_request(session_id: SessionId, query_id: u64) -> Packet {
+        Packet::new(session_id, Intent::RankingRequest, query_id.to_be_bytes().to_vec())
+    }
+
+    /// Query id of a RankingRequest, None for anything else
+    pub fn ranking_query_id(&self) -> Option<u64> {
+        if self.intent != Intent::RankingRequest {
+            return None;
+        }
+        let bytes: [u8; 8] = self.payload.as_slice().try_into().ok()?;
+        Some(u64::from_be_bytes(bytes))
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; KEY_SIZE] = [7u8; KEY_SIZE];
+
+    fn prefs() -> RankingPrefs {
+        let mut prefs = RankingPrefs::new();
+        prefs.set(1, 0.8); // e.g. freshness
+        prefs.set(2, -0.5); // e.g. ad density
+        prefs.set(17, 1.25);
+        prefs
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn test_prefs_roundtrip_through_encrypted_packet() {
+        let packet = Packet::ranking_update(SessionId::new(), &prefs(), EncryptionLevel::ChaCha20, &KEY)
+            .unwrap();
+        assert_eq!(packet.flags.encryption(), EncryptionLevel::ChaCha20);
+
+        let received = Packet::from_bytes(&packet.to_bytes()).unwrap();
+        assert_eq!(received.ranking_prefs(&KEY).unwrap(), prefs());
+    }
+
+    #[test]
+    fn test_unencrypted_prefs_rejected() {
+        let result = Packet::ranking_update(SessionId::new(), &prefs(), EncryptionLevel::None, &KEY);
+        assert!(matches!(result, Err(PacketError::EncryptionRequired)));
+
+        // hand-rolled plaintext update is refused on the reading side too
+        let mut plaintext = Packet::new(SessionId::new(), Intent::RankingUpdate, prefs().to_bytes().unwrap());
+        plaintext.flags.set_encryption(EncryptionLevel::None);
+        plaintext.reseal();
+        assert!(matches!(plaintext.ranking_prefs(&KEY), Err(PacketError::EncryptionRequired)));
+    }
+
+    #[test]
+    fn test_ranking_request_query_id() {
+        let packet = Packet::ranking_request(SessionId::new(), 0xDEAD_BEEF_0042);
+        assert_eq!(packet.ranking_query_id(), Some(0xDEAD_BEEF_0042));
+
+        let ping = Packet::new(SessionId::new(), Intent::Ping, vec![]);
+        assert_eq!(ping.ranking_query_id(), None);
+    }
+}
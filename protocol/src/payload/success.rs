@@ -0,0 +1,82 @@
+//payload for Success, optional: an empty payload is a bare acknowledgment like it always was
+//
+//Success payload:
+// 4 bytes | sequence of the request this answers (u32, big-endian)
+// rest    | result, opaque to the protocol (search results for a Search, say)
+
+use crate::packet::*;
+
+const SUCCESS_HEADER_SIZE: usize = 4;
+
+/// What a Success with a payload says
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuccessPayload {
+    /// Sequence of the request this answers
+    pub correlated_seq: Sequence,
+    pub result: Vec<u8>,
+}
+
+impl Packet {
+    /// Success reply to `request` carrying `result`, same session, referencing its sequence
+    /// Keeps the request's correlation id
+    pub fn success_for(request: &Packet, result: Vec<u8>) -> Packet {
+        let mut payload = Vec::with_capacity(SUCCESS_HEADER_SIZE + result.len());
+        payload.extend_from_slice(&request.sequence.to_be_bytes());
+        payload.extend_from_slice(&result);
+
+        let mut packet = Packet::new(request.session_id, Intent::Success, payload);
+        packet.priority = Priority::for_intent(Intent::Success);
+        packet.ext.correlation_id = request.ext.correlation_id;
+        packet.reseal();
+        packet
+    }
+
+    /// Read a Success packet's payload, None for a bare Success
+    pub fn success_payload(&self) -> Result<Option<SuccessPayload>, PacketError> {
+        if self.intent != Intent::Success {
+            return Err(PacketError::UnexpectedIntent(self.intent));
+        }
+        if self.payload.is_empty() {
+            return Ok(None);
+        }
+        if self.payload.len() < SUCCESS_HEADER_SIZE {
+            return Err(PacketError::InvalidPayload);
+        }
+
+        let (header, result) = self.payload.split_at(SUCCESS_HEADER_SIZE);
+        Ok(Some(SuccessPayload {
+            correlated_seq: u32::from_be_bytes([header[0], header[1], header[2], header[3]]),
+            result: result.to_vec(),
+        }))
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_success_for_search() {
+        let mut search = Packet::new(SessionId::new(), Intent::Search, b"rust async".to_vec());
+        search.set_sequence(77);
+        search.reseal();
+
+        let success = Packet::success_for(&search, b"[\"tokio\",\"async-std\"]".to_vec());
+        assert_eq!(success.session_id, search.session_id);
+
+        let recovered = Packet::from_bytes(&success.to_bytes()).unwrap();
+        let payload = recovered.success_payload().unwrap().unwrap();
+        assert_eq!(payload.correlated_seq, 77);
+        assert_eq!(payload.result, b"[\"tokio\",\"async-std\"]");
+
+        // the bare acknowledgment still parses
+        let bare = Packet::new(search.session_id, Intent::Success, vec![]);
+        assert_eq!(bare.success_payload().unwrap(), None);
+        let short = Packet::new(search.session_id, Intent::Success, vec![0, 1]);
+        assert!(matches!(short.success_payload(), Err(PacketError::InvalidPayload)));
+    }
+}
@@ -0,0 +1,96 @@
+//typed views over a packet's payload, one per intent with a defined format
+//each returns None when the intent is a different one (or the payload doesn't parse), so
+//dispatch is a chain of `if let Some(..) = packet.as_x()` instead of matching the intent and
+//then calling the matching parser by hand. The Result-returning parsers next to each payload
+//format are still there when the reason for a failure matters
+
+use std::ops::RangeInclusive;
+
+use crate::packet::*;
+use crate::payload::{CloseReason, ErrorInfo, SuccessPayload};
+
+impl Packet {
+    pub fn as_error(&self) -> Option<ErrorInfo> {
+        self.error_info().ok()
+    }
+
+    /// None for a bare Success too
+    pub fn as_success(&self) -> Option<SuccessPayload> {
+        self.success_payload().ok().flatten()
+    }
+
+    pub fn as_close(&self) -> Option<CloseReason> {
+        self.close_reason().ok()
+    }
+
+    pub fn as_ack(&self) -> Option<Vec<RangeInclusive<Sequence>>> {
+        self.ack_ranges().ok()
+    }
+
+    pub fn as_nack(&self) -> Option<Vec<RangeInclusive<Sequence>>> {
+        self.nack_ranges().ok()
+    }
+
+    pub fn as_window_update(&self) -> Option<u32> {
+        self.window_size().ok()
+    }
+
+    pub fn as_cancel(&self) -> Option<Sequence> {
+        self.cancelled_sequence().ok()
+    }
+
+    /// The opaque bytes the Ping carries, the Pong echoes them (see ping.rs)
+    pub fn as_ping(&self) -> Option<&[u8]> {
+        (self.intent == Intent::Ping).then_some(self.payload.as_slice())
+    }
+
+    /// The bytes echoed from the Ping
+    pub fn as_pong(&self) -> Option<&[u8]> {
+        (self.intent == Intent::Pong).then_some(self.payload.as_slice())
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_views_match_intent() {
+        let session_id = SessionId::new();
+        let request = Packet::new(session_id, Intent::Search, b"q".to_vec());
+        let error = Packet::error_for(&request, 500, "boom");
+        let ping = Packet::new(session_id, Intent::Ping, vec![]);
+
+        assert_eq!(error.as_error().unwrap().code, 500);
+        assert_eq!(ping.as_error(), None);
+        assert_eq!(ping.as_close(), None);
+        assert_eq!(Packet::success_for(&request, b"ok".to_vec()).as_success().unwrap().result, b"ok");
+        assert_eq!(error.as_success(), None);
+        let close = Packet::close(session_id, CloseReason::GoingAway);
+        assert_eq!(close.as_close(), Some(CloseReason::GoingAway));
+        assert_eq!(Packet::cancel(session_id, 5).as_cancel(), Some(5));
+        assert_eq!(error.as_cancel(), None);
+    }
+
+    #[test]
+    fn test_ping_pong_views() {
+        let session_id = SessionId::new();
+        let ping = Packet::new(session_id, Intent::Ping, b"t=1234".to_vec());
+        let pong = Packet::pong_for(&ping);
+
+        assert_eq!(ping.as_ping(), Some(&b"t=1234"[..]));
+        assert_eq!(pong.as_pong(), Some(&b"t=1234"[..]));
+        assert_eq!(ping.as_pong(), None);
+        assert_eq!(pong.as_ping(), None);
+
+        // an empty Ping is still a Ping
+        assert_eq!(Packet::new(session_id, Intent::Ping, vec![]).as_ping(), Some(&[][..]));
+        let close = Packet::close(session_id, CloseReason::GoingAway);
+        assert_eq!(close.as_ping(), None);
+        assert_eq!(close.as_pong(), None);
+    }
+}
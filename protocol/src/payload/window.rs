@@ -0,0 +1,48 @@
+//payload for WindowUpdate, the receiver's advertised window
+//
+//WindowUpdate payload: window (u32, big-endian) -how many more packets the receiver
+//can take from now on, it replaces the previous window rather than adding to it
+
+use crate::packet::*;
+
+impl Packet {
+    /// Advertise a receive window of `window` packets
+    pub fn window_update(session_id: SessionId, window: u32) -> Packet {
+        let mut packet = Packet::new(session_id, Intent::WindowUpdate, window.to_be_bytes().to_vec());
+        packet.priority = Priority::for_intent(Intent::WindowUpdate);
+        packet.reseal();
+        packet
+    }
+
+    /// The window a WindowUpdate advertises
+    pub fn window_size(&self) -> Result<u32, PacketError> {
+        if self.intent != Intent::WindowUpdate {
+            return Err(PacketError::UnexpectedIntent(self.intent));
+        }
+        let bytes: [u8; 4] = self
+            .payload
+            .as_slice()
+            .try_into()
+            .map_err(|_| PacketError::InvalidPayload)?;
+        Ok(u32::from_be_bytes(bytes))
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_update_roundtrip() {
+        let update = Packet::window_update(SessionId::new(), 32);
+        let recovered = Packet::from_bytes(&update.to_bytes()).unwrap();
+        assert_eq!(recovered.window_size().unwrap(), 32);
+
+        let search = Packet::new(SessionId::new(), Intent::Search, vec![]);
+        assert!(matches!(search.window_size(), Err(PacketError::UnexpectedIntent(Intent::Search))));
+    }
+}
@@ -0,0 +1,503 @@
+//outgoing packet queue, highest priority goes out first
+//within the same priority lower sequence numbers go first so a session's packets keep their order
+//that's the plain u32 order, not seq_cmp: the heap (and BoundedPriorityQueue's eviction scan)
+//need a total order, serial number comparison isn't one across the whole space
+//full ties (fragments can share a sequence) fall back to the session id bytes, lowest first,
+//so the pop order never depends on heap internals
+//
+//a queue can have a minimum priority (metered/background links), packets below it are
+//handed back to the caller instead of queued
+//
+//drain_due() is for packets with a deadline (see Packet::set_deadline): interactive traffic
+//that arrives late is useless, so whatever's past its deadline is dropped instead of sent
+//
+//BoundedPriorityQueue caps the length: when full, whatever would be sent last (the new packet
+//included) is dropped and handed back, so overload sheds the least important traffic first
+//
+//strict priority starves the low lanes as long as anything higher is waiting. WeightedScheduler
+//is the fair alternative: one FIFO lane per priority, and each lane with packets waiting gets
+//turns in proportion to its weight (smooth weighted round robin, so turns are spread out
+//instead of handed out in bursts)
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BinaryHeap, VecDeque};
+
+use crate::packet::*;
+
+// BinaryHeap is a max-heap, so "greater" here means "send sooner"
+#[derive(Debug)]
+struct Queued(Packet);
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0
+            .priority
+            .cmp(&other.0.priority)
+            .then_with(|| other.0.sequence.cmp(&self.0.sequence))
+            .then_with(|| other.0.session_id.as_bytes().cmp(self.0.session_id.as_bytes()))
+    }
+}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Queued {}
+
+/// How long the pending packets have been waiting, all in ms
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AgeStats {
+    pub count: usize,
+    pub oldest: u64,
+    pub newest: u64,
+    pub mean: u64,
+    pub median: u64,
+}
+
+#[derive(Debug)]
+pub struct PriorityQueue {
+    heap: BinaryHeap<Queued>,
+    min_priority: Priority,
+}
+
+impl Default for PriorityQueue {
+    fn default() -> Self {
+        PriorityQueue {
+            heap: BinaryHeap::new(),
+            min_priority: Priority::LOWEST,
+        }
+    }
+}
+
+impl PriorityQueue {
+    pub fn new() -> Self {
+        PriorityQueue::default()
+    }
+
+    /// Only accept packets at or above `min_priority`, push hands anything lower back
+    pub fn with_min_priority(mut self, min_priority: Priority) -> Self {
+        self.min_priority = min_priority;
+        self
+    }
+
+    pub fn min_priority(&self) -> Priority {
+        self.min_priority
+    }
+
+    /// Would this packet be allowed out on this channel?
+    pub fn accept_outgoing(&self, packet: &Packet) -> bool {
+        packet.priority >= self.min_priority
+    }
+
+    /// Queue a packet if the channel accepts it
+    /// None when it was queued, the packet itself when it was below min_priority
+    pub fn push(&mut self, packet: Packet) -> Option<Packet> {
+        if !self.accept_outgoing(&packet) {
+            return Some(packet);
+        }
+        self.heap.push(Queued(packet));
+        None
+    }
+
+    /// Next packet to send
+    pub fn pop(&mut self) -> Option<Packet> {
+        self.heap.pop().map(|queued| queued.0)
+    }
+
+    pub fn peek(&self) -> Option<&Packet> {
+        self.heap.peek().map(|queued| &queued.0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    /// Empty the queue: packets still within their deadline in send order, and the ones
+    /// past it that were dropped
+    pub fn drain_due(&mut self, now_ms: u64) -> (Vec<Packet>, Vec<Packet>) {
+        let mut to_send = Vec::with_capacity(self.heap.len());
+        let mut dropped = Vec::new();
+        while let Some(packet) = self.pop() {
+            if packet.is_past_deadline(now_ms) {
+                dropped.push(packet);
+            } else {
+                to_send.push(packet);
+            }
+        }
+        (to_send, dropped)
+    }
+
+    /// Age distribution of everything still waiting, None when the queue is empty
+    /// Growing ages mean we're sending slower than we're queueing
+    pub fn age_stats(&self, now_ms: u64) -> Option<AgeStats> {
+        let mut ages: Vec<u64> = self.heap.iter().map(|queued| queued.0.age_ms(now_ms)).collect();
+        if ages.is_empty() {
+            return None;
+        }
+        ages.sort_unstable();
+
+        let count = ages.len();
+        Some(AgeStats {
+            count,
+            oldest: ages[count - 1],
+            newest: ages[0],
+            mean: ages.iter().sum::<u64>() / count as u64,
+            median: ages[count / 2],
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct BoundedPriorityQueue {
+    queue: PriorityQueue,
+    capacity: usize,
+}
+
+impl BoundedPriorityQueue {
+    /// Holds at most `capacity` packets (at least one)
+    pub fn new(capacity: usize) -> Self {
+        BoundedPriorityQueue {
+            queue: PriorityQueue::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Queue a packet, returns the packet that got dropped to make room, if any
+    /// That's the one that would go out last: lowest priority, then highest sequence.
+    /// It can be the packet just pushed. Finding it is a scan, fine for queue-sized queues
+    pub fn push(&mut self, packet: Packet) -> Option<Packet> {
+        if self.queue.len() < self.capacity {
+            self.queue.push(packet);
+            return None;
+        }
+
+        let incoming = Queued(packet);
+        let mut queued = std::mem::take(&mut self.queue.heap).into_vec();
+        let lowest = queued
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.cmp(b))
+            .map(|(index, _)| index)
+            .expect("queue is full, so not empty");
+
+        let dropped = if incoming <= queued[lowest] {
+            incoming
+        } else {
+            std::mem::replace(&mut queued[lowest], incoming)
+        };
+        self.queue.heap = BinaryHeap::from(queued);
+        Some(dropped.0)
+    }
+
+    pub fn pop(&mut self) -> Option<Packet> {
+        self.queue.pop()
+    }
+
+    pub fn peek(&self) -> Option<&Packet> {
+        self.queue.peek()
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+// ============================================================================
+// WEIGHTED SCHEDULER
+// ============================================================================
+
+#[derive(Debug, Default)]
+struct Lane {
+    packets: VecDeque<Packet>,
+    // smooth WRR state: grows by the weight every pick, drops by the total when picked
+    current: i64,
+}
+
+#[derive(Debug, Default)]
+pub struct WeightedScheduler {
+    lanes: BTreeMap<Priority, Lane>,
+    weights: BTreeMap<Priority, u32>,
+    len: usize,
+}
+
+impl WeightedScheduler {
+    /// Every priority has weight 1 until with_weight says otherwise
+    pub fn new() -> Self {
+        WeightedScheduler::default()
+    }
+
+    /// Give `priority` `weight` turns for every one a weight 1 lane gets (at least 1)
+    pub fn with_weight(mut self, priority: Priority, weight: u32) -> Self {
+        self.weights.insert(priority, weight.max(1));
+        self
+    }
+
+    pub fn weight(&self, priority: Priority) -> u32 {
+        self.weights.get(&priority).copied().unwrap_or(1)
+    }
+
+    pub fn push(&mut self, packet: Packet) {
+        self.lanes.entry(packet.priority).or_default().packets.push_back(packet);
+        self.len += 1;
+    }
+
+    /// Next packet to send, from the lane whose turn it is
+    /// Lanes that are empty don't take part, their share goes to the others
+    pub fn pop(&mut self) -> Option<Packet> {
+        let mut total = 0i64;
+        let mut chosen: Option<Priority> = None;
+        let mut best = i64::MIN;
+        // highest priority first, so it wins ties
+        for (&priority, lane) in self.lanes.iter_mut().rev() {
+            if lane.packets.is_empty() {
+                continue;
+            }
+            let weight = self.weights.get(&priority).copied().unwrap_or(1) as i64;
+            lane.current += weight;
+            total += weight;
+            if lane.current > best {
+                best = lane.current;
+                chosen = Some(priority);
+            }
+        }
+
+        let lane = self.lanes.get_mut(&chosen?).expect("chosen from the lanes");
+        lane.current -= total;
+        let packet = lane.packets.pop_front();
+        if lane.packets.is_empty() {
+            // a lane coming back later starts fresh instead of cashing in old credit
+            lane.current = 0;
+        }
+        self.len -= 1;
+        packet
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// Group packets into one lane per priority for multi-queue transports
+/// Each lane keeps the packets in the order they came in
+pub fn partition_by_priority(packets: Vec<Packet>) -> BTreeMap<Priority, Vec<Packet>> {
+    let mut lanes: BTreeMap<Priority, Vec<Packet>> = BTreeMap::new();
+    for packet in packets {
+        lanes.entry(packet.priority).or_default().push(packet);
+    }
+    lanes
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(priority: Priority, sequence: Sequence, timestamp: u64) -> Packet {
+        let mut packet = Packet::new(SessionId::from_bytes([1; 16]), Intent::Search, vec![]);
+        packet.priority = priority;
+        packet.sequence = sequence;
+        packet.timestamp = timestamp;
+        packet.reseal();
+        packet
+    }
+
+    #[test]
+    fn test_pops_by_priority_then_sequence() {
+        let mut queue = PriorityQueue::new();
+        queue.push(packet(Priority::LOW, 1, 0));
+        queue.push(packet(Priority::HIGH, 3, 0));
+        queue.push(packet(Priority::HIGH, 2, 0));
+        queue.push(packet(Priority::CRITICAL, 4, 0));
+
+        let order: Vec<Sequence> = std::iter::from_fn(|| queue.pop()).map(|p| p.sequence).collect();
+        assert_eq!(order, vec![4, 2, 3, 1]);
+    }
+
+    #[test]
+    fn test_pop_order_independent_of_push_order() {
+        // spread over the whole space, where serial number comparison stops being transitive
+        let sequences = [1, u32::MAX, 0, 1 << 31, u32::MAX - 1, (1 << 31) + 1];
+        let popped = |order: &mut dyn Iterator<Item = &Sequence>| -> Vec<Sequence> {
+            let mut queue = PriorityQueue::new();
+            for &sequence in order {
+                queue.push(packet(Priority::NORMAL, sequence, 0));
+            }
+            std::iter::from_fn(|| queue.pop()).map(|p| p.sequence).collect()
+        };
+
+        let forward = popped(&mut sequences.iter());
+        assert_eq!(forward, popped(&mut sequences.iter().rev()));
+        assert_eq!(forward, vec![0, 1, 1 << 31, (1 << 31) + 1, u32::MAX - 1, u32::MAX]);
+    }
+
+    #[test]
+    fn test_drain_due_drops_late_packets() {
+        let mut queue = PriorityQueue::new();
+        for (sequence, deadline) in [(1, 900), (2, 1_000), (3, 1_100), (4, 1_500)] {
+            let mut packet = packet(Priority::HIGH, sequence, 0);
+            packet.set_deadline(deadline);
+            queue.push(packet);
+        }
+        // no deadline, always goes out
+        queue.push(packet(Priority::LOW, 5, 0));
+
+        let (to_send, dropped) = queue.drain_due(1_050);
+        let sent: Vec<Sequence> = to_send.iter().map(|p| p.sequence).collect();
+        let late: Vec<Sequence> = dropped.iter().map(|p| p.sequence).collect();
+        assert_eq!(sent, vec![3, 4, 5]);
+        assert_eq!(late, vec![1, 2]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_ties_broken_by_session_id() {
+        let mut queue = PriorityQueue::new();
+        for first_byte in [3u8, 1, 2] {
+            let mut packet = packet(Priority::NORMAL, 7, 0);
+            packet.session_id = SessionId::from_bytes([first_byte; 16]);
+            packet.reseal();
+            queue.push(packet);
+        }
+
+        let order: Vec<u8> = std::iter::from_fn(|| queue.pop())
+            .map(|p| p.session_id.as_bytes()[0])
+            .collect();
+        assert_eq!(order, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_min_priority_rejects_low() {
+        let mut queue = PriorityQueue::new().with_min_priority(Priority::NORMAL);
+
+        let low = packet(Priority::LOW, 1, 0);
+        assert!(!queue.accept_outgoing(&low));
+        let rejected = queue.push(low).unwrap();
+        assert_eq!(rejected.sequence, 1);
+
+        assert!(queue.push(packet(Priority::HIGH, 2, 0)).is_none());
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.pop().unwrap().sequence, 2);
+    }
+
+    #[test]
+    fn test_bounded_queue_evicts_lowest() {
+        let mut queue = BoundedPriorityQueue::new(3);
+        assert!(queue.push(packet(Priority::NORMAL, 1, 0)).is_none());
+        assert!(queue.push(packet(Priority::LOW, 2, 0)).is_none());
+        assert!(queue.push(packet(Priority::HIGH, 3, 0)).is_none());
+
+        // full: the LOW one makes room for the CRITICAL one
+        let evicted = queue.push(packet(Priority::CRITICAL, 4, 0)).unwrap();
+        assert_eq!((evicted.priority, evicted.sequence), (Priority::LOW, 2));
+        assert_eq!(queue.len(), 3);
+
+        // something lower than everything queued is turned away itself
+        let rejected = queue.push(packet(Priority::LOWEST, 5, 0)).unwrap();
+        assert_eq!(rejected.sequence, 5);
+
+        let order: Vec<Sequence> = std::iter::from_fn(|| queue.pop()).map(|p| p.sequence).collect();
+        assert_eq!(order, vec![4, 3, 1]);
+    }
+
+    #[test]
+    fn test_weighted_scheduler_follows_ratios() {
+        let lanes = [
+            (Priority::HIGH, 8),
+            (Priority::NORMAL, 4),
+            (Priority::LOW, 2),
+            (Priority::LOWEST, 1),
+        ];
+        let mut scheduler = lanes
+            .iter()
+            .fold(WeightedScheduler::new(), |scheduler, &(priority, weight)| {
+                scheduler.with_weight(priority, weight)
+            });
+        for sequence in 0..60 {
+            for (priority, _) in lanes {
+                scheduler.push(packet(priority, sequence, 0));
+            }
+        }
+
+        // every window of one full round (15 turns) has exactly 8:4:2:1
+        let order: Vec<Packet> = (0..60).map(|_| scheduler.pop().unwrap()).collect();
+        for window in order.chunks(15) {
+            for (priority, weight) in lanes {
+                assert_eq!(window.iter().filter(|p| p.priority == priority).count(), weight as usize);
+            }
+        }
+        // spread out, not eight HIGH in a row
+        assert!(order[..4].iter().any(|p| p.priority != Priority::HIGH));
+
+        // each lane stays in order
+        let high: Vec<Sequence> = order.iter().filter(|p| p.priority == Priority::HIGH).map(|p| p.sequence).collect();
+        assert!(high.windows(2).all(|pair| pair[0] < pair[1]));
+
+        // and everything still queued comes out too
+        assert_eq!(scheduler.len(), 240 - 60);
+        let rest = std::iter::from_fn(|| scheduler.pop()).count();
+        assert_eq!(rest, 180);
+        assert!(scheduler.is_empty());
+    }
+
+    #[test]
+    fn test_partition_by_priority() {
+        let packets = vec![
+            packet(Priority::LOW, 1, 0),
+            packet(Priority::HIGH, 2, 0),
+            packet(Priority::LOW, 3, 0),
+            packet(Priority::CRITICAL, 4, 0),
+            packet(Priority::HIGH, 5, 0),
+        ];
+
+        let lanes = partition_by_priority(packets);
+        let sequences = |priority| -> Vec<Sequence> { lanes[&priority].iter().map(|p| p.sequence).collect() };
+        assert_eq!(lanes.len(), 3);
+        assert_eq!(sequences(Priority::LOW), vec![1, 3]);
+        assert_eq!(sequences(Priority::HIGH), vec![2, 5]);
+        assert_eq!(sequences(Priority::CRITICAL), vec![4]);
+    }
+
+    #[test]
+    fn test_age_stats() {
+        let mut queue = PriorityQueue::new();
+        assert_eq!(queue.age_stats(1_000), None);
+
+        for (seq, timestamp) in [(1, 900), (2, 800), (3, 500), (4, 1_200)] {
+            queue.push(packet(Priority::NORMAL, seq, timestamp));
+        }
+
+        let stats = queue.age_stats(1_000).unwrap();
+        assert_eq!(stats.count, 4);
+        assert_eq!(stats.oldest, 500);
+        assert_eq!(stats.newest, 0); // future-dated one
+        assert_eq!(stats.mean, (100 + 200 + 500) / 4);
+        assert_eq!(stats.median, 200);
+    }
+}
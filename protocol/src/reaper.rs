@@ -0,0 +1,85 @@
+//drops per-session state for clients that went away without a Close
+//whoever keeps per-session state (dedup windows, rate limiters, subscriptions) touches the
+//session on every packet and calls sweep() now and then, sweep hands back the ids it evicted
+//so the other structures can drop their entries too
+
+use std::collections::HashMap;
+
+use crate::packet::*;
+
+#[derive(Debug)]
+pub struct SessionReaper {
+    ttl_ms: u64,
+    last_seen: HashMap<SessionId, u64>,
+}
+
+impl SessionReaper {
+    /// Sessions idle for longer than `ttl_ms` get reaped
+    pub fn new(ttl_ms: u64) -> Self {
+        SessionReaper {
+            ttl_ms,
+            last_seen: HashMap::new(),
+        }
+    }
+
+    /// Record activity on a session (starts tracking it if it's new)
+    pub fn touch(&mut self, session_id: SessionId, now_ms: u64) {
+        self.last_seen.insert(session_id, now_ms);
+    }
+
+    /// Stop tracking a session that closed properly
+    pub fn remove(&mut self, session_id: &SessionId) {
+        self.last_seen.remove(session_id);
+    }
+
+    pub fn len(&self) -> usize {
+        self.last_seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.last_seen.is_empty()
+    }
+
+    /// Evict every session idle for longer than the TTL, returns the evicted ids
+    pub fn sweep(&mut self, now_ms: u64) -> Vec<SessionId> {
+        let ttl_ms = self.ttl_ms;
+        let mut reaped = Vec::new();
+        self.last_seen.retain(|session_id, last_seen| {
+            let idle = now_ms.saturating_sub(*last_seen) > ttl_ms;
+            if idle {
+                reaped.push(*session_id);
+            }
+            !idle
+        });
+        reaped
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sweep_reaps_idle_sessions() {
+        let idle = SessionId::new();
+        let active = SessionId::new();
+
+        let mut reaper = SessionReaper::new(1_000);
+        reaper.touch(idle, 0);
+        reaper.touch(active, 0);
+
+        // nothing is past the TTL yet
+        assert!(reaper.sweep(1_000).is_empty());
+
+        reaper.touch(active, 1_500);
+        assert_eq!(reaper.sweep(2_001), vec![idle]);
+        assert_eq!(reaper.len(), 1);
+
+        reaper.remove(&active);
+        assert!(reaper.is_empty());
+    }
+}
@@ -0,0 +1,189 @@
+//receiver side ordering: packets come out in sequence order whatever order they arrive in,
+//duplicates are dropped
+//
+//sequences wrap at u32::MAX, so everything here goes by the distance from the next expected
+//sequence (wrapping_sub) and seq_cmp, never by plain `<`. A transfer running through the wrap
+//is ordered and deduplicated like any other
+//
+//packets further ahead than the window are dropped, the sender resends them once they're in
+//reach. Without that a peer could pin memory by jumping the sequence ahead
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+
+use crate::packet::*;
+
+/// How far past the next expected sequence packets are buffered, by default
+pub const DEFAULT_REORDER_WINDOW: u32 = 4096;
+
+// a window past half the sequence space would make "ahead" and "behind" ambiguous
+const HALF_WINDOW: u32 = 1 << 31;
+
+#[derive(Debug)]
+pub struct ReorderBuffer {
+    first: Sequence,
+    next: Sequence,
+    // arrived but waiting for an earlier sequence
+    buffered: HashMap<Sequence, Packet>,
+    window: u32,
+}
+
+impl ReorderBuffer {
+    /// `first` is the sequence the peer starts at
+    pub fn new(first: Sequence) -> Self {
+        ReorderBuffer {
+            first,
+            next: first,
+            buffered: HashMap::new(),
+            window: DEFAULT_REORDER_WINDOW,
+        }
+    }
+
+    /// Buffer at most `window` sequences ahead (at least one)
+    pub fn with_window(mut self, window: u32) -> Self {
+        self.window = window.clamp(1, HALF_WINDOW);
+        self
+    }
+
+    /// The sequence the next in-order packet has to have
+    pub fn next_expected(&self) -> Sequence {
+        self.next
+    }
+
+    /// Packets waiting on an earlier sequence
+    pub fn buffered(&self) -> usize {
+        self.buffered.len()
+    }
+
+    /// Feed a packet in, get back every packet that is now in order (possibly none)
+    /// Duplicates and packets past the window are dropped
+    pub fn push(&mut self, packet: Packet) -> Vec<Packet> {
+        let ahead = packet.sequence.wrapping_sub(self.next);
+        // handed on already, or too far ahead to keep
+        if seq_cmp(packet.sequence, self.next) == Ordering::Less || ahead >= self.window {
+            return Vec::new();
+        }
+        self.buffered.entry(packet.sequence).or_insert(packet);
+
+        let mut ready = Vec::new();
+        while let Some(packet) = self.buffered.remove(&self.next) {
+            self.next = self.next.wrapping_add(1);
+            ready.push(packet);
+        }
+        ready
+    }
+
+    /// What an Ack from here says: everything handed on, plus what's buffered past a gap
+    pub fn ack_ranges(&self) -> Vec<RangeInclusive<Sequence>> {
+        let mut ranges = Vec::new();
+        if self.next != self.first {
+            let last = self.next.wrapping_sub(1);
+            if self.first <= last {
+                ranges.push(self.first..=last);
+            } else {
+                ranges.push(self.first..=Sequence::MAX);
+                ranges.push(0..=last);
+            }
+        }
+
+        let mut buffered: Vec<Sequence> = self.buffered.keys().copied().collect();
+        buffered.sort_by_key(|sequence| sequence.wrapping_sub(self.next));
+        for sequence in buffered {
+            extend_ranges(&mut ranges, sequence);
+        }
+        ranges
+    }
+
+    /// What a Nack from here asks for: the gaps from the next expected sequence up to
+    /// `last` (inclusive), within the window
+    pub fn missing_ranges(&self, last: Sequence) -> Vec<RangeInclusive<Sequence>> {
+        let mut ranges = Vec::new();
+        if seq_cmp(last, self.next) == Ordering::Less {
+            return ranges;
+        }
+        let span = last.wrapping_sub(self.next).min(self.window - 1);
+        for ahead in 0..=span {
+            let sequence = self.next.wrapping_add(ahead);
+            if !self.buffered.contains_key(&sequence) {
+                extend_ranges(&mut ranges, sequence);
+            }
+        }
+        ranges
+    }
+}
+
+// grow the last range when `sequence` follows it, start a new one otherwise (also at the wrap,
+// a range can't run from u32::MAX to 0)
+fn extend_ranges(ranges: &mut Vec<RangeInclusive<Sequence>>, sequence: Sequence) {
+    match ranges.last_mut() {
+        Some(last) if last.end().checked_add(1) == Some(sequence) => *last = *last.start()..=sequence,
+        _ => ranges.push(sequence..=sequence),
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(sequence: Sequence) -> Packet {
+        let mut packet = Packet::new(SessionId::new(), Intent::DataPush, vec![]);
+        packet.sequence = sequence;
+        packet.reseal();
+        packet
+    }
+
+    fn sequences(packets: Vec<Packet>) -> Vec<Sequence> {
+        packets.iter().map(|packet| packet.sequence).collect()
+    }
+
+    #[test]
+    fn test_reorders_and_dedups() {
+        let mut buffer = ReorderBuffer::new(10);
+        assert!(buffer.push(packet(12)).is_empty());
+        assert!(buffer.push(packet(11)).is_empty());
+        assert!(buffer.push(packet(12)).is_empty());
+        assert_eq!(buffer.buffered(), 2);
+
+        assert_eq!(sequences(buffer.push(packet(10))), vec![10, 11, 12]);
+        assert_eq!(buffer.next_expected(), 13);
+
+        // already handed on
+        assert!(buffer.push(packet(11)).is_empty());
+        assert_eq!(buffer.buffered(), 0);
+    }
+
+    #[test]
+    fn test_across_the_wrap() {
+        let first = Sequence::MAX - 1;
+        let mut buffer = ReorderBuffer::new(first);
+        assert!(buffer.push(packet(1)).is_empty());
+        assert!(buffer.push(packet(Sequence::MAX)).is_empty());
+        assert_eq!(buffer.missing_ranges(2), vec![first..=first, 0..=0, 2..=2]);
+
+        assert_eq!(sequences(buffer.push(packet(first))), vec![first, Sequence::MAX]);
+        assert_eq!(buffer.ack_ranges(), vec![first..=Sequence::MAX, 1..=1]);
+
+        assert_eq!(sequences(buffer.push(packet(0))), vec![0, 1]);
+        assert_eq!(buffer.next_expected(), 2);
+        assert_eq!(buffer.ack_ranges(), vec![first..=Sequence::MAX, 0..=1]);
+
+        // from before the wrap, a duplicate
+        assert!(buffer.push(packet(Sequence::MAX)).is_empty());
+        assert_eq!(buffer.buffered(), 0);
+    }
+
+    #[test]
+    fn test_window_drops_far_ahead() {
+        let mut buffer = ReorderBuffer::new(0).with_window(8);
+        assert!(buffer.push(packet(8)).is_empty());
+        assert!(buffer.push(packet(7)).is_empty());
+        assert_eq!(buffer.buffered(), 1);
+        // nacks stop at the window too
+        assert_eq!(buffer.missing_ranges(100), vec![0..=6]);
+    }
+}
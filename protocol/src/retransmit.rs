@@ -0,0 +1,132 @@
+//sender side bookkeeping for packets that may need to go out again
+//every sent packet is kept until the receiver acks it, a Nack pulls exactly the
+//sequences it names back into the send queue instead of resending the whole window
+//resent copies carry the retransmission marker so the receiver can tell them apart
+
+use std::collections::BTreeMap;
+
+use crate::packet::*;
+use crate::queue::PriorityQueue;
+
+#[derive(Debug, Default)]
+pub struct RetransmitTracker {
+    // sent but not acked yet, by sequence
+    in_flight: BTreeMap<Sequence, Packet>,
+}
+
+impl RetransmitTracker {
+    pub fn new() -> Self {
+        RetransmitTracker::default()
+    }
+
+    /// Remember a packet that just went out
+    pub fn record(&mut self, packet: Packet) {
+        self.in_flight.insert(packet.sequence, packet);
+    }
+
+    /// Receiver has everything up to and including `sequence`, forget it
+    pub fn ack_through(&mut self, sequence: Sequence) {
+        self.in_flight.retain(|&sent, _| seq_cmp(sent, sequence).is_gt());
+    }
+
+    pub fn len(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.in_flight.is_empty()
+    }
+
+    /// Forget everything an Ack covers
+    pub fn handle_ack(&mut self, ack: &Packet) -> Result<(), PacketError> {
+        for range in ack.ack_ranges()? {
+            // walk what we hold, not the range, a peer can ack 0..=u32::MAX
+            let acked: Vec<Sequence> = self.in_flight.range(range).map(|(&sequence, _)| sequence).collect();
+            for sequence in acked {
+                self.in_flight.remove(&sequence);
+            }
+        }
+        Ok(())
+    }
+
+    /// Put the packets a Nack asks for back on the send queue, marked as retransmissions
+    /// Sequences we no longer have (already acked, never sent) are skipped, so are packets
+    /// below the queue's min_priority. Returns how many packets were re-queued
+    pub fn handle_nack(&self, nack: &Packet, queue: &mut PriorityQueue) -> Result<usize, PacketError> {
+        let mut requeued = 0;
+        for range in nack.nack_ranges()? {
+            for (_, packet) in self.in_flight.range(range) {
+                let mut resend = packet.clone();
+                resend.mark_retransmission();
+                if queue.push(resend).is_none() {
+                    requeued += 1;
+                }
+            }
+        }
+        Ok(requeued)
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sent(tracker: &mut RetransmitTracker, session_id: SessionId, sequences: std::ops::Range<Sequence>) {
+        for sequence in sequences {
+            let mut packet = Packet::new(session_id, Intent::DataPush, vec![sequence as u8]);
+            packet.sequence = sequence;
+            packet.reseal();
+            tracker.record(packet);
+        }
+    }
+
+    #[test]
+    fn test_nack_requeues_exactly_the_missing_packets() {
+        let session_id = SessionId::new();
+        let mut tracker = RetransmitTracker::new();
+        sent(&mut tracker, session_id, 1..13);
+
+        let mut queue = PriorityQueue::new();
+        let nack = Packet::nack(session_id, &[5..=6, 10..=10]);
+        assert_eq!(tracker.handle_nack(&nack, &mut queue).unwrap(), 3);
+
+        let resent: Vec<Packet> = std::iter::from_fn(|| queue.pop()).collect();
+        let mut sequences: Vec<Sequence> = resent.iter().map(|p| p.sequence).collect();
+        sequences.sort_unstable();
+        assert_eq!(sequences, vec![5, 6, 10]);
+
+        // marked, and the marker survives the wire with the hash intact
+        for packet in resent {
+            let recovered = Packet::from_bytes(&packet.to_bytes()).unwrap();
+            assert!(recovered.is_retransmission());
+        }
+        assert!(!tracker.in_flight[&5].is_retransmission());
+    }
+
+    #[test]
+    fn test_acked_packets_are_not_resent() {
+        let session_id = SessionId::new();
+        let mut tracker = RetransmitTracker::new();
+        sent(&mut tracker, session_id, 1..9);
+        tracker.ack_through(5);
+        assert_eq!(tracker.len(), 3);
+
+        let mut queue = PriorityQueue::new();
+        let nack = Packet::nack(session_id, &[2..=2, 4..=7]);
+        assert_eq!(tracker.handle_nack(&nack, &mut queue).unwrap(), 2); // only 6 and 7 left
+    }
+
+    #[test]
+    fn test_batched_ack_clears_tracker() {
+        let session_id = SessionId::new();
+        let mut tracker = RetransmitTracker::new();
+        sent(&mut tracker, session_id, 1..11);
+
+        tracker.handle_ack(&Packet::ack(session_id, &[1..=3, 6..=10])).unwrap();
+        assert_eq!(tracker.len(), 2); // 4 and 5 still out
+    }
+}
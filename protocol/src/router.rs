@@ -0,0 +1,102 @@
+//dispatch for servers: one handler per intent instead of a big match on packet.intent
+//a handler gets the request and returns the reply, or None when there's nothing to send back
+//(a DataPush that was just stored, say). Intents nobody registered get an Error reply, so the
+//client hears about it instead of waiting for a response that never comes. Errors and
+//responses without a handler are dropped instead: nobody waits on them, and answering them
+//with an Error would have two routers bouncing Errors off each other forever
+
+use std::collections::HashMap;
+
+use crate::packet::*;
+
+/// Error code in the reply to an intent without a handler
+pub const UNHANDLED_INTENT: u16 = 501;
+
+pub type Handler = Box<dyn Fn(&Packet) -> Option<Packet> + Send + Sync>;
+
+#[derive(Default)]
+pub struct IntentRouter {
+    handlers: HashMap<u8, Handler>, // by the intent byte
+}
+
+impl IntentRouter {
+    pub fn new() -> Self {
+        IntentRouter::default()
+    }
+
+    /// Handle `intent` with `handler`, replacing the one registered before
+    pub fn register<F>(&mut self, intent: Intent, handler: F)
+    where
+        F: Fn(&Packet) -> Option<Packet> + Send + Sync + 'static,
+    {
+        self.handlers.insert(intent.to_u8(), Box::new(handler));
+    }
+
+    pub fn is_registered(&self, intent: Intent) -> bool {
+        self.handlers.contains_key(&intent.to_u8())
+    }
+
+    /// Run the packet's handler, an Error reply if its intent has none
+    /// (unless it's an Error or a response itself, those get nothing)
+    pub fn dispatch(&self, packet: &Packet) -> Option<Packet> {
+        match self.handlers.get(&packet.intent.to_u8()) {
+            Some(handler) => handler(packet),
+            None if packet.intent == Intent::Error || packet.intent.is_response() => None,
+            None => Some(Packet::error_for(
+                packet,
+                UNHANDLED_INTENT,
+                &format!("no handler for {:?}", packet.intent),
+            )),
+        }
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dispatch_by_intent() {
+        let mut router = IntentRouter::new();
+        router.register(Intent::Ping, |ping| {
+            Some(Packet::new(ping.session_id, Intent::Pong, ping.payload.clone()))
+        });
+        router.register(Intent::DataPush, |_| None);
+
+        let session_id = SessionId::new();
+        let pong = router
+            .dispatch(&Packet::new(session_id, Intent::Ping, b"hi".to_vec()))
+            .unwrap();
+        assert_eq!(pong.intent, Intent::Pong);
+        assert_eq!(pong.session_id, session_id);
+        assert_eq!(pong.payload, b"hi");
+
+        assert!(router.dispatch(&Packet::new(session_id, Intent::DataPush, vec![1])).is_none());
+
+        let mut search = Packet::new(session_id, Intent::Search, b"rust".to_vec());
+        search.set_sequence(12);
+        let error = router.dispatch(&search).unwrap();
+        let info = error.error_info().unwrap();
+        assert_eq!(info.code, UNHANDLED_INTENT);
+        assert_eq!(info.request_sequence, 12);
+    }
+
+    #[test]
+    fn test_unhandled_errors_and_responses_get_no_reply() {
+        let router = IntentRouter::new();
+        let session_id = SessionId::new();
+
+        // a peer's router would answer an Error reply with another one, and so on
+        let search = Packet::new(session_id, Intent::Search, b"rust".to_vec());
+        let error = router.dispatch(&search).unwrap();
+        assert!(router.dispatch(&error).is_none());
+
+        for intent in [Intent::Pong, Intent::Ack, Intent::Success] {
+            assert!(router.dispatch(&Packet::new(session_id, intent, vec![])).is_none());
+        }
+    }
+}
@@ -0,0 +1,100 @@
+//what a session agreed on during the handshake, checked against every packet arriving on it
+//
+//a packet on the session's channel has to carry the session's id (a misrouted or spliced
+//packet doesn't), the version both ends settled on, and encryption if the session asked for
+//it (a peer or someone in the middle quietly falling back to plaintext is a downgrade)
+//ChaCha20 and AES-256 are both 256 bit, either one meets an encrypted session's minimum
+
+use crate::packet::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Session {
+    id: SessionId,
+    version: u8,
+    min_encryption: EncryptionLevel,
+}
+
+impl Session {
+    /// `version` is the negotiated one, `min_encryption` None accepts plaintext
+    pub fn new(id: SessionId, version: u8, min_encryption: EncryptionLevel) -> Self {
+        Session {
+            id,
+            version,
+            min_encryption,
+        }
+    }
+
+    pub fn id(&self) -> SessionId {
+        self.id
+    }
+
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    pub fn min_encryption(&self) -> EncryptionLevel {
+        self.min_encryption
+    }
+
+    /// Does this packet belong on this session, as agreed?
+    pub fn validate_incoming(&self, packet: &Packet) -> Result<(), PacketError> {
+        if packet.session_id != self.id {
+            return Err(PacketError::SessionMismatch);
+        }
+        if packet.version != self.version {
+            return Err(PacketError::VersionMismatch {
+                negotiated: self.version,
+                got: packet.version,
+            });
+        }
+        if self.min_encryption != EncryptionLevel::None && packet.flags.encryption() == EncryptionLevel::None {
+            return Err(PacketError::EncryptionRequired);
+        }
+        Ok(())
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encrypted(session_id: SessionId) -> Packet {
+        let mut packet = Packet::new(session_id, Intent::Search, b"q".to_vec());
+        packet.flags.set_encryption(EncryptionLevel::ChaCha20);
+        packet.reseal();
+        packet
+    }
+
+    #[test]
+    fn test_validate_incoming() {
+        let session = Session::new(SessionId::new(), FDP_VERSION, EncryptionLevel::ChaCha20);
+        assert!(session.validate_incoming(&encrypted(session.id())).is_ok());
+
+        // either cipher will do
+        let mut aes = encrypted(session.id());
+        aes.flags.set_encryption(EncryptionLevel::Aes256);
+        assert!(session.validate_incoming(&aes).is_ok());
+
+        let elsewhere = encrypted(SessionId::new());
+        assert!(matches!(session.validate_incoming(&elsewhere), Err(PacketError::SessionMismatch)));
+
+        let mut downgraded = encrypted(session.id());
+        downgraded.flags.set_encryption(EncryptionLevel::None);
+        assert!(matches!(session.validate_incoming(&downgraded), Err(PacketError::EncryptionRequired)));
+
+        let mut newer = encrypted(session.id());
+        newer.version = FDP_VERSION + 1;
+        assert!(matches!(
+            session.validate_incoming(&newer),
+            Err(PacketError::VersionMismatch { negotiated: FDP_VERSION, got }) if got == FDP_VERSION + 1
+        ));
+
+        // a plaintext session takes plaintext
+        let open = Session::new(session.id(), FDP_VERSION, EncryptionLevel::None);
+        assert!(open.validate_incoming(&downgraded).is_ok());
+    }
+}
@@ -0,0 +1,247 @@
+//transports move whole serialized packets between two ends
+//the protocol code above talks to a `dyn Transport`, so the same session logic runs over
+//UDP, TCP or an in-memory pair in tests
+//
+//UDP: one datagram per packet, the socket is connected to the peer
+//TCP: a stream has no packet boundaries, so each packet gets a u32 big-endian length in front
+//memory: two channels, one per direction, see MemoryTransport::pair
+//
+//the trait is async only, every carrier here is a tokio socket or channel. A blocking caller
+//drives it with its runtime's block_on rather than going through a second sync trait
+//connection::Connection is the consumer, it holds a Box<dyn Transport>
+
+use std::future::Future;
+use std::io::ErrorKind;
+use std::pin::Pin;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::mpsc;
+
+use crate::packet::*;
+
+pub type TransportFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, PacketError>> + Send + 'a>>;
+
+// biggest datagram UDP can carry
+const MAX_DATAGRAM: usize = 65_535;
+
+// biggest frame TCP will accept: fixed header, largest extended header, payload and hash
+const MAX_FRAME: usize = HEADER_SIZE + EXT_LENGTH_SIZE + u16::MAX as usize + MAX_PAYLOAD_SIZE + HASH_SIZE;
+
+/// Something that can carry serialized packets to a peer and back
+/// Boxed futures instead of async fns so it works behind `dyn Transport`
+pub trait Transport: Send {
+    /// Send one serialized packet
+    fn send<'a>(&'a mut self, frame: &'a [u8]) -> TransportFuture<'a, ()>;
+
+    /// Wait for the next serialized packet
+    fn recv(&mut self) -> TransportFuture<'_, Vec<u8>>;
+
+    /// Serialize and send a packet
+    fn send_packet<'a>(&'a mut self, packet: &'a Packet) -> TransportFuture<'a, ()> {
+        Box::pin(async move { self.send(&packet.to_bytes()).await })
+    }
+
+    /// Receive and fully check a packet
+    fn recv_packet(&mut self) -> TransportFuture<'_, Packet> {
+        Box::pin(async move { Packet::from_bytes(&self.recv().await?) })
+    }
+}
+
+fn io_error(error: std::io::Error) -> PacketError {
+    PacketError::Io(error.kind())
+}
+
+// ============================================================================
+// UDP
+// ============================================================================
+
+#[derive(Debug)]
+pub struct UdpTransport {
+    socket: UdpSocket,
+}
+
+impl UdpTransport {
+    /// The socket has to be connected to the peer already
+    pub fn new(socket: UdpSocket) -> Self {
+        UdpTransport { socket }
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send<'a>(&'a mut self, frame: &'a [u8]) -> TransportFuture<'a, ()> {
+        Box::pin(async move {
+            if frame.len() > MAX_DATAGRAM {
+                return Err(PacketError::TooLarge);
+            }
+            self.socket.send(frame).await.map_err(io_error)?;
+            Ok(())
+        })
+    }
+
+    fn recv(&mut self) -> TransportFuture<'_, Vec<u8>> {
+        Box::pin(async move {
+            let mut buffer = vec![0u8; MAX_DATAGRAM];
+            let len = self.socket.recv(&mut buffer).await.map_err(io_error)?;
+            buffer.truncate(len);
+            Ok(buffer)
+        })
+    }
+}
+
+// ============================================================================
+// TCP
+// ============================================================================
+
+#[derive(Debug)]
+pub struct TcpTransport {
+    stream: TcpStream,
+}
+
+impl TcpTransport {
+    pub fn new(stream: TcpStream) -> Self {
+        TcpTransport { stream }
+    }
+}
+
+impl Transport for TcpTransport {
+    fn send<'a>(&'a mut self, frame: &'a [u8]) -> TransportFuture<'a, ()> {
+        Box::pin(async move {
+            if frame.len() > MAX_FRAME {
+                return Err(PacketError::TooLarge);
+            }
+            self.stream
+                .write_all(&(frame.len() as u32).to_be_bytes())
+                .await
+                .map_err(io_error)?;
+            self.stream.write_all(frame).await.map_err(io_error)?;
+            Ok(())
+        })
+    }
+
+    fn recv(&mut self) -> TransportFuture<'_, Vec<u8>> {
+        Box::pin(async move {
+            let mut len = [0u8; 4];
+            self.stream.read_exact(&mut len).await.map_err(io_error)?;
+            let len = u32::from_be_bytes(len) as usize;
+            // check before allocating, the length came off the wire
+            if len > MAX_FRAME {
+                return Err(PacketError::TooLarge);
+            }
+
+            let mut frame = vec![0u8; len];
+            self.stream.read_exact(&mut frame).await.map_err(io_error)?;
+            Ok(frame)
+        })
+    }
+}
+
+// ============================================================================
+// IN-MEMORY
+// ============================================================================
+
+#[derive(Debug)]
+pub struct MemoryTransport {
+    outgoing: mpsc::UnboundedSender<Vec<u8>>,
+    incoming: mpsc::UnboundedReceiver<Vec<u8>>,
+}
+
+impl MemoryTransport {
+    /// Two connected ends, what one sends the other receives
+    pub fn pair() -> (Self, Self) {
+        let (a_tx, a_rx) = mpsc::unbounded_channel();
+        let (b_tx, b_rx) = mpsc::unbounded_channel();
+        (
+            MemoryTransport {
+                outgoing: a_tx,
+                incoming: b_rx,
+            },
+            MemoryTransport {
+                outgoing: b_tx,
+                incoming: a_rx,
+            },
+        )
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn send<'a>(&'a mut self, frame: &'a [u8]) -> TransportFuture<'a, ()> {
+        Box::pin(async move {
+            self.outgoing
+                .send(frame.to_vec())
+                .map_err(|_| PacketError::Io(ErrorKind::BrokenPipe))
+        })
+    }
+
+    fn recv(&mut self) -> TransportFuture<'_, Vec<u8>> {
+        Box::pin(async move {
+            self.incoming
+                .recv()
+                .await
+                .ok_or(PacketError::Io(ErrorKind::UnexpectedEof))
+        })
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // client side of a handshake, only knows it has some transport
+    async fn handshake(transport: &mut dyn Transport, session_id: SessionId) -> Result<Packet, PacketError> {
+        let init = Packet::new(session_id, Intent::HandshakeInit, b"hello".to_vec());
+        transport.send_packet(&init).await?;
+        transport.recv_packet().await
+    }
+
+    #[tokio::test]
+    async fn test_handshake_over_memory() {
+        let (mut client, mut server) = MemoryTransport::pair();
+        let session_id = SessionId::new();
+
+        let server_task = tokio::spawn(async move {
+            let init = server.recv_packet().await.unwrap();
+            assert_eq!(init.intent, Intent::HandshakeInit);
+            let ack = Packet::new(init.session_id, Intent::HandshakeAck, b"welcome".to_vec());
+            server.send_packet(&ack).await.unwrap();
+        });
+
+        let ack = handshake(&mut client, session_id).await.unwrap();
+        assert_eq!(ack.intent, Intent::HandshakeAck);
+        assert_eq!(ack.session_id, session_id);
+        assert_eq!(ack.payload, b"welcome");
+        server_task.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_tcp_framing() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut client = TcpTransport::new(TcpStream::connect(addr).await.unwrap());
+        let mut server = TcpTransport::new(listener.accept().await.unwrap().0);
+
+        // back to back on the stream, framing has to keep them apart
+        let first = Packet::new(SessionId::new(), Intent::DataPush, vec![1; 3000]);
+        let second = Packet::new(SessionId::new(), Intent::Ping, vec![]);
+        client.send_packet(&first).await.unwrap();
+        client.send_packet(&second).await.unwrap();
+
+        assert_eq!(server.recv_packet().await.unwrap().payload, first.payload);
+        assert_eq!(server.recv_packet().await.unwrap().intent, Intent::Ping);
+    }
+
+    #[tokio::test]
+    async fn test_closed_memory_transport() {
+        let (mut client, server) = MemoryTransport::pair();
+        drop(server);
+        assert!(matches!(
+            client.recv().await,
+            Err(PacketError::Io(ErrorKind::UnexpectedEof))
+        ));
+    }
+}
@@ -0,0 +1,205 @@
+//the reliability pieces (retransmit tracker, acks/nacks, reassembly) driven together over a
+//link that loses and reorders packets
+//
+//LossyChannel stands in for the network, seeded so a failure reproduces. Every packet goes
+//through its wire bytes on the way, so the extended header entries get exercised as well
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use fdp::fragment::Reassembler;
+use fdp::packet::*;
+use fdp::queue::PriorityQueue;
+use fdp::reorder::ReorderBuffer;
+use fdp::retransmit::RetransmitTracker;
+
+/// Drops each packet with probability `loss_rate`, and moves each survivor further back in
+/// the batch with probability `reorder_probability`
+struct LossyChannel {
+    rng: StdRng,
+    loss_rate: f64,
+    reorder_probability: f64,
+}
+
+impl LossyChannel {
+    fn new(seed: u64, loss_rate: f64, reorder_probability: f64) -> Self {
+        LossyChannel {
+            rng: StdRng::seed_from_u64(seed),
+            loss_rate,
+            reorder_probability,
+        }
+    }
+
+    fn transmit(&mut self, packets: Vec<Packet>) -> Vec<Packet> {
+        let mut delivered: Vec<Packet> = packets
+            .into_iter()
+            .filter(|_| !self.rng.gen_bool(self.loss_rate))
+            .map(|packet| Packet::from_bytes(&packet.to_bytes()).unwrap())
+            .collect();
+
+        for index in 0..delivered.len() {
+            if self.rng.gen_bool(self.reorder_probability) {
+                let later = self.rng.gen_range(index..delivered.len());
+                delivered.swap(index, later);
+            }
+        }
+        delivered
+    }
+}
+
+/// Receiver side: the crate's ReorderBuffer puts packets back in order, the fragments go on
+/// to the reassembler from there
+struct Receiver {
+    reorder: ReorderBuffer,
+    // last sequence of the message, known once any fragment arrived
+    last: Option<Sequence>,
+    reassembler: Reassembler,
+    message: Option<Vec<u8>>,
+}
+
+impl Receiver {
+    fn new(first: Sequence) -> Self {
+        Receiver {
+            reorder: ReorderBuffer::new(first),
+            last: None,
+            reassembler: Reassembler::new(),
+            message: None,
+        }
+    }
+
+    fn receive(&mut self, packet: Packet) {
+        if let Some(info) = packet.ext.fragment {
+            let first = packet.sequence.wrapping_sub(info.index as Sequence);
+            self.last = Some(first.wrapping_add(info.count as Sequence - 1));
+        }
+        for packet in self.reorder.push(packet) {
+            if let Some(message) = self.reassembler.push(packet).unwrap() {
+                self.message = Some(message);
+            }
+        }
+    }
+
+    /// What went out in answer: an Ack for what's here, a Nack for the gaps
+    fn feedback(&self, session_id: SessionId) -> Vec<Packet> {
+        let mut feedback = Vec::new();
+        let acked = self.reorder.ack_ranges();
+        // nothing to ack before the first packet arrives
+        if !acked.is_empty() {
+            feedback.push(Packet::ack(session_id, &acked));
+        }
+        if let Some(last) = self.last {
+            let missing = self.reorder.missing_ranges(last);
+            if !missing.is_empty() {
+                feedback.push(Packet::nack(session_id, &missing));
+            }
+        }
+        feedback
+    }
+}
+
+fn deliver_over(first: Sequence, mut forward: LossyChannel, mut backward: LossyChannel) {
+    let session_id = SessionId::new();
+    let original: Vec<u8> = (0..40_000u32).map(|i| (i * 31 % 251) as u8).collect();
+    let message = Packet::new(session_id, Intent::DataPush, original.clone());
+
+    // one sequence per fragment, that's what acks and nacks talk about
+    let mut fragments = message.fragment(1_000).unwrap();
+    for (offset, fragment) in fragments.iter_mut().enumerate() {
+        fragment.sequence = first.wrapping_add(offset as Sequence);
+        fragment.reseal();
+    }
+    // everything sent, split in two if it runs through the wrap
+    let last = first.wrapping_add(fragments.len() as Sequence - 1);
+    let everything = if first <= last {
+        vec![first..=last]
+    } else {
+        vec![first..=Sequence::MAX, 0..=last]
+    };
+
+    let mut tracker = RetransmitTracker::new();
+    let mut queue = PriorityQueue::new();
+    for fragment in fragments {
+        queue.push(fragment);
+    }
+    let mut receiver = Receiver::new(first);
+
+    let mut rounds = 0;
+    while receiver.message.is_none() {
+        rounds += 1;
+        assert!(rounds < 200, "no delivery after {} rounds", rounds);
+
+        let mut sending = Vec::new();
+        while let Some(packet) = queue.pop() {
+            tracker.record(packet.clone());
+            sending.push(packet);
+        }
+        for packet in forward.transmit(sending) {
+            receiver.receive(packet);
+        }
+
+        let mut heard_back = false;
+        for packet in backward.transmit(receiver.feedback(session_id)) {
+            heard_back = true;
+            match packet.intent {
+                Intent::Ack => tracker.handle_ack(&packet).unwrap(),
+                Intent::Nack => {
+                    tracker.handle_nack(&packet, &mut queue).unwrap();
+                }
+                other => panic!("unexpected {:?} from the receiver", other),
+            }
+        }
+        // nothing came back: time out and resend whatever is still unacked
+        if !heard_back || (queue.is_empty() && !tracker.is_empty()) {
+            tracker
+                .handle_nack(&Packet::nack(session_id, &everything), &mut queue)
+                .unwrap();
+        }
+    }
+
+    assert_eq!(receiver.message.unwrap(), original);
+    assert_eq!(receiver.reorder.buffered(), 0);
+}
+
+#[test]
+fn test_reliable_delivery_despite_loss_and_reordering() {
+    for seed in 0..5 {
+        deliver_over(
+            1,
+            LossyChannel::new(seed, 0.3, 0.3),
+            LossyChannel::new(seed + 100, 0.3, 0.3),
+        );
+    }
+}
+
+#[test]
+fn test_reliable_delivery_across_the_sequence_wrap() {
+    // 40 fragments starting 20 before u32::MAX
+    for seed in 0..3 {
+        deliver_over(
+            Sequence::MAX - 19,
+            LossyChannel::new(seed, 0.3, 0.3),
+            LossyChannel::new(seed + 100, 0.3, 0.3),
+        );
+    }
+}
+
+#[test]
+fn test_lossy_channel_loses_and_reorders() {
+    let session_id = SessionId::new();
+    let packets: Vec<Packet> = (0..1_000)
+        .map(|sequence| {
+            let mut packet = Packet::new(session_id, Intent::DataPush, vec![]);
+            packet.sequence = sequence;
+            packet.reseal();
+            packet
+        })
+        .collect();
+
+    let delivered = LossyChannel::new(7, 0.2, 0.5).transmit(packets);
+    let sequences: Vec<Sequence> = delivered.iter().map(|packet| packet.sequence).collect();
+    assert!((700..900).contains(&sequences.len()));
+    assert!(sequences.windows(2).any(|pair| pair[0] > pair[1]));
+
+    let clean = LossyChannel::new(7, 0.0, 0.0).transmit(delivered.clone());
+    assert_eq!(clean.len(), delivered.len());
+}